@@ -119,6 +119,47 @@ impl CameraController {
         }
     }
 
+    // Discrete lens adjustments, brackets change the aperture, B cycles the bokeh shape
+    // and M the shutter angle
+    pub fn process_camera_key(&mut self, key_code: KeyCode, state: ElementState, camera: &mut Camera) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+
+        match key_code {
+            KeyCode::BracketRight => {
+                camera.set_aperture_radius((camera.aperture_radius() * 1.5).max(0.005));
+                true
+            }
+            KeyCode::BracketLeft => {
+                let radius = camera.aperture_radius() / 1.5;
+                camera.set_aperture_radius(if radius < 0.005 { 0.0 } else { radius });
+                true
+            }
+            KeyCode::KeyB => {
+                let blades = match camera.bokeh_blades() {
+                    0 => 5,
+                    5 => 6,
+                    6 => 8,
+                    _ => 0,
+                };
+                camera.set_bokeh(blades, 0.0);
+                true
+            }
+            KeyCode::KeyM => {
+                let close = match camera.shutter().1 {
+                    0.0 => 0.25,
+                    0.25 => 0.5,
+                    0.5 => 1.0,
+                    _ => 0.0,
+                };
+                camera.set_shutter(0.0, close);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn process_scroll(&mut self, delta_y: f32, camera: &mut Camera) {
         let fov_change = delta_y * 0.1;
         let new_fov = (camera.fov - fov_change).clamp(10.0_f32.to_radians(), 120.0_f32.to_radians());
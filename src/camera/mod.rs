@@ -4,7 +4,7 @@ use bytemuck::{Pod, Zeroable};
 pub use controller::CameraController;
 
 use dolly::prelude::*;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 
 // Uniform buffer structure matching GLSL layout
 #[repr(C)]
@@ -12,6 +12,15 @@ use glam::{Mat4, Vec3};
 pub struct CameraUniform {
     pub inv_view: [[f32; 4]; 4],
     pub inv_proj: [[f32; 4]; 4],
+    // Aperture radius, focus distance, bokeh blade count and blade rotation
+    pub lens: [f32; 4],
+    // Camera of the previous frame, rays interpolate towards inv_view over the shutter
+    pub previous_inv_view: [[f32; 4]; 4],
+    // Shutter open and close time as fractions of the frame interval
+    pub shutter: [f32; 4],
+    // World to clip space of this and the previous frame, for motion vectors
+    pub view_proj: [[f32; 4]; 4],
+    pub previous_view_proj: [[f32; 4]; 4],
 }
 
 pub struct Camera {
@@ -21,6 +30,15 @@ pub struct Camera {
     fov: f32,
     near: f32,
     far: f32,
+    // Thin lens, a radius of zero is a pinhole camera
+    aperture_radius: f32,
+    focus_distance: f32,
+    // Polygonal aperture when at least 3, circular otherwise
+    bokeh_blades: u32,
+    bokeh_rotation: f32,
+    previous_inv_view: Mat4,
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
@@ -37,14 +55,23 @@ impl Camera {
 
         let projection = Mat4::perspective_rh(fov, aspect_ratio, near, far);
 
-        Self {
+        let mut camera = Self {
             rig,
             projection,
             aspect_ratio,
             fov,
             near,
             far,
-        }
+            aperture_radius: 0.0,
+            focus_distance: 5.0,
+            bokeh_blades: 0,
+            bokeh_rotation: 0.0,
+            previous_inv_view: Mat4::IDENTITY,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        camera.previous_inv_view = camera.inverse_view_matrix();
+        camera
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -59,7 +86,39 @@ impl Camera {
         self.projection = Mat4::perspective_rh(self.fov, self.aspect_ratio, self.near, self.far);
     }
 
+    pub fn set_aperture_radius(&mut self, radius: f32) {
+        self.aperture_radius = radius.max(0.0);
+    }
+
+    pub fn aperture_radius(&self) -> f32 {
+        self.aperture_radius
+    }
+
+    pub fn set_focus_distance(&mut self, distance: f32) {
+        self.focus_distance = distance.max(self.near);
+    }
+
+    pub fn set_bokeh(&mut self, blades: u32, rotation: f32) {
+        self.bokeh_blades = blades;
+        self.bokeh_rotation = rotation;
+    }
+
+    pub fn bokeh_blades(&self) -> u32 {
+        self.bokeh_blades
+    }
+
+    // Zero length shutters disable motion blur
+    pub fn set_shutter(&mut self, open: f32, close: f32) {
+        self.shutter_open = open.clamp(0.0, 1.0);
+        self.shutter_close = close.clamp(self.shutter_open, 1.0);
+    }
+
+    pub fn shutter(&self) -> (f32, f32) {
+        (self.shutter_open, self.shutter_close)
+    }
+
     pub fn update(&mut self, delta_time: f32) {
+        self.previous_inv_view = self.inverse_view_matrix();
         self.rig.update(delta_time);
     }
 
@@ -96,9 +155,55 @@ impl Camera {
         CameraUniform {
             inv_view: inv_view.to_cols_array_2d(),
             inv_proj: inv_proj.to_cols_array_2d(),
+            lens: [
+                self.aperture_radius,
+                self.focus_distance,
+                self.bokeh_blades as f32,
+                self.bokeh_rotation,
+            ],
+            previous_inv_view: self.previous_inv_view.to_cols_array_2d(),
+            shutter: [self.shutter_open, self.shutter_close, 0.0, 0.0],
+            view_proj: (self.projection * self.view_matrix()).to_cols_array_2d(),
+            previous_view_proj: (self.projection * self.previous_inv_view.inverse()).to_cols_array_2d(),
         }
     }
 
+    // Pinhole ray through uv in [0, 1]^2, matching the raygen shader without lens sampling
+    pub fn primary_ray(&self, uv: Vec2) -> (Vec3, Vec3) {
+        let d = uv * 2.0 - 1.0;
+        let target = self.inverse_projection_matrix() * Vec4::new(d.x, -d.y, 1.0, 1.0);
+        let inv_view = self.inverse_view_matrix();
+
+        let origin = inv_view.transform_point3(Vec3::ZERO);
+        let direction = inv_view.transform_vector3(target.truncate().normalize()).normalize();
+        (origin, direction)
+    }
+
+    // Focuses on whatever lies at distance t along a primary ray, measured along the view axis
+    pub fn focus_on(&mut self, direction: Vec3, t: f32) {
+        let forward = self.inverse_view_matrix().transform_vector3(Vec3::NEG_Z).normalize();
+        self.set_focus_distance(t * direction.dot(forward));
+    }
+
+    // Pose and lens as key value pairs for image metadata, enough to reproduce the view
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let transform = &self.rig.final_transform;
+        let position: Vec3 = transform.position.into();
+        let rotation: glam::Quat = transform.rotation.into();
+        let (yaw, pitch, _) = rotation.to_euler(glam::EulerRot::YXZ);
+
+        vec![
+            ("Camera position".to_owned(), format!("{} {} {}", position.x, position.y, position.z)),
+            ("Camera rotation".to_owned(), format!("{} {} {} {}", rotation.x, rotation.y, rotation.z, rotation.w)),
+            ("Camera yaw pitch".to_owned(), format!("{} {}", yaw.to_degrees(), pitch.to_degrees())),
+            ("Field of view".to_owned(), format!("{}", self.fov.to_degrees())),
+            ("Aperture radius".to_owned(), self.aperture_radius.to_string()),
+            ("Focus distance".to_owned(), self.focus_distance.to_string()),
+            ("Bokeh blades".to_owned(), self.bokeh_blades.to_string()),
+            ("Shutter".to_owned(), format!("{} {}", self.shutter_open, self.shutter_close)),
+        ]
+    }
+
     pub fn rig_mut(&mut self) -> &mut CameraRig {
         &mut self.rig
     }
@@ -115,6 +220,11 @@ impl Camera {
                  yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees());
         println!("Aspect Ratio: {:.2}", self.aspect_ratio);
         println!("FOV: {:.2}°", self.fov.to_degrees());
+        println!(
+            "Lens: aperture radius={:.3}, focus distance={:.2}, blades={}",
+            self.aperture_radius, self.focus_distance, self.bokeh_blades
+        );
+        println!("Shutter: {:.2} to {:.2}", self.shutter_open, self.shutter_close);
         
         // Print view matrix
         let view = self.view_matrix();
@@ -0,0 +1,630 @@
+// Command line interface, parsed and validated without touching Vulkan
+
+use crate::renderer::{AdaptiveSampling, Backend, DebugView, DeviceSelector, DisplayMode, Integrator, RenderSettings, Tonemapper, WorkingSpace};
+use crate::resolution::MIN_RENDER_SCALE;
+use crate::scene::{Preset, Scene};
+use crate::screenshot::OutputFormat;
+use anyhow::{Result, bail, ensure};
+use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "vulkano_pathtracer", version, about = "Vulkan ray tracing path tracer")]
+pub struct Cli {
+    /// Render offline without a window and write the image to --output
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Built-in scene to render
+    #[arg(long, value_enum, default_value_t = Preset::Default)]
+    pub preset: Preset,
+
+    /// Scene file, currently a NanoVDB volume (.nvdb) placed in the --preset scene
+    #[arg(long, value_name = "FILE")]
+    pub scene: Option<PathBuf>,
+
+    /// Window or image size
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1280x720", value_parser = parse_size)]
+    pub size: (u32, u32),
+
+    /// Samples per pixel of a headless render, the maximum with --adaptive
+    #[arg(long, value_name = "N", requires = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    pub spp: Option<u32>,
+
+    /// Maximum number of scattering events along a path
+    #[arg(long, value_name = "N", default_value_t = 6)]
+    pub max_bounces: u32,
+
+    /// Image written by a headless render
+    #[arg(long, short, value_name = "FILE", requires = "headless")]
+    pub output: Option<PathBuf>,
+
+    /// Output file format, derived from the --output extension by default
+    #[arg(long, value_enum, requires = "headless")]
+    pub format: Option<OutputFormat>,
+
+    /// Physical device to render on, by index in enumeration order or by part of its name
+    #[arg(long, value_name = "INDEX|NAME")]
+    pub device: Option<DeviceSelector>,
+
+    /// Print every device with its ray tracing capabilities and exit
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// Ray tracing backend, the fastest one the device supports by default
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+
+    /// Tonemapping operator applied to the accumulated radiance
+    #[arg(long, value_enum, default_value_t = Tonemapper::Aces)]
+    pub tonemapper: Tonemapper,
+
+    /// Exposure adjustment in stops, relative to auto exposure when enabled
+    #[arg(long, value_name = "EV", default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// Expose for the average luminance of the image, measured with a histogram
+    #[arg(long)]
+    pub auto_exposure: bool,
+
+    /// Use the spectral integrator instead of RGB
+    #[arg(long)]
+    pub spectral: bool,
+
+    /// Render albedo, normal, depth, ID and motion AOVs, written as layers of EXR output
+    #[arg(long)]
+    pub aovs: bool,
+
+    /// Filter the displayed image with an edge-avoiding wavelet denoiser, guided by the AOVs
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// Temporal anti-aliasing, keeps the reprojected history while the camera moves
+    #[arg(long, conflicts_with = "headless")]
+    pub taa: bool,
+
+    /// Concentrate samples on noisy tiles, skipping tiles below --target-error
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Relative error of the noisiest pixel below which a tile stops receiving samples
+    #[arg(long, value_name = "ERROR", default_value_t = 0.01, requires = "adaptive")]
+    pub target_error: f32,
+
+    /// Samples every pixel receives before adaptive sampling skips tiles
+    #[arg(long, value_name = "N", default_value_t = 16, requires = "adaptive", value_parser = clap::value_parser!(u32).range(2..))]
+    pub min_spp: u32,
+
+    /// Stop a headless render after this many seconds, even below --spp
+    #[arg(long, value_name = "SECONDS", requires = "headless")]
+    pub time_budget: Option<f64>,
+
+    /// Output shown instead of the tonemapped image, also written by headless PNG renders
+    #[arg(long, value_enum, default_value_t = DebugView::Beauty)]
+    pub debug_view: DebugView,
+
+    /// Denoise the headless output with Intel Open Image Denoise, needs the oidn cargo feature
+    #[arg(long, requires = "headless")]
+    pub oidn: bool,
+
+    /// Primaries the RGB integrator works in, scene colours and outputs stay Rec. 709
+    #[arg(long, value_enum, default_value_t = WorkingSpace::Rec709)]
+    pub working_space: WorkingSpace,
+
+    /// Swapchain colour space, falls back to SDR when the display does not support it
+    #[arg(long, value_enum, default_value_t = DisplayMode::Sdr, conflicts_with = "headless")]
+    pub display: DisplayMode,
+
+    /// Luminance of SDR white on HDR displays, in nits
+    #[arg(long, value_name = "NITS", default_value_t = 203.0)]
+    pub paper_white: f32,
+
+    /// Swapchain presentation, falls back to FIFO when the surface does not support it
+    #[arg(long, value_enum, default_value_t = PresentMode::Fifo, conflicts_with = "headless")]
+    pub present_mode: PresentMode,
+
+    /// Frames recorded ahead of the GPU, 1 waits for every frame to finish
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=3))]
+    pub frames_in_flight: u32,
+
+    /// Limit the frame rate of the window, useful with the mailbox and immediate present modes
+    #[arg(long, value_name = "FPS", conflicts_with = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_fps: Option<u32>,
+
+    /// Resolution traced relative to the window, the image is scaled to the window when presented
+    #[arg(long, value_name = "SCALE", default_value_t = 1.0, conflicts_with = "headless")]
+    pub render_scale: f32,
+
+    /// Lower the render scale while the camera moves to keep frames within this many milliseconds
+    #[arg(long, value_name = "MS", conflicts_with = "headless")]
+    pub frame_budget: Option<f64>,
+
+    /// Filter scaling the traced image to the window
+    #[arg(long, value_enum, default_value_t = Upscaler::Edge, conflicts_with = "headless")]
+    pub upscaler: Upscaler,
+
+    /// Show frame and GPU pass timings on screen from the start, F3 toggles them
+    #[arg(long, conflicts_with = "headless")]
+    pub overlay: bool,
+
+    /// Write the timings of every frame to a CSV file
+    #[arg(long, value_name = "PATH", conflicts_with = "headless")]
+    pub stats_log: Option<PathBuf>,
+
+    /// Render on the CPU reference path tracer instead of Vulkan, writes linear radiance only
+    #[arg(long, requires = "headless", conflicts_with_all = ["spectral", "aovs", "denoise", "oidn", "adaptive", "time_budget", "debug_view"])]
+    pub cpu: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum PresentMode {
+    /// Wait for vertical blank, never tears
+    Fifo,
+    /// Replace the queued image on every frame, no tearing and low latency
+    Mailbox,
+    /// Present right away, may tear
+    Immediate,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Upscaler {
+    /// Edge-aware Lanczos filter, keeps edges sharp without ringing
+    Edge,
+    /// Plain bilinear blit
+    Bilinear,
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("'{size}' is not of the form WIDTHxHEIGHT"))?;
+    let width: u32 = width.parse().map_err(|_| format!("invalid width '{width}'"))?;
+    let height: u32 = height.parse().map_err(|_| format!("invalid height '{height}'"))?;
+    if width == 0 || height == 0 {
+        return Err(format!("'{size}' has a zero dimension"));
+    }
+    Ok((width, height))
+}
+
+pub struct HeadlessOptions {
+    pub samples: u32,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    pub oidn: bool,
+    pub time_budget: Option<Duration>,
+    pub cpu: bool,
+}
+
+pub enum Mode {
+    Interactive,
+    Headless(HeadlessOptions),
+    ListDevices,
+}
+
+// Validated settings shared by the interactive and headless paths
+pub struct Config {
+    pub mode: Mode,
+    pub preset: Preset,
+    pub scene: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub device: Option<DeviceSelector>,
+    pub backend: Option<Backend>,
+    pub integrator: Integrator,
+    pub render: RenderSettings,
+    pub display: DisplayMode,
+    pub paper_white: f32,
+    pub present_mode: PresentMode,
+    pub max_fps: Option<u32>,
+    pub render_scale: f32,
+    pub frame_budget: Option<Duration>,
+    pub upscaler: Upscaler,
+    pub overlay: bool,
+    pub stats_log: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load_scene(&self) -> Result<Scene> {
+        let mut scene = self.preset.build();
+        if let Some(path) = &self.scene {
+            scene.add_file(path)?;
+        }
+        Ok(scene)
+    }
+}
+
+impl Cli {
+    // Checks the combinations clap can not express on its own
+    pub fn validate(self) -> Result<Config> {
+        ensure!(self.exposure.is_finite(), "Exposure must be a finite number of stops");
+        ensure!(self.max_bounces > 0, "--max-bounces must be at least 1");
+        ensure!(
+            self.paper_white.is_finite() && self.paper_white > 0.0,
+            "--paper-white must be a positive number of nits"
+        );
+        ensure!(
+            (MIN_RENDER_SCALE..=2.0).contains(&self.render_scale),
+            "--render-scale must be between {MIN_RENDER_SCALE} and 2"
+        );
+        ensure!(
+            self.target_error.is_finite() && self.target_error > 0.0,
+            "--target-error must be a positive number"
+        );
+        let time_budget = self
+            .time_budget
+            .map(|seconds| {
+                ensure!(seconds.is_finite() && seconds > 0.0, "--time-budget must be a positive number of seconds");
+                Ok(Duration::from_secs_f64(seconds))
+            })
+            .transpose()?;
+        let frame_budget = self
+            .frame_budget
+            .map(|milliseconds| {
+                ensure!(
+                    milliseconds.is_finite() && milliseconds > 0.0,
+                    "--frame-budget must be a positive number of milliseconds"
+                );
+                Ok(Duration::from_secs_f64(milliseconds / 1000.0))
+            })
+            .transpose()?;
+
+        let mode = if self.list_devices {
+            Mode::ListDevices
+        } else if self.headless {
+            let output = self.output.unwrap_or_else(|| {
+                let format = self.format.unwrap_or(OutputFormat::Png);
+                PathBuf::from("render").with_extension(format.extension())
+            });
+
+            let format = match (self.format, OutputFormat::from_extension(&output)) {
+                (Some(format), Some(extension)) if format != extension => bail!(
+                    "--format {format:?} does not match the extension of {}",
+                    output.display()
+                ),
+                (Some(format), _) | (None, Some(format)) => format,
+                (None, None) => bail!(
+                    "Can not derive the format of {}, use a .png, .exr or .hdr extension or pass --format",
+                    output.display()
+                ),
+            };
+
+            ensure!(
+                !self.aovs || format == OutputFormat::Exr,
+                "AOV layers can only be written to EXR files"
+            );
+            ensure!(
+                !self.oidn || cfg!(feature = "oidn"),
+                "--oidn needs a build with the oidn cargo feature"
+            );
+            // The reference renderer has no tonemapping and integrates in Rec. 709 only
+            ensure!(
+                !self.cpu || format.is_hdr(),
+                "--cpu renders can only be written to EXR or HDR files"
+            );
+            ensure!(
+                !self.cpu || self.working_space == WorkingSpace::Rec709,
+                "--cpu renders always use the Rec. 709 working space"
+            );
+
+            Mode::Headless(HeadlessOptions {
+                samples: self.spp.unwrap_or(256),
+                output,
+                format,
+                oidn: self.oidn,
+                time_budget,
+                cpu: self.cpu,
+            })
+        } else {
+            Mode::Interactive
+        };
+
+        if let Some(scene) = &self.scene {
+            ensure!(scene.exists(), "Scene file {} does not exist", scene.display());
+        }
+
+        Ok(Config {
+            mode,
+            preset: self.preset,
+            scene: self.scene,
+            width: self.size.0,
+            height: self.size.1,
+            device: self.device,
+            backend: self.backend,
+            integrator: if self.spectral { Integrator::Spectral } else { Integrator::Rgb },
+            render: RenderSettings {
+                max_bounces: self.max_bounces,
+                tonemapper: self.tonemapper,
+                exposure: self.exposure,
+                auto_exposure: self.auto_exposure,
+                // Debug views other than the sample heatmap read the AOVs
+                aovs: self.aovs || !matches!(self.debug_view, DebugView::Beauty | DebugView::Samples),
+                debug_view: self.debug_view,
+                working_space: self.working_space,
+                denoise: self.denoise,
+                taa: self.taa,
+                adaptive: self.adaptive.then_some(AdaptiveSampling {
+                    target_error: self.target_error,
+                    min_samples: self.min_spp,
+                }),
+                frames_in_flight: self.frames_in_flight,
+            },
+            display: self.display,
+            paper_white: self.paper_white,
+            present_mode: self.present_mode,
+            max_fps: self.max_fps,
+            render_scale: self.render_scale,
+            frame_budget,
+            upscaler: self.upscaler,
+            overlay: self.overlay,
+            stats_log: self.stats_log,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config> {
+        let cli = Cli::try_parse_from(std::iter::once("vulkano_pathtracer").chain(args.iter().copied()))?;
+        cli.validate()
+    }
+
+    #[test]
+    fn defaults_to_interactive() {
+        let config = parse(&[]).unwrap();
+        assert!(matches!(config.mode, Mode::Interactive));
+        assert_eq!((config.width, config.height), (1280, 720));
+        assert_eq!(config.integrator, Integrator::Rgb);
+        assert_eq!(config.preset, Preset::Default);
+    }
+
+    #[test]
+    fn render_settings() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.render.max_bounces, 6);
+        assert_eq!(config.render.tonemapper, Tonemapper::Aces);
+        assert_eq!(config.render.working_space, WorkingSpace::Rec709);
+
+        assert!(parse(&["--max-bounces", "0"]).is_err());
+    }
+
+    #[test]
+    fn selects_presets_by_name() {
+        for (name, preset) in [
+            ("cornell-box", Preset::CornellBox),
+            ("sphere-grid", Preset::SphereGrid),
+            ("furnace", Preset::Furnace),
+            ("corridor", Preset::Corridor),
+            ("motion-blur", Preset::MotionBlur),
+        ] {
+            let config = parse(&["--preset", name]).unwrap();
+            assert_eq!(config.preset, preset);
+            assert!(!config.load_scene().unwrap().instances.is_empty());
+        }
+        assert!(parse(&["--preset", "sponza"]).is_err());
+    }
+
+    #[test]
+    fn headless_render_settings() {
+        let config = parse(&[
+            "--headless",
+            "--size",
+            "640x480",
+            "--spp",
+            "16",
+            "--max-bounces",
+            "3",
+            "-o",
+            "out.png",
+            "--exposure",
+            "-1.5",
+            "--device",
+            "1",
+            "--backend",
+            "ray-query",
+            "--spectral",
+            "--tonemapper",
+            "pbr-neutral",
+            "--auto-exposure",
+            "--working-space",
+            "acescg",
+            "--denoise",
+        ])
+        .unwrap();
+
+        let Mode::Headless(headless) = config.mode else {
+            panic!("expected headless mode");
+        };
+        assert_eq!(headless.samples, 16);
+        assert_eq!(headless.output, PathBuf::from("out.png"));
+        assert_eq!(headless.format, OutputFormat::Png);
+        assert_eq!((config.width, config.height), (640, 480));
+        assert_eq!(config.render.max_bounces, 3);
+        assert_eq!(config.render.exposure, -1.5);
+        assert_eq!(config.device, Some(DeviceSelector::Index(1)));
+        assert_eq!(config.backend, Some(Backend::RayQuery));
+        assert_eq!(config.integrator, Integrator::Spectral);
+        assert_eq!(config.render.tonemapper, Tonemapper::PbrNeutral);
+        assert!(config.render.auto_exposure);
+        assert_eq!(config.render.working_space, WorkingSpace::Acescg);
+        assert!(config.render.denoise);
+    }
+
+    #[test]
+    fn device_selection() {
+        let config = parse(&["--device", "GeForce RTX", "--list-devices"]).unwrap();
+        assert_eq!(config.device, Some(DeviceSelector::Name("GeForce RTX".to_owned())));
+        assert!(matches!(config.mode, Mode::ListDevices));
+        assert!(parse(&["--device", " "]).is_err());
+    }
+
+    #[test]
+    fn display_settings() {
+        assert_eq!(parse(&[]).unwrap().display, DisplayMode::Sdr);
+
+        let config = parse(&["--display", "hdr10", "--paper-white", "250"]).unwrap();
+        assert_eq!(config.display, DisplayMode::Hdr10);
+        assert_eq!(config.paper_white, 250.0);
+
+        assert!(parse(&["--headless", "--display", "scrgb"]).is_err());
+        assert!(parse(&["--paper-white", "0"]).is_err());
+    }
+
+    #[test]
+    fn taa_settings() {
+        assert!(parse(&["--taa"]).unwrap().render.taa);
+        assert!(parse(&["--headless", "--taa"]).is_err());
+    }
+
+    #[test]
+    fn frame_pacing_settings() {
+        let config = parse(&["--present-mode", "mailbox", "--frames-in-flight", "3", "--max-fps", "144"]).unwrap();
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+        assert_eq!(config.render.frames_in_flight, 3);
+        assert_eq!(config.max_fps, Some(144));
+
+        assert!(parse(&["--frames-in-flight", "4"]).is_err());
+        assert!(parse(&["--headless", "--max-fps", "60"]).is_err());
+    }
+
+    #[test]
+    fn render_scale_settings() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.render_scale, 1.0);
+        assert_eq!(config.frame_budget, None);
+        assert_eq!(config.upscaler, Upscaler::Edge);
+
+        let config = parse(&["--render-scale", "0.5", "--frame-budget", "12.5", "--upscaler", "bilinear"]).unwrap();
+        assert_eq!(config.render_scale, 0.5);
+        assert_eq!(config.frame_budget, Some(Duration::from_micros(12500)));
+        assert_eq!(config.upscaler, Upscaler::Bilinear);
+
+        assert!(parse(&["--render-scale", "0"]).is_err());
+        assert!(parse(&["--headless", "--render-scale", "0.5"]).is_err());
+        assert!(parse(&["--frame-budget", "0"]).is_err());
+        assert!(parse(&["--headless", "--upscaler", "edge"]).is_err());
+    }
+
+    #[test]
+    fn statistics_settings() {
+        let config = parse(&["--overlay", "--stats-log", "frames.csv"]).unwrap();
+        assert!(config.overlay);
+        assert_eq!(config.stats_log, Some(PathBuf::from("frames.csv")));
+
+        assert!(parse(&["--headless", "--stats-log", "frames.csv"]).is_err());
+    }
+
+    #[test]
+    fn adaptive_sampling_settings() {
+        assert_eq!(parse(&[]).unwrap().render.adaptive, None);
+
+        let config = parse(&[
+            "--headless",
+            "--spp",
+            "1024",
+            "--adaptive",
+            "--target-error",
+            "0.02",
+            "--min-spp",
+            "32",
+            "--time-budget",
+            "1.5",
+            "--debug-view",
+            "samples",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.render.adaptive,
+            Some(AdaptiveSampling {
+                target_error: 0.02,
+                min_samples: 32,
+            })
+        );
+        assert_eq!(config.render.debug_view, DebugView::Samples);
+        assert!(!config.render.aovs);
+        let Mode::Headless(headless) = config.mode else {
+            panic!("expected headless mode");
+        };
+        assert_eq!(headless.time_budget, Some(Duration::from_millis(1500)));
+
+        // First hit views need the AOVs
+        assert!(parse(&["--debug-view", "normal"]).unwrap().render.aovs);
+
+        assert!(parse(&["--target-error", "0.1"]).is_err());
+        assert!(parse(&["--adaptive", "--target-error", "0"]).is_err());
+        assert!(parse(&["--adaptive", "--min-spp", "1"]).is_err());
+        assert!(parse(&["--time-budget", "10"]).is_err());
+        assert!(parse(&["--headless", "--time-budget", "-1"]).is_err());
+    }
+
+    #[test]
+    fn cpu_reference_settings() {
+        let config = parse(&["--headless", "--cpu", "-o", "reference.exr", "--spp", "64"]).unwrap();
+        let Mode::Headless(headless) = config.mode else {
+            panic!("expected headless mode");
+        };
+        assert!(headless.cpu);
+        assert_eq!(headless.samples, 64);
+
+        assert!(parse(&["--cpu"]).is_err());
+        assert!(parse(&["--headless", "--cpu", "-o", "reference.png"]).is_err());
+        assert!(parse(&["--headless", "--cpu", "-o", "reference.exr", "--spectral"]).is_err());
+        assert!(parse(&["--headless", "--cpu", "-o", "reference.exr", "--working-space", "acescg"]).is_err());
+    }
+
+    #[test]
+    fn format_follows_extension() {
+        let config = parse(&["--headless", "-o", "out.EXR"]).unwrap();
+        let Mode::Headless(headless) = config.mode else {
+            panic!("expected headless mode");
+        };
+        assert_eq!(headless.format, OutputFormat::Exr);
+
+        let config = parse(&["--headless", "-o", "out.exr", "--aovs"]).unwrap();
+        assert!(config.render.aovs);
+
+        let config = parse(&["--headless", "--format", "hdr"]).unwrap();
+        let Mode::Headless(headless) = config.mode else {
+            panic!("expected headless mode");
+        };
+        assert_eq!(headless.output, PathBuf::from("render.hdr"));
+    }
+
+    #[test]
+    fn output_settings_require_headless() {
+        assert!(parse(&["--output", "out.png"]).is_err());
+        assert!(parse(&["--spp", "4"]).is_err());
+        assert!(parse(&["--format", "png"]).is_err());
+        assert!(parse(&["--headless", "--spp", "0"]).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_output_formats() {
+        assert!(parse(&["--headless", "-o", "out.png", "--format", "exr"]).is_err());
+        assert!(parse(&["--headless", "-o", "out.jpg"]).is_err());
+        // AOV layers only fit into EXR files
+        assert!(parse(&["--headless", "-o", "out.png", "--aovs"]).is_err());
+    }
+
+    #[test]
+    fn denoiser_settings() {
+        assert!(parse(&["--headless", "--denoise"]).unwrap().render.denoise);
+        assert!(parse(&["--oidn"]).is_err());
+        assert_eq!(parse(&["--headless", "--oidn"]).is_ok(), cfg!(feature = "oidn"));
+    }
+
+    #[test]
+    fn rejects_missing_scene_files() {
+        assert!(parse(&["--scene", "does/not/exist.nvdb"]).is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+        assert!(parse_size("1920").is_err());
+        assert!(parse_size("0x10").is_err());
+        assert!(parse_size("ax10").is_err());
+    }
+}
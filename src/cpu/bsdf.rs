@@ -0,0 +1,332 @@
+// Metallic-roughness surfaces, a port of bsdf.glsl. Directions are in the shading frame with
+// the normal as z, wo points towards the viewer.
+
+use super::sampling::sample_cosine_hemisphere;
+use crate::scene::Material;
+use glam::{Vec2, Vec3};
+use std::f32::consts::PI;
+
+// Smallest GGX alpha, smoother surfaces become numerically unstable
+const MIN_ALPHA: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bsdf {
+    albedo: Vec3,
+    alpha: f32,
+    // Specular reflectance at normal incidence
+    f0: Vec3,
+    diffuse_weight: f32,
+}
+
+fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn ggx_distribution(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+// Visible normal of the GGX distribution seen from wo (Heitz 2018)
+fn sample_ggx_visible_normal(wo: Vec3, alpha: f32, u: Vec2) -> Vec3 {
+    let v = Vec3::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+    let length2 = v.x * v.x + v.y * v.y;
+    let t1 = if length2 > 0.0 {
+        Vec3::new(-v.y, v.x, 0.0) / length2.sqrt()
+    } else {
+        Vec3::X
+    };
+    let t2 = v.cross(t1);
+
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+    Vec3::new(alpha * n.x, alpha * n.y, n.z.max(0.0)).normalize()
+}
+
+impl Bsdf {
+    pub fn new(material: &Material) -> Self {
+        Self {
+            albedo: material.base_color,
+            alpha: (material.roughness * material.roughness).max(MIN_ALPHA),
+            f0: Vec3::splat(0.04).lerp(material.base_color, material.metallic),
+            diffuse_weight: 1.0 - material.metallic,
+        }
+    }
+
+    // BSDF times the cosine of wi. The base is weighted by the Fresnel transmission in both
+    // directions, which keeps it reciprocal and the albedo at most one.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let h = (wo + wi).normalize();
+        let g2 = 1.0 / (1.0 + smith_lambda(wo.z, self.alpha) + smith_lambda(wi.z, self.alpha));
+        let specular =
+            fresnel_schlick(self.f0, wo.dot(h)) * ggx_distribution(h.z, self.alpha) * g2 / (4.0 * wo.z * wi.z);
+        let transmission = (Vec3::ONE - fresnel_schlick(self.f0, wo.z)) * (Vec3::ONE - fresnel_schlick(self.f0, wi.z));
+        let diffuse = self.diffuse_weight * self.albedo / PI * transmission;
+        (specular + diffuse) * wi.z
+    }
+
+    // Chance of sampling the specular lobe, by its reflectance relative to the base
+    fn specular_probability(&self, wo: Vec3) -> f32 {
+        let specular = fresnel_schlick(self.f0, wo.z).max_element();
+        let diffuse = self.diffuse_weight * self.albedo.max_element() * (1.0 - specular);
+        if specular + diffuse > 0.0 { specular / (specular + diffuse) } else { 1.0 }
+    }
+
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let h = (wo + wi).normalize();
+        let specular = ggx_distribution(h.z, self.alpha) / ((1.0 + smith_lambda(wo.z, self.alpha)) * 4.0 * wo.z);
+        let diffuse = wi.z / PI;
+        diffuse + (specular - diffuse) * self.specular_probability(wo)
+    }
+
+    // Picks a lobe with u_lobe and a direction in it with u. Returns the direction and the BSDF
+    // times the cosine over the combined pdf, None when the direction points below the surface.
+    pub fn sample(&self, wo: Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)> {
+        let wi = if u_lobe < self.specular_probability(wo) {
+            let h = sample_ggx_visible_normal(wo, self.alpha, u);
+            2.0 * wo.dot(h) * h - wo
+        } else {
+            sample_cosine_hemisphere(u)
+        };
+
+        let pdf = self.pdf(wo, wi);
+        (pdf > 0.0).then(|| (wi, self.eval(wo, wi) / pdf))
+    }
+}
+
+// Monte Carlo checks of the shipped BSDF over the whole parameter range. bsdf.glsl is a line by
+// line port, so a failure here points at a bug in both renderers.
+#[cfg(test)]
+mod tests {
+    use super::super::sampling::Rng;
+    use super::*;
+
+    const SAMPLES: u32 = 100_000;
+
+    fn material(roughness: f32, metallic: f32) -> Bsdf {
+        Bsdf::new(&Material {
+            base_color: Vec3::new(1.0, 0.77, 0.34),
+            roughness,
+            metallic,
+        })
+    }
+
+    // Rough enough for the integration grids to resolve the specular lobe
+    fn rough_materials() -> impl Iterator<Item = (f32, f32)> {
+        [0.3, 0.6, 1.0].into_iter().flat_map(|roughness| [0.0, 0.5, 1.0].map(|metallic| (roughness, metallic)))
+    }
+
+    fn directions() -> [Vec3; 4] {
+        [0.0f32, 30.0, 60.0, 85.0].map(|degrees| {
+            let theta = degrees.to_radians();
+            Vec3::new(theta.sin(), 0.0, theta.cos())
+        })
+    }
+
+    fn direction(theta: f32, phi: f32) -> Vec3 {
+        Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    // Midpoint rule over the part of the upper hemisphere between the given polar and azimuth
+    // angles, uniform in the angles so lobes around the normal are resolved as well
+    fn integrate(theta: [f32; 2], phi: [f32; 2], resolution: usize, f: impl Fn(Vec3) -> Vec3) -> Vec3 {
+        let d_theta = (theta[1] - theta[0]) / resolution as f32;
+        let d_phi = (phi[1] - phi[0]) / (2 * resolution) as f32;
+        let mut sum = Vec3::ZERO;
+        for i in 0..resolution {
+            let t = theta[0] + (i as f32 + 0.5) * d_theta;
+            let row: Vec3 = (0..2 * resolution).map(|j| f(direction(t, phi[0] + (j as f32 + 0.5) * d_phi))).sum();
+            sum += row * t.sin();
+        }
+        sum * d_theta * d_phi
+    }
+
+    fn hemisphere(resolution: usize, f: impl Fn(Vec3) -> Vec3) -> Vec3 {
+        integrate([0.0, PI / 2.0], [0.0, 2.0 * PI], resolution, f)
+    }
+
+    // Mean and standard error of the sampled weights, failed samples count as zero
+    fn sampled_albedo(bsdf: &Bsdf, wo: Vec3) -> (Vec3, Vec3) {
+        let mut rng = Rng::new([0, 0], 0);
+        let (mut sum, mut sum2) = (Vec3::ZERO, Vec3::ZERO);
+        for _ in 0..SAMPLES {
+            let u_lobe = rng.next();
+            if let Some((_, weight)) = bsdf.sample(wo, u_lobe, rng.next2()) {
+                sum += weight;
+                sum2 += weight * weight;
+            }
+        }
+        let mean = sum / SAMPLES as f32;
+        let variance = sum2 / SAMPLES as f32 - mean * mean;
+        (mean, (variance.max(Vec3::ZERO) / SAMPLES as f32).powf(0.5))
+    }
+
+    #[test]
+    fn albedo_is_at_most_one() {
+        for roughness in [0.0, 0.1, 0.3, 0.6, 1.0] {
+            for metallic in [0.0, 0.5, 1.0] {
+                for wo in directions() {
+                    let bsdf = Bsdf::new(&Material {
+                        base_color: Vec3::ONE,
+                        roughness,
+                        metallic,
+                    });
+                    let (albedo, error) = sampled_albedo(&bsdf, wo);
+                    assert!(
+                        albedo.cmple(Vec3::ONE + 3.0 * error + 1e-3).all(),
+                        "roughness {roughness}, metallic {metallic}, wo {wo}: albedo {albedo}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_albedo_matches_integrated_eval() {
+        for (roughness, metallic) in rough_materials() {
+            let bsdf = material(roughness, metallic);
+            for wo in directions() {
+                let (sampled, error) = sampled_albedo(&bsdf, wo);
+                let integrated = hemisphere(256, |wi| bsdf.eval(wo, wi));
+                assert!(
+                    (sampled - integrated).abs().cmple(4.0 * error + 2e-3).all(),
+                    "roughness {roughness}, metallic {metallic}, wo {wo}: sampled {sampled}, integrated {integrated}"
+                );
+            }
+        }
+    }
+
+    // Sampled directions below the surface are rejected, together with them the pdf covers all
+    #[test]
+    fn pdf_integrates_to_one() {
+        for (roughness, metallic) in rough_materials() {
+            let bsdf = material(roughness, metallic);
+            for wo in directions() {
+                let mut rng = Rng::new([0, 0], 0);
+                let rejected = (0..SAMPLES)
+                    .filter(|_| {
+                        let u_lobe = rng.next();
+                        bsdf.sample(wo, u_lobe, rng.next2()).is_none()
+                    })
+                    .count() as f32
+                    / SAMPLES as f32;
+                let integral = hemisphere(256, |wi| Vec3::splat(bsdf.pdf(wo, wi))).x;
+                assert!(
+                    (integral + rejected - 1.0).abs() < 5e-3,
+                    "roughness {roughness}, metallic {metallic}, wo {wo}: integral {integral}, rejected {rejected}"
+                );
+            }
+        }
+    }
+
+    // Pearson's chi-square test of the sampled directions against the pdf, on a grid of polar
+    // and azimuth bins plus one for the rejected samples. Bins expecting fewer than five samples
+    // are pooled. Returns the statistic as a standard normal score (Wilson-Hilferty).
+    fn chi_square(bsdf: &Bsdf, wo: Vec3) -> f32 {
+        const THETA_BINS: usize = 10;
+        const PHI_BINS: usize = 20;
+        let bin_theta = PI / 2.0 / THETA_BINS as f32;
+        let bin_phi = 2.0 * PI / PHI_BINS as f32;
+
+        let mut observed = vec![0.0; THETA_BINS * PHI_BINS + 1];
+        let mut rng = Rng::new([0, 0], 0);
+        for _ in 0..SAMPLES {
+            let u_lobe = rng.next();
+            let bin = match bsdf.sample(wo, u_lobe, rng.next2()) {
+                Some((wi, _)) => {
+                    let theta = ((wi.z.clamp(-1.0, 1.0).acos() / bin_theta) as usize).min(THETA_BINS - 1);
+                    let phi = ((wi.y.atan2(wi.x).rem_euclid(2.0 * PI) / bin_phi) as usize).min(PHI_BINS - 1);
+                    theta * PHI_BINS + phi
+                }
+                None => THETA_BINS * PHI_BINS,
+            };
+            observed[bin] += 1.0;
+        }
+
+        let mut expected: Vec<f32> = (0..THETA_BINS * PHI_BINS)
+            .map(|bin| {
+                let theta = (bin / PHI_BINS) as f32 * bin_theta;
+                let phi = (bin % PHI_BINS) as f32 * bin_phi;
+                let probability = integrate([theta, theta + bin_theta], [phi, phi + bin_phi], 16, |wi| {
+                    Vec3::splat(bsdf.pdf(wo, wi))
+                });
+                probability.x * SAMPLES as f32
+            })
+            .collect();
+        let covered: f32 = expected.iter().sum();
+        expected.push((SAMPLES as f32 - covered).max(0.0));
+
+        let (mut statistic, mut bins) = (0.0, 0);
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (observed, expected) in observed.into_iter().zip(expected) {
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                statistic += (observed - expected) * (observed - expected) / expected;
+                bins += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            statistic += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected) / pooled_expected;
+            bins += 1;
+        }
+
+        let dof = (bins - 1) as f32;
+        let variance = 2.0 / (9.0 * dof);
+        ((statistic / dof).cbrt() - (1.0 - variance)) / variance.sqrt()
+    }
+
+    #[test]
+    fn samples_follow_pdf() {
+        for (roughness, metallic) in rough_materials() {
+            let bsdf = material(roughness, metallic);
+            for wo in directions() {
+                let z = chi_square(&bsdf, wo);
+                assert!(z < 4.0, "roughness {roughness}, metallic {metallic}, wo {wo}: z {z}");
+            }
+        }
+    }
+
+    #[test]
+    fn eval_is_reciprocal() {
+        let mut rng = Rng::new([0, 0], 0);
+        let mut random_direction = || {
+            let u = rng.next2();
+            direction((1.0 - u.x).acos(), 2.0 * PI * u.y)
+        };
+        for roughness in [0.0, 0.3, 1.0] {
+            for metallic in [0.0, 0.5, 1.0] {
+                let bsdf = material(roughness, metallic);
+                for _ in 0..1000 {
+                    let (a, b) = (random_direction(), random_direction());
+                    let ab = bsdf.eval(a, b) / b.z;
+                    let ba = bsdf.eval(b, a) / a.z;
+                    assert!(
+                        (ab - ba).abs().cmple(ab.abs() * 1e-3 + 1e-6).all(),
+                        "roughness {roughness}, metallic {metallic}: {ab} from {a} to {b}, {ba} back"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Participating media, a port of medium.glsl working on the scene's Medium directly
+
+use super::sampling::Rng;
+use crate::scene::{DensityGrid, Medium, Ray};
+use glam::{IVec3, Vec3};
+
+const MAX_TRACKING_STEPS: usize = 256;
+
+// Representative wavelengths of the RGB primaries in nm
+const RGB_WAVELENGTHS: Vec3 = Vec3::new(610.0, 550.0, 465.0);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MediumEvent {
+    Pass,
+    Scatter,
+    Absorb,
+}
+
+fn average(v: Vec3) -> f32 {
+    (v.x + v.y + v.z) / 3.0
+}
+
+fn grid_density(grid: &DensityGrid, p: Vec3) -> f32 {
+    let uvw = (p - grid.bounds_min) / (grid.bounds_max - grid.bounds_min);
+    if uvw.cmplt(Vec3::ZERO).any() || uvw.cmpgt(Vec3::ONE).any() {
+        return 0.0;
+    }
+
+    let resolution = IVec3::from_array(grid.resolution.map(|r| r as i32));
+    let coord = uvw * resolution.as_vec3() - 0.5;
+    let c0 = coord.floor().as_ivec3();
+    let f = coord - c0.as_vec3();
+
+    // Trilinear interpolation, never exceeds the grid maximum
+    let mut density = 0.0;
+    for i in 0..8 {
+        let o = IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        let c = (c0 + o).clamp(IVec3::ZERO, resolution - 1);
+        let w = Vec3::select(o.cmpeq(IVec3::ONE), f, Vec3::ONE - f);
+        let index = c.x + resolution.x * (c.y + resolution.y * c.z);
+        density += w.x * w.y * w.z * grid.values[index as usize];
+    }
+    density
+}
+
+// Medium with its majorant, which needs a pass over the density grid
+pub struct MediumData<'a> {
+    medium: &'a Medium,
+    majorant: f32,
+}
+
+impl<'a> MediumData<'a> {
+    pub fn new(medium: &'a Medium) -> Self {
+        let max_density = medium.density.as_ref().map_or(1.0, DensityGrid::max_density);
+        Self {
+            medium,
+            majorant: (medium.sigma_a + medium.sigma_s).max_element() * max_density,
+        }
+    }
+
+    pub fn g(&self) -> f32 {
+        self.medium.g
+    }
+
+    fn density(&self, p: Vec3) -> f32 {
+        self.medium.density.as_ref().map_or(1.0, |grid| grid_density(grid, p))
+    }
+
+    // Restricts [t_min, t_max] to the part of the ray where the medium can have non-zero density
+    fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let Some(grid) = &self.medium.density else {
+            return (t_max > t_min).then_some((t_min, t_max));
+        };
+
+        let t0 = (grid.bounds_min - ray.origin) * ray.inv_direction;
+        let t1 = (grid.bounds_max - ray.origin) * ray.inv_direction;
+        let t_min = t_min.max(t0.min(t1).max_element());
+        let t_max = t_max.min(t0.max(t1).min_element());
+        (t_max > t_min).then_some((t_min, t_max))
+    }
+
+    // Spectral delta tracking with history aware collision probabilities, see sample_medium in
+    // medium.glsl. Returns the event and where it happened, the throughput is weighted for both
+    // real events.
+    pub fn sample(&self, ray: &Ray, t_max: f32, rng: &mut Rng, throughput: &mut Vec3) -> (MediumEvent, f32) {
+        let majorant = self.majorant;
+        let Some((mut t, t_max)) = self.clip(ray, 0.0, t_max).filter(|_| majorant > 0.0) else {
+            return (MediumEvent::Pass, 0.0);
+        };
+
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rng.next()).ln() / majorant;
+            if t >= t_max {
+                return (MediumEvent::Pass, t);
+            }
+
+            let density = self.density(ray.at(t));
+            let sigma_a = self.medium.sigma_a * density;
+            let sigma_s = self.medium.sigma_s * density;
+            let sigma_n = Vec3::splat(majorant) - sigma_a - sigma_s;
+
+            let p_a = average(sigma_a * *throughput);
+            let p_s = average(sigma_s * *throughput);
+            let p_n = average((sigma_n * *throughput).abs());
+            let c = p_a + p_s + p_n;
+            if c <= 0.0 {
+                return (MediumEvent::Absorb, t);
+            }
+
+            let u = rng.next() * c;
+            if u < p_a {
+                *throughput *= sigma_a * (c / (majorant * p_a));
+                return (MediumEvent::Absorb, t);
+            }
+            if u < p_a + p_s {
+                *throughput *= sigma_s * (c / (majorant * p_s));
+                return (MediumEvent::Scatter, t);
+            }
+            *throughput *= sigma_n * (c / (majorant * p_n));
+        }
+
+        // Out of steps, end the path without contribution, as in medium.glsl
+        *throughput = Vec3::ZERO;
+        (MediumEvent::Absorb, t)
+    }
+
+    // Ratio tracking for heterogeneous media, closed form otherwise
+    pub fn transmittance(&self, ray: &Ray, t_max: f32, rng: &mut Rng) -> Vec3 {
+        let sigma_t = self.medium.sigma_a + self.medium.sigma_s;
+        if self.medium.density.is_none() {
+            return (-sigma_t * t_max).exp();
+        }
+
+        let majorant = self.majorant;
+        let Some((mut t, t_max)) = self.clip(ray, 0.0, t_max).filter(|_| majorant > 0.0) else {
+            return Vec3::ONE;
+        };
+
+        let mut transmittance = Vec3::ONE;
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rng.next()).ln() / majorant;
+            if t >= t_max {
+                break;
+            }
+
+            transmittance *= Vec3::ONE - sigma_t * (self.density(ray.at(t)) / majorant);
+
+            // Russian roulette once the estimate becomes small
+            let max_transmittance = transmittance.max_element();
+            if max_transmittance < 0.1 {
+                if rng.next() > max_transmittance {
+                    return Vec3::ZERO;
+                }
+                transmittance /= max_transmittance;
+            }
+        }
+        transmittance
+    }
+
+    // Emitted radiance per unit absorption at p, at the representative RGB wavelengths
+    pub fn emission(&self, p: Vec3) -> Vec3 {
+        let Some(grid) = &self.medium.temperature else {
+            return Vec3::ZERO;
+        };
+        let temperature = grid_density(grid, p) * self.medium.temperature_scale;
+        blackbody(temperature, RGB_WAVELENGTHS) * self.medium.emission_scale
+    }
+}
+
+// Planck's law at three wavelengths given in nm, in W sr^-1 m^-2 nm^-1
+fn blackbody(temperature: f32, lambda_nm: Vec3) -> Vec3 {
+    if temperature <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let lambda = lambda_nm * 1e-9;
+    let lambda5 = lambda * lambda * lambda * lambda * lambda;
+    1.191042e-16 / (lambda5 * ((1.4387769e-2 / (lambda * temperature)).exp() - 1.0)) * 1e-9
+}
@@ -0,0 +1,596 @@
+// CPU reference path tracer: the RGB integrator of rgen.glsl on the same Scene and
+// CameraUniform, traced through its own BVHs with tiles spread over all cores. Needs no GPU,
+// so it renders in CI and serves as ground truth for the Vulkan renderer.
+
+mod bsdf;
+mod medium;
+mod sampling;
+
+use crate::camera::CameraUniform;
+use crate::renderer::jitter;
+use crate::scene::{Aabb, Bvh, Ray, Scene};
+use bsdf::Bsdf;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use medium::{MediumData, MediumEvent};
+use sampling::{Rng, basis, henyey_greenstein, sample_aperture, sample_henyey_greenstein};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+// Same values as common.glsl and rgen.glsl
+const RAY_EPSILON: f32 = 0.0001;
+const T_FAR: f32 = 10000.0;
+const MAX_SEGMENTS: usize = 32;
+
+// Pixels per side of the tiles handed to the worker threads
+const TILE_SIZE: u32 = 16;
+
+// Instances a ray can hit, like the instance masks of the ray tracing pipeline
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    All,
+    Solid,
+    MediumBoundary,
+}
+
+struct Hit {
+    t: f32,
+    position: Vec3,
+    // Geometric normal, flipped to face the incoming ray
+    normal: Vec3,
+    // Instance index, volumes follow the scene instances
+    instance: usize,
+    front_face: bool,
+}
+
+// Möller-Trumbore without culling, the distance of a hit within [t_min, t_max]
+fn intersect_triangle(ray: &Ray, [a, b, c]: [Vec3; 3], t_min: f32, t_max: f32) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    (t_min..=t_max).contains(&t).then_some(t)
+}
+
+struct MeshData {
+    triangles: Vec<[Vec3; 3]>,
+    bvh: Bvh,
+}
+
+impl MeshData {
+    fn new(positions: &[[f32; 3]]) -> Self {
+        let triangles: Vec<[Vec3; 3]> = positions
+            .chunks_exact(3)
+            .map(|t| [Vec3::from(t[0]), Vec3::from(t[1]), Vec3::from(t[2])])
+            .collect();
+        let bounds: Vec<Aabb> = triangles.iter().map(|t| Aabb::from_points(*t)).collect();
+        Self {
+            bvh: Bvh::new(&bounds),
+            triangles,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.triangles.iter().flatten().fold(Aabb::EMPTY, |bounds, &p| bounds.union(&Aabb { min: p, max: p }))
+    }
+
+    // Closest triangle and its distance
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(usize, f32)> {
+        self.bvh.closest(ray, t_min, t_max, |triangle, t_max| {
+            intersect_triangle(ray, self.triangles[triangle], t_min, t_max)
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.bvh.any(ray, t_min, t_max, |triangle, t_max| {
+            intersect_triangle(ray, self.triangles[triangle], t_min, t_max).is_some()
+        })
+    }
+}
+
+pub struct CpuRenderer<'a> {
+    scene: &'a Scene,
+    meshes: Vec<MeshData>,
+    // Inverse instance transforms, moving instances invert theirs at the ray's time
+    world_to_object: Vec<Mat4>,
+    // Static instances in world space, moving ones are tested one by one at the ray's time
+    static_instances: Vec<usize>,
+    static_bvh: Bvh,
+    moving_instances: Vec<usize>,
+    media: Vec<MediumData<'a>>,
+    max_bounces: u32,
+}
+
+impl<'a> CpuRenderer<'a> {
+    pub fn new(scene: &'a Scene, max_bounces: u32) -> Self {
+        let meshes: Vec<MeshData> = scene.meshes.iter().map(|mesh| MeshData::new(&mesh.positions)).collect();
+        let world_to_object = scene.instances.iter().map(|instance| instance.transform.inverse()).collect();
+
+        let (moving_instances, static_instances): (Vec<usize>, Vec<usize>) =
+            (0..scene.instances.len()).partition(|&i| scene.instances[i].previous_transform.is_some());
+        let static_bounds: Vec<Aabb> = static_instances
+            .iter()
+            .map(|&i| {
+                let instance = &scene.instances[i];
+                meshes[instance.mesh].bounds().transformed(&instance.transform)
+            })
+            .collect();
+
+        Self {
+            scene,
+            static_bvh: Bvh::new(&static_bounds),
+            meshes,
+            world_to_object,
+            static_instances,
+            moving_instances,
+            media: scene.media.iter().map(MediumData::new).collect(),
+            max_bounces,
+        }
+    }
+
+    fn in_mask(&self, instance: usize, mask: Mask) -> bool {
+        let boundary = self.interior_medium(instance).is_some();
+        match mask {
+            Mask::All => true,
+            Mask::Solid => !boundary,
+            Mask::MediumBoundary => boundary,
+        }
+    }
+
+    fn interior_medium(&self, instance: usize) -> Option<usize> {
+        match self.scene.instances.get(instance) {
+            Some(instance) => instance.interior_medium,
+            None => Some(self.scene.volumes[instance - self.scene.instances.len()]),
+        }
+    }
+
+    fn world_to_object(&self, instance: usize, time: f32) -> Mat4 {
+        let instance_data = &self.scene.instances[instance];
+        if instance_data.previous_transform.is_some() {
+            instance_data.transform_at(time).inverse()
+        } else {
+            self.world_to_object[instance]
+        }
+    }
+
+    // Closest triangle of an instance in world space
+    fn intersect_instance(&self, instance: usize, ray: &Ray, t_max: f32, time: f32) -> Option<Hit> {
+        let world_to_object = self.world_to_object(instance, time);
+        let object_ray = Ray::new(
+            world_to_object.transform_point3(ray.origin),
+            world_to_object.transform_vector3(ray.direction),
+        );
+        let mesh = &self.meshes[self.scene.instances[instance].mesh];
+        let (triangle, t) = mesh.intersect(&object_ray, RAY_EPSILON, t_max)?;
+
+        // Counter-clockwise triangles face outwards
+        let [a, b, c] = mesh.triangles[triangle];
+        let normal = world_to_object.transpose().transform_vector3((b - a).cross(c - a)).normalize();
+        let front_face = normal.dot(ray.direction) < 0.0;
+        Some(Hit {
+            t,
+            position: ray.at(t),
+            normal: if front_face { normal } else { -normal },
+            instance,
+            front_face,
+        })
+    }
+
+    // Where the ray enters the box of a volume's density grid, or leaves it when starting inside
+    fn intersect_volume(&self, volume: usize, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let grid = self.scene.media[self.scene.volumes[volume]].density.as_ref()?;
+        let t0 = (grid.bounds_min - ray.origin) * ray.inv_direction;
+        let t1 = (grid.bounds_max - ray.origin) * ray.inv_direction;
+        let t_near = t0.min(t1).max_element();
+        let t_far = t0.max(t1).min_element();
+        if t_near > t_far {
+            return None;
+        }
+
+        let (t, front_face) = if t_near >= RAY_EPSILON {
+            (t_near, true)
+        } else if t_far >= RAY_EPSILON {
+            (t_far, false)
+        } else {
+            return None;
+        };
+        (t <= t_max).then(|| Hit {
+            t,
+            position: ray.at(t),
+            normal: -ray.direction,
+            instance: self.scene.instances.len() + volume,
+            front_face,
+        })
+    }
+
+    fn trace_closest(&self, ray: &Ray, t_max: f32, mask: Mask, time: f32) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        let consider = |hit: Option<Hit>, closest: &mut Option<Hit>| {
+            if let Some(hit) = hit
+                && closest.as_ref().is_none_or(|c| hit.t < c.t)
+            {
+                *closest = Some(hit);
+            }
+        };
+
+        self.static_bvh.closest(ray, RAY_EPSILON, t_max, |index, t_max| {
+            let instance = self.static_instances[index];
+            if !self.in_mask(instance, mask) {
+                return None;
+            }
+            let hit = self.intersect_instance(instance, ray, t_max, time)?;
+            let t = hit.t;
+            consider(Some(hit), &mut closest);
+            Some(t)
+        });
+
+        for &instance in &self.moving_instances {
+            if self.in_mask(instance, mask) {
+                let limit = closest.as_ref().map_or(t_max, |hit| hit.t);
+                consider(self.intersect_instance(instance, ray, limit, time), &mut closest);
+            }
+        }
+        if mask != Mask::Solid {
+            for volume in 0..self.scene.volumes.len() {
+                let limit = closest.as_ref().map_or(t_max, |hit| hit.t);
+                consider(self.intersect_volume(volume, ray, limit), &mut closest);
+            }
+        }
+        closest
+    }
+
+    // Whether solid geometry blocks the ray before t_max
+    fn occluded(&self, ray: &Ray, t_max: f32, time: f32) -> bool {
+        let hits_instance = |instance: usize| {
+            if !self.in_mask(instance, Mask::Solid) {
+                return false;
+            }
+            let world_to_object = self.world_to_object(instance, time);
+            let object_ray = Ray::new(
+                world_to_object.transform_point3(ray.origin),
+                world_to_object.transform_vector3(ray.direction),
+            );
+            self.meshes[self.scene.instances[instance].mesh].occluded(&object_ray, RAY_EPSILON, t_max)
+        };
+
+        self.static_bvh
+            .any(ray, RAY_EPSILON, t_max, |index, _| hits_instance(self.static_instances[index]))
+            || self.moving_instances.iter().any(|&instance| hits_instance(instance))
+    }
+
+    fn medium_after_crossing(&self, hit: &Hit, interior: Option<usize>) -> Option<usize> {
+        if hit.front_face { interior } else { self.scene.atmosphere }
+    }
+
+    // Visibility of a light through solid geometry and every medium along the way
+    fn transmittance(&self, origin: Vec3, direction: Vec3, distance: f32, mut medium: Option<usize>, time: f32, rng: &mut Rng) -> Vec3 {
+        if self.occluded(&Ray::new(origin, direction), distance, time) {
+            return Vec3::ZERO;
+        }
+
+        let mut result = Vec3::ONE;
+        let mut t = 0.0;
+        for _ in 0..MAX_SEGMENTS {
+            let segment_ray = Ray::new(origin + direction * t, direction);
+            let hit = self.trace_closest(&segment_ray, distance - t, Mask::MediumBoundary, time);
+            let segment = hit.as_ref().map_or(distance - t, |hit| hit.t);
+
+            if let Some(medium) = medium {
+                result *= self.media[medium].transmittance(&segment_ray, segment, rng);
+            }
+            let Some(hit) = hit.filter(|_| result.max_element() > 0.0) else {
+                break;
+            };
+
+            t += segment;
+            medium = self.medium_after_crossing(&hit, self.interior_medium(hit.instance));
+        }
+        result
+    }
+
+    // Reflected light, bsdf gives the BSDF times the cosine for a world space direction to a light
+    fn direct_light_surface(
+        &self,
+        position: Vec3,
+        bsdf: impl Fn(Vec3) -> Vec3,
+        medium: Option<usize>,
+        time: f32,
+        rng: &mut Rng,
+    ) -> Vec3 {
+        let mut result = Vec3::ZERO;
+        for light in &self.scene.lights {
+            let offset = light.position - position;
+            let distance = offset.length();
+            let to_light = offset / distance;
+
+            let f = bsdf(to_light);
+            if f.max_element() > 0.0 {
+                let radiance = light.color * light.intensity / (distance * distance);
+                result += radiance * f * self.transmittance(position, to_light, distance, medium, time, rng);
+            }
+        }
+        result
+    }
+
+    fn direct_light_medium(&self, position: Vec3, direction: Vec3, medium: usize, time: f32, rng: &mut Rng) -> Vec3 {
+        let g = self.media[medium].g();
+        let mut result = Vec3::ZERO;
+        for light in &self.scene.lights {
+            let offset = light.position - position;
+            let distance = offset.length();
+            let to_light = offset / distance;
+
+            let radiance = light.color * light.intensity / (distance * distance);
+            let phase = henyey_greenstein(direction.dot(to_light), g);
+            result += radiance * phase * self.transmittance(position, to_light, distance, Some(medium), time, rng);
+        }
+        result
+    }
+
+    // One path through the pixel, the radiance and alpha raygen adds to the accumulation
+    fn sample_pixel(&self, camera: &CameraMatrices, pixel: [u32; 2], size: Vec2, sample: u32) -> Vec4 {
+        let mut rng = Rng::new(pixel, sample);
+
+        let pixel_center = Vec2::new(pixel[0] as f32, pixel[1] as f32) + Vec2::from(jitter(sample));
+        let d = pixel_center / size * 2.0 - 1.0;
+        let target = camera.proj_inverse * Vec4::new(d.x, -d.y, 1.0, 1.0);
+        let mut lens_position = Vec3::ZERO;
+        let mut view_direction = target.truncate().normalize();
+
+        // Thin lens, every ray through the pixel converges on the focus plane
+        let [aperture_radius, focus_distance, blades, rotation] = camera.lens.to_array();
+        if aperture_radius > 0.0 {
+            let focus_point = view_direction * (focus_distance / -view_direction.z);
+            lens_position = (sample_aperture(rng.next2(), blades, rotation) * aperture_radius).extend(0.0);
+            view_direction = (focus_point - lens_position).normalize();
+        }
+
+        // Instances are placed at the exact time, the GPU draws a time slice from the second number
+        let time = camera.shutter.x + (camera.shutter.y - camera.shutter.x) * rng.next();
+        rng.next();
+
+        let origin = camera
+            .previous_view_inverse
+            .transform_point3(lens_position)
+            .lerp(camera.view_inverse.transform_point3(lens_position), time);
+        let direction = camera
+            .previous_view_inverse
+            .transform_vector3(view_direction)
+            .lerp(camera.view_inverse.transform_vector3(view_direction), time);
+
+        let mut ray = Ray::new(origin, direction.normalize());
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut alpha = 0.0;
+        let mut medium = self.scene.atmosphere;
+        let mut bounce = 0;
+
+        for _ in 0..MAX_SEGMENTS {
+            let hit = self.trace_closest(&ray, T_FAR, Mask::All, time);
+            let t_surface = hit.as_ref().map_or(T_FAR, |hit| hit.t);
+
+            if let Some(index) = medium {
+                let current = &self.media[index];
+                let (event, t_collision) = current.sample(&ray, t_surface, &mut rng, &mut throughput);
+                match event {
+                    MediumEvent::Absorb => {
+                        alpha = 1.0;
+                        radiance += throughput * current.emission(ray.at(t_collision));
+                        break;
+                    }
+                    MediumEvent::Scatter => {
+                        alpha = 1.0;
+                        let position = ray.at(t_collision);
+                        radiance += throughput * self.direct_light_medium(position, ray.direction, index, time, &mut rng);
+
+                        if bounce >= self.max_bounces {
+                            break;
+                        }
+                        let direction = sample_henyey_greenstein(ray.direction, current.g(), rng.next2());
+                        ray = Ray::new(position, direction);
+                        bounce += 1;
+                        continue;
+                    }
+                    MediumEvent::Pass => {}
+                }
+            }
+
+            let Some(hit) = hit else {
+                radiance += throughput * self.scene.environment;
+                break;
+            };
+
+            // Index matched medium boundary, continue on the other side without shading
+            if let Some(interior) = self.interior_medium(hit.instance) {
+                medium = self.medium_after_crossing(&hit, Some(interior));
+                ray = Ray::new(hit.position + ray.direction * RAY_EPSILON, ray.direction);
+                continue;
+            }
+
+            alpha = 1.0;
+
+            // Next event estimation towards every light, then continue in a direction drawn from
+            // the BSDF
+            let material = self.scene.instances[hit.instance].material;
+            let bsdf = Bsdf::new(&self.scene.materials[material]);
+            let frame = basis(hit.normal);
+            let wo = frame.transpose() * -ray.direction;
+            let position = hit.position + hit.normal * RAY_EPSILON;
+            let to_light_bsdf = |to_light| bsdf.eval(wo, frame.transpose() * to_light);
+            radiance += throughput * self.direct_light_surface(position, to_light_bsdf, medium, time, &mut rng);
+
+            if bounce >= self.max_bounces {
+                break;
+            }
+
+            let u_lobe = rng.next();
+            let Some((wi, weight)) = bsdf.sample(wo, u_lobe, rng.next2()) else {
+                break;
+            };
+            ray = Ray::new(position, frame * wi);
+            throughput *= weight;
+            bounce += 1;
+
+            if bounce > 2 {
+                let survival = throughput.max_element().min(0.95);
+                if rng.next() > survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        if !radiance.is_finite() {
+            radiance = Vec3::ZERO;
+        }
+        radiance.extend(alpha)
+    }
+
+    // Averages samples paths per pixel, seeded with the sample index like the GPU frames since
+    // startup. Returns linear Rec. 709 RGBA rows from the top left, like Renderer::read_radiance.
+    pub fn render(&self, camera: &CameraUniform, width: u32, height: u32, samples: u32) -> Vec<f32> {
+        let camera = CameraMatrices::new(camera);
+        let size = Vec2::new(width as f32, height as f32);
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tile_count = (tiles_x * height.div_ceil(TILE_SIZE)) as usize;
+        let next_tile = AtomicUsize::new(0);
+
+        let render_tile = |tile: usize| {
+            let x0 = (tile as u32 % tiles_x) * TILE_SIZE;
+            let y0 = (tile as u32 / tiles_x) * TILE_SIZE;
+            let mut pixels = Vec::new();
+            for y in y0..(y0 + TILE_SIZE).min(height) {
+                for x in x0..(x0 + TILE_SIZE).min(width) {
+                    let sum: Vec4 = (0..samples).map(|sample| self.sample_pixel(&camera, [x, y], size, sample)).sum();
+                    pixels.push((x, y, sum / samples.max(1) as f32));
+                }
+            }
+            pixels
+        };
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(tile_count.max(1));
+        let results: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut pixels = Vec::new();
+                        loop {
+                            let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if tile >= tile_count {
+                                break pixels;
+                            }
+                            pixels.extend(render_tile(tile));
+                        }
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().expect("CPU render thread panicked")).collect()
+        });
+
+        let mut image = vec![0.0; (width * height * 4) as usize];
+        for (x, y, color) in results {
+            let offset = ((y * width + x) * 4) as usize;
+            image[offset..offset + 4].copy_from_slice(&color.to_array());
+        }
+        image
+    }
+}
+
+// Camera uniforms as glam types
+struct CameraMatrices {
+    view_inverse: Mat4,
+    proj_inverse: Mat4,
+    lens: Vec4,
+    previous_view_inverse: Mat4,
+    shutter: Vec2,
+}
+
+impl CameraMatrices {
+    fn new(camera: &CameraUniform) -> Self {
+        Self {
+            view_inverse: Mat4::from_cols_array_2d(&camera.inv_view),
+            proj_inverse: Mat4::from_cols_array_2d(&camera.inv_proj),
+            lens: Vec4::from_array(camera.lens),
+            previous_view_inverse: Mat4::from_cols_array_2d(&camera.previous_inv_view),
+            shutter: Vec2::new(camera.shutter[0], camera.shutter[1]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::scene::{Instance, Material, Mesh, PointLight};
+    use std::f32::consts::PI;
+
+    // Diffuse wall in the z = 0 plane seen head-on by the default camera at (0, 2, 5)
+    fn wall_scene(light: Vec3) -> Scene {
+        let mut scene = Scene::default();
+        let mesh = scene.add_mesh(Mesh::cuboid(Vec3::new(-10.0, -10.0, -1.0), Vec3::new(10.0, 10.0, 0.0)));
+        let material = scene.add_material(Material::diffuse(Vec3::splat(0.5)));
+        scene.add_instance(Instance {
+            mesh,
+            transform: Mat4::IDENTITY,
+            previous_transform: None,
+            material,
+            interior_medium: None,
+        });
+        scene.lights.push(PointLight {
+            position: light,
+            color: Vec3::ONE,
+            intensity: 10.0,
+        });
+        scene
+    }
+
+    fn render_center(scene: &Scene, max_bounces: u32) -> Vec4 {
+        // Narrow enough that every jittered ray hits the wall at (0, 2, 0)
+        let camera = Camera::new(1, 1, 0.001);
+        let pixels = CpuRenderer::new(scene, max_bounces).render(&camera.get_ray_tracing_uniforms(), 1, 1, 16);
+        Vec4::from_slice(&pixels)
+    }
+
+    #[test]
+    fn direct_lighting_matches_analytic_radiance() {
+        // Light 3 units in front of the hit point, straight along the normal
+        let color = render_center(&wall_scene(Vec3::new(0.0, 2.0, 3.0)), 1);
+        // Base under the 4% Fresnel layer plus the reflection of the fully rough layer itself
+        let expected = (0.5 * 0.96 * 0.96 + 0.04 / 4.0) / PI * 10.0 / 9.0;
+        assert!((color.truncate() - Vec3::splat(expected)).abs().max_element() < 1e-3, "{color}");
+        assert_eq!(color.w, 1.0);
+
+        // Behind the wall the light is occluded
+        let color = render_center(&wall_scene(Vec3::new(0.0, 2.0, -3.0)), 1);
+        assert_eq!(color.truncate(), Vec3::ZERO);
+    }
+
+    #[test]
+    fn moving_instances_follow_the_shutter() {
+        // Wall moving out of view over the frame, the camera keeps its shutter of [0, 0]
+        let mut scene = wall_scene(Vec3::new(0.0, 2.0, 3.0));
+        scene.instances[0].previous_transform = Some(Mat4::IDENTITY);
+        scene.instances[0].transform = Mat4::from_translation(Vec3::new(0.0, 100.0, 0.0));
+        assert_eq!(render_center(&scene, 1).w, 1.0);
+
+        scene.instances[0].previous_transform = Some(scene.instances[0].transform);
+        assert_eq!(render_center(&scene, 1).w, 0.0);
+    }
+}
@@ -0,0 +1,107 @@
+// Random numbers and sampling routines, ports of common.glsl and rgen.glsl consuming random
+// numbers in the same order so both renderers draw the same sequence per pixel and sample
+
+use glam::{Mat3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+// PCG hash, see pcg in common.glsl
+fn pcg(state: &mut u32) -> u32 {
+    *state = state.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((*state >> ((*state >> 28) + 4)) ^ *state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(pixel: [u32; 2], frame: u32) -> Self {
+        let mut state = pixel[0]
+            .wrapping_mul(1973)
+            .wrapping_add(pixel[1].wrapping_mul(9277))
+            .wrapping_add(frame.wrapping_mul(26699));
+        pcg(&mut state);
+        Self(state)
+    }
+
+    // Like rand in common.glsl, can round up to 1
+    pub fn next(&mut self) -> f32 {
+        pcg(&mut self.0) as f32 * (1.0 / 4294967296.0)
+    }
+
+    pub fn next2(&mut self) -> Vec2 {
+        let x = self.next();
+        Vec2::new(x, self.next())
+    }
+}
+
+// Orthonormal basis with n as the z axis (Duff et al. 2017)
+pub fn basis(n: Vec3) -> Mat3 {
+    let s = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    Mat3::from_cols(
+        Vec3::new(1.0 + s * n.x * n.x * a, s * b, -s * n.x),
+        Vec3::new(b, s + n.y * n.y * a, -n.y),
+        n,
+    )
+}
+
+pub fn sample_cosine_hemisphere(u: Vec2) -> Vec3 {
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.x).max(0.0).sqrt())
+}
+
+// Henyey-Greenstein, cos_theta is measured between the propagation directions
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+pub fn sample_henyey_greenstein(direction: Vec3, g: f32, u: Vec2) -> Vec3 {
+    let cos_theta = if g.abs() < 0.001 {
+        1.0 - 2.0 * u.x
+    } else {
+        let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+        (1.0 + g * g - sq * sq) / (2.0 * g)
+    };
+
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    basis(direction) * Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// Point on the unit aperture, a disk or a regular polygon with the given number of blades
+pub fn sample_aperture(u: Vec2, blades: f32, rotation: f32) -> Vec2 {
+    if blades < 3.0 {
+        let r = u.x.sqrt();
+        let phi = 2.0 * PI * u.y;
+        return r * Vec2::new(phi.cos(), phi.sin());
+    }
+
+    // Pick one of the triangles fanning out from the center, then a point inside it
+    let sector = (u.x * blades).floor().min(blades - 1.0);
+    let v = u.x * blades - sector;
+    let a0 = rotation + 2.0 * PI * sector / blades;
+    let a1 = a0 + 2.0 * PI / blades;
+    let edge0 = Vec2::new(a0.cos(), a0.sin());
+    let edge1 = Vec2::new(a1.cos(), a1.sin());
+    v.sqrt() * edge0.lerp(edge1, u.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basis_is_orthonormal() {
+        for n in [Vec3::Z, Vec3::NEG_Z, Vec3::X, Vec3::new(0.3, -0.5, 0.8).normalize()] {
+            let m = basis(n);
+            assert!((m.x_axis.dot(m.y_axis)).abs() < 1e-5);
+            assert!((m.x_axis.dot(n)).abs() < 1e-5);
+            assert!((m.x_axis.length() - 1.0).abs() < 1e-5);
+            assert!((m.y_axis.length() - 1.0).abs() < 1e-5);
+            assert!(m.x_axis.cross(m.y_axis).dot(n) > 0.99);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// LDR-FLIP of Andersson et al. 2020, "FLIP: A Difference Evaluator for Alternating Images".
+// Follows the reference implementation for sRGB images viewed at 67 pixels per degree.
+
+use super::metrics::{Plane, kernel};
+use glam::{Mat3, Vec3};
+use std::f32::consts::PI;
+
+const PIXELS_PER_DEGREE: f32 = 67.0;
+
+// Exponents of the colour and feature differences and the colour error redistribution
+const QC: f32 = 0.7;
+const QF: f32 = 0.5;
+const PC: f32 = 0.4;
+const PT: f32 = 0.95;
+
+const LINEAR_TO_XYZ: Mat3 = Mat3::from_cols_array(&[
+    0.4124564, 0.2126729, 0.0193339, 0.3575761, 0.7151522, 0.119192, 0.1804375, 0.072175, 0.9503041,
+]);
+const XYZ_TO_LINEAR: Mat3 = Mat3::from_cols_array(&[
+    3.2404542, -0.969266, 0.0556434, -1.5371385, 1.8760108, -0.2040259, -0.4985314, 0.041556, 1.0572252,
+]);
+const D65: Vec3 = Vec3::new(0.9504285, 1.0, 1.0889004);
+
+pub(super) fn srgb_eotf(encoded: f32) -> f32 {
+    if encoded > 0.04045 {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    } else {
+        encoded / 12.92
+    }
+}
+
+fn linear_to_ycxcz(rgb: Vec3) -> Vec3 {
+    let xyz = LINEAR_TO_XYZ * rgb / D65;
+    Vec3::new(116.0 * xyz.y - 16.0, 500.0 * (xyz.x - xyz.y), 200.0 * (xyz.y - xyz.z))
+}
+
+fn ycxcz_to_linear(ycxcz: Vec3) -> Vec3 {
+    let y = (ycxcz.x + 16.0) / 116.0;
+    let xyz = Vec3::new(y + ycxcz.y / 500.0, y, y - ycxcz.z / 200.0) * D65;
+    XYZ_TO_LINEAR * xyz
+}
+
+// CIELAB with the lightness weighted chroma of the Hunt effect
+fn hunt_lab(rgb: Vec3) -> Vec3 {
+    const DELTA: f32 = 6.0 / 29.0;
+    let f = |t: f32| {
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let xyz = LINEAR_TO_XYZ * rgb / D65;
+    let l = 116.0 * f(xyz.y) - 16.0;
+    let a = 500.0 * (f(xyz.x) - f(xyz.y));
+    let b = 200.0 * (f(xyz.y) - f(xyz.z));
+    Vec3::new(l, 0.01 * l * a, 0.01 * l * b)
+}
+
+fn hyab(a: Vec3, b: Vec3) -> f32 {
+    let d = a - b;
+    d.x.abs() + (d.y * d.y + d.z * d.z).sqrt()
+}
+
+// Contrast sensitivity of the achromatic and both chromatic channels as sums of Gaussians
+fn csf_kernels() -> [Vec<f32>; 3] {
+    const PARAMETERS: [[f32; 4]; 3] = [[1.0, 0.0047, 0.0, 1e-5], [1.0, 0.0053, 0.0, 1e-5], [34.1, 0.04, 13.5, 0.025]];
+    let radius = (3.0 * (0.04 / (2.0 * PI * PI)).sqrt() * PIXELS_PER_DEGREE).ceil() as usize;
+    PARAMETERS.map(|[a1, b1, a2, b2]| {
+        kernel(radius, |x, y| {
+            let r2 = (x * x + y * y) / (PIXELS_PER_DEGREE * PIXELS_PER_DEGREE);
+            a1 * (PI / b1).sqrt() * (-PI * PI * r2 / b1).exp() + a2 * (PI / b2).sqrt() * (-PI * PI * r2 / b2).exp()
+        })
+    })
+}
+
+// First and second Gaussian derivative along x, positive and negative lobes each sum to one
+fn feature_kernels() -> (Vec<f32>, Vec<f32>) {
+    let sigma = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let radius = (3.0 * sigma).ceil() as isize;
+    let offsets = || (-radius..=radius).flat_map(|y| (-radius..=radius).map(move |x| (x as f32, y as f32)));
+    let gaussian = |x: f32, y: f32| (-(x * x + y * y) / (2.0 * sigma * sigma)).exp();
+
+    let normalize = |mut kernel: Vec<f32>| {
+        let positive: f32 = kernel.iter().filter(|&&k| k > 0.0).sum();
+        let negative: f32 = -kernel.iter().filter(|&&k| k < 0.0).sum::<f32>();
+        for k in &mut kernel {
+            *k /= if *k > 0.0 { positive } else { negative };
+        }
+        kernel
+    };
+    let edge = normalize(offsets().map(|(x, y)| -x * gaussian(x, y)).collect());
+    let point = normalize(offsets().map(|(x, y)| (x * x / (sigma * sigma) - 1.0) * gaussian(x, y)).collect());
+    (edge, point)
+}
+
+fn transpose(kernel: &[f32]) -> Vec<f32> {
+    let size = (kernel.len() as f32).sqrt() as usize;
+    (0..kernel.len()).map(|i| kernel[(i % size) * size + i / size]).collect()
+}
+
+// Gradient magnitude of the edge and point detectors
+fn features(luminance: &Plane, edge: &[f32], point: &[f32]) -> (Plane, Plane) {
+    let magnitude = |kernel: &[f32]| {
+        let dx = luminance.convolve(kernel);
+        let dy = luminance.convolve(&transpose(kernel));
+        dx.map2(&dy, |x, y| x.hypot(y))
+    };
+    (magnitude(edge), magnitude(point))
+}
+
+// Per pixel error in [0, 1] between two sRGB encoded images given as RGB planes
+pub fn error_map(reference: &[Plane; 3], test: &[Plane; 3]) -> Plane {
+    let (width, height) = (reference[0].width, reference[0].height);
+    let linear = |planes: &[Plane; 3], i: usize| Vec3::from_array([0, 1, 2].map(|c| srgb_eotf(planes[c].values[i])));
+
+    // Colour pipeline, the opponent channels are filtered by the contrast sensitivity
+    let kernels = csf_kernels();
+    let filtered = |planes: &[Plane; 3]| {
+        let ycxcz: Vec<Vec3> = (0..width * height).map(|i| linear_to_ycxcz(linear(planes, i))).collect();
+        let channels = [0, 1, 2].map(|c| Plane::from_fn(width, height, |i| ycxcz[i][c]).convolve(&kernels[c]));
+        (0..width * height)
+            .map(|i| {
+                let rgb = ycxcz_to_linear(Vec3::from_array([0, 1, 2].map(|c| channels[c].values[i])));
+                hunt_lab(rgb.clamp(Vec3::ZERO, Vec3::ONE))
+            })
+            .collect::<Vec<_>>()
+    };
+    let reference_lab = filtered(reference);
+    let test_lab = filtered(test);
+
+    let max_error = hyab(hunt_lab(Vec3::Y), hunt_lab(Vec3::Z)).powf(QC);
+    let color_error = |i: usize| {
+        let error = hyab(reference_lab[i], test_lab[i]).powf(QC);
+        if error < PC * max_error {
+            PT / (PC * max_error) * error
+        } else {
+            PT + (error - PC * max_error) / (max_error - PC * max_error) * (1.0 - PT)
+        }
+    };
+
+    // Feature pipeline on the normalized luminance
+    let (edge, point) = feature_kernels();
+    let luminance = |planes: &[Plane; 3]| Plane::from_fn(width, height, |i| (LINEAR_TO_XYZ * linear(planes, i)).y);
+    let (reference_edges, reference_points) = features(&luminance(reference), &edge, &point);
+    let (test_edges, test_points) = features(&luminance(test), &edge, &point);
+
+    Plane::from_fn(width, height, |i| {
+        let edge_difference = (reference_edges.values[i] - test_edges.values[i]).abs();
+        let point_difference = (reference_points.values[i] - test_points.values[i]).abs();
+        let feature_error = (edge_difference.max(point_difference) / 2.0f32.sqrt()).powf(QF);
+        color_error(i).powf(1.0 - feature_error)
+    })
+}
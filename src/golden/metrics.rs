@@ -0,0 +1,182 @@
+// Image difference metrics. RMSE compares linear radiance, the others compare what a viewer
+// sees after a fixed Reinhard tonemap, so differences in bright highlights do not dominate.
+
+use super::flip;
+use crate::screenshot::srgb_oetf;
+
+// Single channel image
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+}
+
+impl Plane {
+    pub fn from_fn(width: usize, height: usize, f: impl Fn(usize) -> f32) -> Self {
+        Self {
+            width,
+            height,
+            values: (0..width * height).map(f).collect(),
+        }
+    }
+
+    pub fn map2(&self, other: &Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        Self::from_fn(self.width, self.height, |i| f(self.values[i], other.values[i]))
+    }
+
+    // Clamps to the edge, kernel is square with an odd side
+    pub fn convolve(&self, kernel: &[f32]) -> Self {
+        let size = (kernel.len() as f32).sqrt() as usize;
+        let radius = (size / 2) as isize;
+        let (width, height) = (self.width as isize, self.height as isize);
+        Self::from_fn(self.width, self.height, |i| {
+            let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+            let mut sum = 0.0;
+            for ky in 0..size as isize {
+                let sy = (y + ky - radius).clamp(0, height - 1);
+                for kx in 0..size as isize {
+                    let sx = (x + kx - radius).clamp(0, width - 1);
+                    sum += kernel[(ky * size as isize + kx) as usize] * self.values[(sy * width + sx) as usize];
+                }
+            }
+            sum
+        })
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.values.iter().sum::<f32>() / self.values.len() as f32
+    }
+}
+
+// Square kernel of the given radius from a function of the offset, normalized to sum to one
+pub fn kernel(radius: usize, f: impl Fn(f32, f32) -> f32) -> Vec<f32> {
+    let size = 2 * radius + 1;
+    let mut kernel: Vec<f32> = (0..size * size)
+        .map(|i| f((i % size) as f32 - radius as f32, (i / size) as f32 - radius as f32))
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= sum);
+    kernel
+}
+
+// Radiance tonemapped to [0, 1], still linear
+pub fn display_linear(radiance: f32) -> f32 {
+    let radiance = radiance.max(0.0);
+    radiance / (1.0 + radiance)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Metrics {
+    pub rmse: f32,
+    // In dB of the tonemapped sRGB image
+    pub psnr: f32,
+    pub ssim: f32,
+    // Mean LDR-FLIP error
+    pub flip: f32,
+}
+
+pub struct Comparison {
+    pub metrics: Metrics,
+    // Per pixel FLIP error, written as the diff image
+    pub error_map: Plane,
+}
+
+// Compares RGBA images of equal size, alpha is ignored
+pub fn compare(reference: &[f32], test: &[f32], width: usize, height: usize) -> Comparison {
+    assert_eq!(reference.len(), test.len(), "images differ in size");
+
+    let squared_error: f32 = reference
+        .chunks_exact(4)
+        .zip(test.chunks_exact(4))
+        .flat_map(|(r, t)| (0..3).map(move |c| (r[c] - t[c]).powi(2)))
+        .sum();
+    let rmse = (squared_error / (width * height * 3) as f32).sqrt();
+
+    let encoded = |image: &[f32], channel: usize| {
+        Plane::from_fn(width, height, |i| srgb_oetf(display_linear(image[i * 4 + channel])))
+    };
+    let reference_display = [0, 1, 2].map(|c| encoded(reference, c));
+    let test_display = [0, 1, 2].map(|c| encoded(test, c));
+
+    let mse = (0..3)
+        .map(|c| reference_display[c].map2(&test_display[c], |r, t| (r - t).powi(2)).mean())
+        .sum::<f32>()
+        / 3.0;
+    let psnr = if mse > 0.0 { -10.0 * mse.log10() } else { f32::INFINITY };
+
+    let luma = |rgb: &[Plane; 3]| {
+        Plane::from_fn(width, height, |i| {
+            0.2126 * rgb[0].values[i] + 0.7152 * rgb[1].values[i] + 0.0722 * rgb[2].values[i]
+        })
+    };
+    let ssim = ssim(&luma(&reference_display), &luma(&test_display));
+
+    let error_map = flip::error_map(&reference_display, &test_display);
+    Comparison {
+        metrics: Metrics {
+            rmse,
+            psnr,
+            ssim,
+            flip: error_map.mean(),
+        },
+        error_map,
+    }
+}
+
+// Mean structural similarity of Wang et al. 2004 with the usual 11x11 Gaussian window
+fn ssim(reference: &Plane, test: &Plane) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let window = kernel(5, |x, y| (-(x * x + y * y) / (2.0 * 1.5 * 1.5)).exp());
+    let mean_r = reference.convolve(&window);
+    let mean_t = test.convolve(&window);
+    let var_r = reference.map2(reference, |a, b| a * b).convolve(&window);
+    let var_t = test.map2(test, |a, b| a * b).convolve(&window);
+    let covariance = reference.map2(test, |a, b| a * b).convolve(&window);
+
+    let ssim = Plane::from_fn(reference.width, reference.height, |i| {
+        let (mr, mt) = (mean_r.values[i], mean_t.values[i]);
+        let sr = var_r.values[i] - mr * mr;
+        let st = var_t.values[i] - mt * mt;
+        let srt = covariance.values[i] - mr * mt;
+        ((2.0 * mr * mt + C1) * (2.0 * srt + C2)) / ((mr * mr + mt * mt + C1) * (sr + st + C2))
+    });
+    ssim.mean()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize, scale: f32) -> Vec<f32> {
+        (0..width * height)
+            .flat_map(|i| {
+                let v = ((i % width) as f32 / width as f32 + (i / width) as f32 / height as f32) * scale;
+                [v, v * 0.5, 1.0 - v, 1.0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_images_match_perfectly() {
+        let image = gradient(24, 16, 1.0);
+        let metrics = compare(&image, &image, 24, 16).metrics;
+        assert_eq!(metrics.rmse, 0.0);
+        assert_eq!(metrics.psnr, f32::INFINITY);
+        assert!((metrics.ssim - 1.0).abs() < 1e-4);
+        assert_eq!(metrics.flip, 0.0);
+    }
+
+    #[test]
+    fn metrics_grow_with_the_difference() {
+        let reference = gradient(24, 16, 1.0);
+        let small = compare(&reference, &gradient(24, 16, 1.05), 24, 16).metrics;
+        let large = compare(&reference, &gradient(24, 16, 1.5), 24, 16).metrics;
+        assert!(small.rmse < large.rmse);
+        assert!(small.psnr > large.psnr);
+        assert!(small.ssim >= large.ssim);
+        assert!(small.flip < large.flip);
+        assert!(large.flip <= 1.0);
+    }
+}
@@ -0,0 +1,195 @@
+// Golden image regression tests. Bundled scenes are rendered at a fixed sample count, where the
+// sample index seeds every path, and compared against the references in tests/golden.
+// Renders on the first Vulkan device, which may be a software driver like lavapipe, and on the
+// CPU reference renderer when there is none. Failed comparisons leave the render and a FLIP
+// heatmap in target/golden. Run with UPDATE_GOLDEN=1 to write new references instead.
+
+mod flip;
+mod metrics;
+mod scenes;
+
+use crate::cli::{Cli, Mode};
+use crate::headless;
+use crate::renderer::create_device;
+use crate::scene::{Preset, Scene};
+use crate::screenshot::{OutputFormat, Screenshot};
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use glam::Vec3;
+use metrics::{Metrics, compare};
+use std::path::{Path, PathBuf};
+use vulkano::VulkanLibrary;
+use vulkano::instance::{Instance, InstanceCreateInfo};
+
+const SIZE: &str = "40x40";
+const SAMPLES: &str = "128";
+const MAX_BOUNCES: &str = "4";
+
+// Limits a render has to stay within, set between the difference of two renders with
+// independent noise and one with a 10% brighter light. FLIP separates them best.
+struct Thresholds {
+    max_rmse: f32,
+    min_psnr: f32,
+    min_ssim: f32,
+    max_flip: f32,
+}
+
+impl Thresholds {
+    fn accept(&self, metrics: &Metrics) -> bool {
+        metrics.rmse <= self.max_rmse
+            && metrics.psnr >= self.min_psnr
+            && metrics.ssim >= self.min_ssim
+            && metrics.flip <= self.max_flip
+    }
+}
+
+fn vulkan_available() -> bool {
+    let Ok(library) = VulkanLibrary::new() else {
+        return false;
+    };
+    Instance::new(library, InstanceCreateInfo::default())
+        .is_ok_and(|instance| create_device(&instance, None, None, None).is_ok())
+}
+
+// Same path as a headless render from the command line
+fn render(scene: Scene) -> Result<Screenshot> {
+    let mut args = vec![
+        "vulkano_pathtracer",
+        "--headless",
+        "--size",
+        SIZE,
+        "--spp",
+        SAMPLES,
+        "--max-bounces",
+        MAX_BOUNCES,
+        "-o",
+        "golden.exr",
+    ];
+    if !vulkan_available() {
+        println!("No Vulkan device, rendering on the CPU reference renderer");
+        args.push("--cpu");
+    }
+
+    let config = Cli::try_parse_from(args)?.validate()?;
+    let Mode::Headless(options) = &config.mode else {
+        unreachable!("golden renders are headless");
+    };
+    headless::render_scene(&config, options, scene)
+}
+
+fn load_exr(path: &Path) -> Result<Screenshot> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![0.0; resolution.width() * resolution.height() * 4]),
+        |(width, pixels): &mut (usize, Vec<f32>), position, (r, g, b, a): (f32, f32, f32, f32)| {
+            let offset = (position.y() * *width + position.x()) * 4;
+            pixels[offset..offset + 4].copy_from_slice(&[r, g, b, a]);
+        },
+    )
+    .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let size = image.layer_data.size;
+    Ok(Screenshot {
+        width: size.width() as u32,
+        height: size.height() as u32,
+        pixels: image.layer_data.channel_data.pixels.1,
+        channels: Vec::new(),
+        metadata: Vec::new(),
+    })
+}
+
+// Magma colour map, sRGB encoded control points from no to maximum error
+fn heatmap(error: f32) -> Vec3 {
+    const MAGMA: [Vec3; 5] = [
+        Vec3::new(0.001, 0.0, 0.014),
+        Vec3::new(0.316, 0.071, 0.485),
+        Vec3::new(0.716, 0.215, 0.475),
+        Vec3::new(0.987, 0.536, 0.382),
+        Vec3::new(0.987, 0.991, 0.75),
+    ];
+    let x = error.clamp(0.0, 1.0) * (MAGMA.len() - 1) as f32;
+    let i = (x as usize).min(MAGMA.len() - 2);
+    MAGMA[i].lerp(MAGMA[i + 1], x - i as f32)
+}
+
+fn check(name: &str, scene: Scene, thresholds: Thresholds) -> Result<()> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference_path = root.join("tests/golden").join(name).with_extension("exr");
+    let screenshot = render(scene)?;
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(root.join("tests/golden")).context("Failed to create tests/golden")?;
+        screenshot.save(&reference_path, OutputFormat::Exr)?;
+        println!("Updated {}", reference_path.display());
+        return Ok(());
+    }
+
+    let reference = load_exr(&reference_path).context("Missing reference, run with UPDATE_GOLDEN=1 to create it")?;
+    if (reference.width, reference.height) != (screenshot.width, screenshot.height) {
+        bail!(
+            "{name} is {}x{}, the reference {}x{}",
+            screenshot.width,
+            screenshot.height,
+            reference.width,
+            reference.height
+        );
+    }
+
+    let (width, height) = (screenshot.width as usize, screenshot.height as usize);
+    let comparison = compare(&reference.pixels, &screenshot.pixels, width, height);
+    let metrics = comparison.metrics;
+    println!("{name}: {metrics:?}");
+    if thresholds.accept(&metrics) {
+        return Ok(());
+    }
+
+    let output: PathBuf = root.join("target/golden");
+    std::fs::create_dir_all(&output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let render_path = output.join(name).with_extension("exr");
+    let diff_path = output.join(format!("{name}_flip.png"));
+    screenshot.save(&render_path, OutputFormat::Exr)?;
+
+    let diff = Screenshot {
+        pixels: comparison
+            .error_map
+            .values
+            .iter()
+            .flat_map(|&error| {
+                let color = heatmap(error).to_array().map(flip::srgb_eotf);
+                [color[0], color[1], color[2], 1.0]
+            })
+            .collect(),
+        channels: Vec::new(),
+        metadata: vec![("Mean FLIP".to_owned(), metrics.flip.to_string())],
+        ..screenshot
+    };
+    diff.save(&diff_path, OutputFormat::Png)?;
+
+    bail!(
+        "{name} differs from its reference with {metrics:?}, see {} and {}",
+        render_path.display(),
+        diff_path.display()
+    )
+}
+
+#[test]
+fn cornell_box() {
+    let thresholds = Thresholds {
+        max_rmse: 0.04,
+        min_psnr: 30.0,
+        min_ssim: 0.95,
+        max_flip: 0.05,
+    };
+    check("cornell_box", Preset::CornellBox.build(), thresholds).unwrap();
+}
+
+#[test]
+fn material_spheres() {
+    let thresholds = Thresholds {
+        max_rmse: 0.015,
+        min_psnr: 35.0,
+        min_ssim: 0.9,
+        max_flip: 0.04,
+    };
+    check("material_spheres", scenes::material_spheres(), thresholds).unwrap();
+}
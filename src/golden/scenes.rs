@@ -0,0 +1,72 @@
+// Reference scenes of the golden image tests besides the presets, framed for the default camera
+// at (0, 2, 5)
+
+use crate::scene::{Instance, Material, Medium, Mesh, PointLight, Scene};
+use glam::{Mat4, Vec3};
+
+// Columns of spheres from smooth to rough with dark, bright and coloured bases, dielectric in the
+// bottom row, half metallic in the middle one and metallic on top, with a sphere of fog at the end
+pub fn material_spheres() -> Scene {
+    let mut scene = Scene::default();
+    let floor = scene.add_material(Material::default());
+    scene.add_static(
+        Mesh::new(
+            [
+                Mesh::quad([[-10.0, 0.0, -4.0], [-10.0, 0.0, 10.0], [10.0, 0.0, 10.0], [10.0, 0.0, -4.0]]).positions,
+                Mesh::quad([[-10.0, 0.0, -4.0], [10.0, 0.0, -4.0], [10.0, 10.0, -4.0], [-10.0, 10.0, -4.0]]).positions,
+            ]
+            .concat(),
+        ),
+        Mat4::IDENTITY,
+        floor,
+    );
+
+    let colors = [
+        Vec3::splat(0.05),
+        Vec3::splat(0.4),
+        Vec3::splat(0.9),
+        Vec3::new(0.8, 0.1, 0.1),
+        Vec3::new(0.1, 0.2, 0.8),
+    ];
+    let sphere = scene.add_mesh(Mesh::sphere(Vec3::ZERO, 0.4, 12, 24));
+    for row in 0..3 {
+        for (column, base_color) in colors.into_iter().enumerate() {
+            let material = scene.add_material(Material {
+                base_color,
+                roughness: column as f32 / 4.0,
+                metallic: row as f32 / 2.0,
+            });
+            scene.add_instance(Instance {
+                mesh: sphere,
+                transform: Mat4::from_translation(Vec3::new((column as f32 - 2.5) * 1.1, 0.4 + row as f32 * 0.85, 0.0)),
+                previous_transform: None,
+                material,
+                interior_medium: None,
+            });
+        }
+    }
+
+    let fog = scene.add_medium(Medium::new(Vec3::splat(0.2), Vec3::splat(3.0), 0.5, None));
+    let mesh = scene.add_mesh(Mesh::sphere(Vec3::new(2.5 * 1.1, 0.5, 0.0), 0.5, 12, 24));
+    scene.add_instance(Instance {
+        mesh,
+        transform: Mat4::IDENTITY,
+        previous_transform: None,
+        material: floor,
+        interior_medium: Some(fog),
+    });
+
+    scene.lights = vec![
+        PointLight {
+            position: Vec3::new(-3.0, 3.0, 2.0),
+            color: Vec3::ONE,
+            intensity: 20.0,
+        },
+        PointLight {
+            position: Vec3::new(3.0, 1.5, 2.5),
+            color: Vec3::new(1.0, 0.7, 0.4),
+            intensity: 6.0,
+        },
+    ];
+    scene
+}
@@ -0,0 +1,185 @@
+// Offline rendering without a window or swapchain, for render farms and CI.
+// Accumulates up to a fixed number of samples into an offscreen image and writes it to disk,
+// stopping early once adaptive sampling converged or the time budget ran out.
+
+use crate::camera::Camera;
+use crate::cli::{Config, HeadlessOptions};
+use crate::cpu::CpuRenderer;
+use crate::renderer::{RenderSettings, Renderer, create_device};
+use crate::scene::Scene;
+use crate::screenshot::Screenshot;
+use anyhow::{Context, Result};
+use std::time::Instant;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::instance::{Instance, InstanceCreateInfo};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::sync::{GpuFuture, now};
+use vulkano::VulkanLibrary;
+
+#[cfg(feature = "oidn")]
+use crate::oidn::denoise as oidn_denoise;
+
+#[cfg(not(feature = "oidn"))]
+fn oidn_denoise(_: &mut [f32], _: &[f32], _: &[f32], _: u32, _: u32, _: bool) -> Result<()> {
+    anyhow::bail!("Open Image Denoise support is not compiled in, rebuild with --features oidn")
+}
+
+pub fn render(config: &Config, options: &HeadlessOptions) -> Result<()> {
+    let screenshot = render_scene(config, options, config.load_scene()?)?;
+    screenshot.save(&options.output, options.format)?;
+    println!("Saved {}", options.output.display());
+
+    Ok(())
+}
+
+// Renders a scene to an image in the output format, without writing it
+pub fn render_scene(config: &Config, options: &HeadlessOptions, scene: Scene) -> Result<Screenshot> {
+    if options.cpu {
+        return render_cpu(config, options, &scene);
+    }
+
+    let vulkan_library = VulkanLibrary::new().context("Failed to load Vulkan library")?;
+    let instance = Instance::new(vulkan_library, InstanceCreateInfo::default())
+        .context("Failed to create Vulkan Instance")?;
+
+    let (device, queue) = create_device(&instance, None, config.device.as_ref(), config.backend)?;
+
+    let camera = Camera::new(config.width, config.height, 70.0_f32.to_radians());
+    let extent = [config.width, config.height, 1];
+
+    let mut renderer = Renderer::new(
+        device.clone(),
+        queue.clone(),
+        scene,
+        config.integrator,
+        // Open Image Denoise is guided by the albedo and normal AOVs
+        RenderSettings {
+            aovs: config.render.aovs || options.oidn,
+            ..config.render
+        },
+        camera.get_ray_tracing_uniforms(),
+        extent,
+    )?;
+    println!(
+        "Rendering on {} with the {:?} backend",
+        device.physical_device().properties().device_name,
+        renderer.backend()
+    );
+    // Samples arrive much faster than display frames, expose for the current image right away
+    renderer.set_exposure_adaptation_rate(f32::INFINITY);
+
+    // Target of the per-frame post-process pass, the saved image is read back separately
+    let output = ImageView::new_default(
+        Image::new(
+            renderer.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create output image")?,
+    )
+    .context("Failed to create image view for output image")?;
+
+    let start = Instant::now();
+    for sample in 0..options.samples {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            renderer.command_buffer_allocator.clone(),
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .context("Failed to create command buffer builder")?;
+
+        renderer.record(&mut builder, output.clone())?;
+
+        let command_buffer = builder.build().context("Failed to build command buffer")?;
+        now(device.clone())
+            .then_execute(queue.clone(), command_buffer)
+            .context("Failed to execute command buffer")?
+            .then_signal_fence_and_flush()
+            .context("Failed to signal fence and flush")?
+            .wait(None)
+            .context("Failed to wait for future")?;
+
+        if (sample + 1) % 64 == 0 || sample + 1 == options.samples {
+            println!("{}/{} samples", sample + 1, options.samples);
+        }
+
+        if let Some(adaptive) = config.render.adaptive
+            && sample + 1 >= adaptive.min_samples
+            && renderer.active_tiles()? == 0
+        {
+            println!("Converged after {} samples", sample + 1);
+            break;
+        }
+        if options.time_budget.is_some_and(|budget| start.elapsed() >= budget) {
+            println!("Time budget reached after {} samples", sample + 1);
+            break;
+        }
+    }
+    println!("Rendered in {:.2}s", start.elapsed().as_secs_f32());
+
+    let mut screenshot = renderer.capture(options.format)?;
+    if options.oidn {
+        let (albedo, normal) = renderer.read_denoiser_guides()?;
+        let start = Instant::now();
+        oidn_denoise(
+            &mut screenshot.pixels,
+            &albedo,
+            &normal,
+            screenshot.width,
+            screenshot.height,
+            options.format.is_hdr(),
+        )?;
+        println!("Denoised in {:.2}s", start.elapsed().as_secs_f32());
+    }
+    // Only AOVs that were asked for become EXR layers, the sample counts come with adaptive sampling
+    if !config.render.aovs {
+        screenshot
+            .channels
+            .retain(|channel| config.render.adaptive.is_some() && channel.name.starts_with("samples."));
+    }
+    screenshot.metadata.extend(camera.metadata());
+
+    Ok(screenshot)
+}
+
+// Ground truth on the CPU reference path tracer, needs no Vulkan device
+fn render_cpu(config: &Config, options: &HeadlessOptions, scene: &Scene) -> Result<Screenshot> {
+    let camera = Camera::new(config.width, config.height, 70.0_f32.to_radians());
+    let renderer = CpuRenderer::new(scene, config.render.max_bounces);
+    println!("Rendering on the CPU reference renderer");
+
+    let start = Instant::now();
+    let pixels = renderer.render(
+        &camera.get_ray_tracing_uniforms(),
+        config.width,
+        config.height,
+        options.samples,
+    );
+    println!("Rendered in {:.2}s", start.elapsed().as_secs_f32());
+
+    let mut metadata = vec![
+        ("Renderer".to_owned(), "CPU reference".to_owned()),
+        ("Samples per pixel".to_owned(), options.samples.to_string()),
+        ("Max bounces".to_owned(), config.render.max_bounces.to_string()),
+    ];
+    metadata.extend(camera.metadata());
+    Ok(Screenshot {
+        width: config.width,
+        height: config.height,
+        pixels,
+        channels: Vec::new(),
+        metadata,
+    })
+}
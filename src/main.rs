@@ -1,56 +1,22 @@
-mod rgen {
-    vulkano_shaders::shader! {
-        ty: "raygen",
-        path: "src/shaders/rgen.glsl",
-        vulkan_version: "1.3",
-    }
-}
-
-mod rchit {
-    vulkano_shaders::shader! {
-        ty: "closesthit",
-        path: "src/shaders/rchit.glsl",
-        vulkan_version: "1.3",
-    }
-}
-
-mod rmiss {
-    vulkano_shaders::shader! {
-        ty: "miss",
-        path: "src/shaders/rmiss.glsl",
-        vulkan_version: "1.3",
-    }
-}
-
-mod srmiss {
-    vulkano_shaders::shader! {
-        ty: "miss",
-        path: "src/shaders/srmiss.glsl",
-        vulkan_version: "1.3",
-    }
-}
-
 use anyhow::{Context, Error, Result};
-use bytemuck::{Pod, Zeroable};
-use glam::{Vec3};
+use glam::Vec2;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use vulkano::acceleration_structure::{
-    AccelerationStructure, AccelerationStructureBuildGeometryInfo,
+    AabbPositions, AccelerationStructure, AccelerationStructureBuildGeometryInfo,
     AccelerationStructureBuildRangeInfo, AccelerationStructureBuildType,
     AccelerationStructureCreateInfo, AccelerationStructureGeometries,
-    AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryInstancesDataType,
+    AccelerationStructureGeometryAabbsData, AccelerationStructureGeometryInstancesData,
+    AccelerationStructureGeometryInstancesDataType,
     AccelerationStructureGeometryTrianglesData, AccelerationStructureInstance,
     AccelerationStructureType, BuildAccelerationStructureFlags, BuildAccelerationStructureMode,
 };
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, BlitImageInfo, CommandBufferUsage, ImageBlit,
+    AutoCommandBufferBuilder, BlitImageInfo, CommandBufferExecFuture, CommandBufferUsage, ImageBlit,
 };
-use vulkano::descriptor_set::allocator::{StandardDescriptorSetAllocator};
-use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
-use vulkano::device::DeviceFeatures;
 use vulkano::format::Format;
 use vulkano::image::sampler::Filter;
 use vulkano::image::view::ImageView;
@@ -60,31 +26,24 @@ use vulkano::image::{
 use vulkano::memory::allocator::{
     AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator,
 };
-use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
-use vulkano::pipeline::ray_tracing::{
-    RayTracingPipeline, RayTracingPipelineCreateInfo, RayTracingShaderGroupCreateInfo,
-    ShaderBindingTable,
+use vulkano::swapchain::{
+    ColorSpace, CompositeAlpha, PresentFuture, SwapchainAcquireFuture, SwapchainPresentInfo,
 };
-use vulkano::pipeline::{
-    Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
-};
-use vulkano::swapchain::{CompositeAlpha, SwapchainPresentInfo};
+use vulkano::sync::future::{FenceSignalFuture, JoinFuture};
 use vulkano::{Validated, VulkanError, swapchain};
 use vulkano::{
     VulkanLibrary,
     buffer::BufferContents,
-    device::{
-        Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
-        physical::PhysicalDeviceType,
-    },
+    device::{Device, Queue},
     image::ImageUsage,
     instance::{Instance, InstanceExtensions},
     pipeline::graphics::vertex_input::Vertex,
     swapchain::{Surface, Swapchain, SwapchainCreateInfo},
     sync::{GpuFuture, now},
 };
+use winit::dpi::PhysicalPosition;
 use winit::event::{DeviceEvent, ElementState, KeyEvent, MouseScrollDelta};
-use winit::keyboard::{PhysicalKey};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
 use winit::{
     application::ApplicationHandler,
     event::WindowEvent,
@@ -92,9 +51,30 @@ use winit::{
     window::{Window, WindowId},
 };
 
-use crate::camera::{Camera, CameraController, CameraUniform};
+use crate::camera::{Camera, CameraController};
+use crate::cli::{Cli, Config, Mode, PresentMode, Upscaler};
+use crate::renderer::{DisplayMode, Integrator, OutputEncoding, Renderer, WorkingSpace, create_device, print_devices};
+use crate::overlay::Overlay;
+use crate::resolution::DynamicResolution;
+use crate::screenshot::OutputFormat;
+use crate::stats::{FrameInfo, Stats};
+use clap::Parser;
+use winit::dpi::PhysicalSize;
 
 mod camera;
+mod cli;
+mod cpu;
+#[cfg(test)]
+mod golden;
+mod headless;
+#[cfg(feature = "oidn")]
+mod oidn;
+mod overlay;
+mod renderer;
+mod resolution;
+mod scene;
+mod screenshot;
+mod stats;
 
 #[derive(BufferContents, Vertex)]
 #[repr(C)]
@@ -104,49 +84,104 @@ struct MyVertex {
 }
 
 
-const RAY_RECURSION_DEPTH: u32 = 4;
-
-#[repr(C)]
-#[derive(Copy, Clone, Pod, Zeroable)]
-pub struct PushConstants {
-    max_ray_recursion_depth: u32,
-    time: f32,
-}
+// Signalled once a frame was presented, the renderer's resources of its slot are free again
+type FrameFence =
+    FenceSignalFuture<PresentFuture<CommandBufferExecFuture<JoinFuture<Box<dyn GpuFuture + Send + Sync>, SwapchainAcquireFuture>>>>;
 
+// Presents the renderer output in a window and drives the camera from input events
 struct GraphicsState {
     //instance: Arc<Instance>,
     window: Arc<Window>,
     //surface: Arc<Surface>,
-    device: Arc<Device>,
-    queue: Arc<Queue>,
     swapchain: Arc<Swapchain>,
     swapchain_images: Vec<Arc<Image>>,
     storage_images: Vec<Arc<ImageView>>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    memory_allocator: Arc<StandardMemoryAllocator>,
+    // Window sized targets of the edge-aware upscaler, empty with the bilinear one
+    upscaled_images: Vec<Arc<ImageView>>,
+    upscaler: Upscaler,
+    renderer: Renderer,
     camera: Camera,
-    raytracing_pipeline: Arc<RayTracingPipeline>,
-    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
-    tlas: Arc<AccelerationStructure>,
-    camera_buffer: Subbuffer<CameraUniform>,
-    shader_binding_table: Arc<ShaderBindingTable>,
     controller: CameraController,
     last_frame_time: Instant,
     recreate_swapchain: bool,
-    time: Instant,
+    // One per renderer frame slot, frames are submitted after the previous one
+    frame_fences: Vec<Option<Arc<FrameFence>>>,
+    previous_slot: Option<usize>,
+    // Submitted frames waiting for their GPU timings, by frame slot
+    pending_frames: Vec<Option<FrameInfo>>,
+    stats: Stats,
+    overlay: Overlay,
+    frame_interval: Option<Duration>,
+    next_frame: Instant,
+    // Ratio of the traced resolution to the window, the upscaler scales between them
+    render_scale: f32,
+    dynamic_resolution: Option<DynamicResolution>,
+    // Nothing is rendered while the window has no area
+    minimized: bool,
+    cursor_position: PhysicalPosition<f64>,
+    modifiers: ModifiersState,
 }
 
-fn create_storage_images(swapchain_images: &Vec<Arc<Image>>, memory_allocator: Arc<StandardMemoryAllocator>) -> Result<Vec<Arc<ImageView>>> {
-    swapchain_images
+// Surface format and colour space for the requested display mode, with the encoding the
+// post-process pass has to write so the blit to the swapchain image shows correct colours
+fn choose_surface_format(
+    available: &[(Format, ColorSpace)],
+    display: DisplayMode,
+    paper_white: f32,
+) -> Result<(Format, ColorSpace, OutputEncoding)> {
+    let find = |formats: &[Format], color_space: ColorSpace| {
+        available
+            .iter()
+            .find(|(format, space)| formats.contains(format) && *space == color_space)
+            .map(|&(format, space)| (format, space))
+    };
+
+    let hdr = match display {
+        DisplayMode::Sdr => None,
+        DisplayMode::Scrgb => find(&[Format::R16G16B16A16_SFLOAT], ColorSpace::ExtendedSrgbLinear)
+            .map(|(format, space)| (format, space, OutputEncoding::Scrgb { paper_white })),
+        DisplayMode::Hdr10 => find(
+            &[Format::A2B10G10R10_UNORM_PACK32, Format::A2R10G10B10_UNORM_PACK32],
+            ColorSpace::Hdr10St2084,
+        )
+        .map(|(format, space)| (format, space, OutputEncoding::Hdr10 { paper_white })),
+    };
+    if let Some(choice) = hdr {
+        return Ok(choice);
+    }
+    if display != DisplayMode::Sdr {
+        println!("The surface does not support {display:?} output, falling back to SDR");
+    }
+
+    // sRGB formats encode on write, others get the transfer function applied in the shader
+    if let Some((format, space)) = find(&[Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB], ColorSpace::SrgbNonLinear) {
+        return Ok((format, space, OutputEncoding::Linear));
+    }
+    let &(format, space) = available
         .iter()
-        .map(|image| {
+        .find(|(_, space)| *space == ColorSpace::SrgbNonLinear)
+        .or(available.first())
+        .context("The surface supports no formats")?;
+    Ok((format, space, OutputEncoding::Srgb))
+}
+
+// Traced resolution for a window extent, at least one pixel in each dimension
+fn render_extent([width, height, depth]: [u32; 3], scale: f32) -> [u32; 3] {
+    let scaled = |size: u32| ((size as f32 * scale).round() as u32).max(1);
+    [scaled(width), scaled(height), depth]
+}
+
+// Output of the renderer for each swapchain image, upscaled to it after the post-process pass
+fn create_storage_images(count: usize, extent: [u32; 3], memory_allocator: Arc<StandardMemoryAllocator>) -> Result<Vec<Arc<ImageView>>> {
+    (0..count)
+        .map(|_| {
             ImageView::new_default(
                 Image::new(
                     memory_allocator.clone(),
                     ImageCreateInfo {
                         image_type: ImageType::Dim2d,
                         format: Format::R16G16B16A16_SFLOAT,
-                        extent: image.extent(),
+                        extent,
                         usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
                         ..Default::default()
                     },
@@ -162,8 +197,48 @@ fn create_storage_images(swapchain_images: &Vec<Arc<Image>>, memory_allocator: A
         .collect::<Result<Vec<_>>>()
 }
 
+fn create_upscaled_images(
+    swapchain_images: &[Arc<Image>],
+    upscaler: Upscaler,
+    renderer: &Renderer,
+) -> Result<Vec<Arc<ImageView>>> {
+    match upscaler {
+        Upscaler::Edge => create_storage_images(
+            swapchain_images.len(),
+            swapchain_images[0].extent(),
+            renderer.memory_allocator.clone(),
+        ),
+        Upscaler::Bilinear => Ok(Vec::new()),
+    }
+}
+
+impl From<PresentMode> for swapchain::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => Self::Fifo,
+            PresentMode::Mailbox => Self::Mailbox,
+            PresentMode::Immediate => Self::Immediate,
+        }
+    }
+}
+
 impl GraphicsState {
+    // Sleeps until the next frame is due under --max-fps
+    fn limit_frame_rate(&mut self) {
+        let Some(interval) = self.frame_interval else {
+            return;
+        };
+        let now_time = Instant::now();
+        if self.next_frame > now_time {
+            std::thread::sleep(self.next_frame - now_time);
+        }
+        // Late frames do not make the following ones catch up
+        self.next_frame = (self.next_frame + interval).max(Instant::now());
+    }
+
     fn update(&mut self) -> Result<()> {
+        self.limit_frame_rate();
+
         let now_time = Instant::now();
         let delta_time = (now_time - self.last_frame_time).as_secs_f32();
         self.last_frame_time = now_time;
@@ -171,25 +246,22 @@ impl GraphicsState {
         self.controller.update_camera(&mut self.camera, delta_time);
         self.camera.update(delta_time);
 
-        let camera_uniforms = self.camera.get_ray_tracing_uniforms();
-
+        let moving = self.renderer.set_camera(self.camera.get_ray_tracing_uniforms());
+        if let Some(dynamic_resolution) = &mut self.dynamic_resolution
+            && let Some(scale) = dynamic_resolution.update(delta_time, moving)
         {
-            let mut content = self
-                .camera_buffer
-                .write()
-                .context("Failed to write to camera buffer")?;
-            *content = camera_uniforms;
+            self.render_scale = scale;
+            self.resize_render_targets()?;
         }
 
         if self.recreate_swapchain {
-            self.recreate_swapchain = false;
-
             let new_dimensions = self.window.inner_size();
 
+            // Retried once the window has an area again
             if new_dimensions.width == 0 || new_dimensions.height == 0 {
-                println!("Skipping swapchain recreation due to zero dimensions");
                 return Ok(());
             }
+            self.recreate_swapchain = false;
 
             let (new_swapchain, new_swapchain_images) = self.swapchain.recreate(SwapchainCreateInfo {
                 image_extent: new_dimensions.into(),
@@ -199,11 +271,22 @@ impl GraphicsState {
 
             self.swapchain = new_swapchain;
             self.swapchain_images = new_swapchain_images;
+            self.upscaled_images = create_upscaled_images(&self.swapchain_images, self.upscaler, &self.renderer)?;
+            self.resize_render_targets()?;
+        }
 
-            self.storage_images = create_storage_images(&self.swapchain_images, self.memory_allocator.clone())?;
 
+        // Bounds the frames in flight by waiting for the last one recorded into this slot
+        let slot = self.renderer.frame_slot();
+        if let Some(fence) = &self.frame_fences[slot] {
+            fence.wait(None).context("Failed to wait for frame fence")?;
+        }
+        if let Some(info) = self.pending_frames[slot].take() {
+            let gpu_timings = self.renderer.gpu_timings()?;
+            if self.stats.record(info, gpu_timings)? && !self.overlay.visible {
+                println!("{}", self.stats.summary()[0]);
+            }
         }
-
 
         let (image_index, suboptimal, acquire_future) =
             match swapchain::acquire_next_image(self.swapchain.clone(), None) {
@@ -218,68 +301,31 @@ impl GraphicsState {
         if suboptimal {
             self.recreate_swapchain = true;
         }
-
-        let descriptor_set_layout = self
-            .raytracing_pipeline
-            .layout()
-            .set_layouts()
-            .get(0)
-            .context("No descriptor set layout found")?;
-
-        let descriptor_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            descriptor_set_layout.clone(),
-            [
-                WriteDescriptorSet::acceleration_structure(0, self.tlas.clone()),
-                WriteDescriptorSet::image_view(
-                    1,
-                    self.storage_images[image_index as usize].clone(),
-                ),
-                WriteDescriptorSet::buffer(2, self.camera_buffer.clone()),
-            ],
-            [],
-        )
-        .context("Failed to create descriptor set")?;
-
-
-
-        let push_constants = PushConstants {
-            max_ray_recursion_depth: RAY_RECURSION_DEPTH,
-            time: self.time.elapsed().as_secs_f32(),
-        };
+        let record_start = Instant::now();
 
         let mut builder = AutoCommandBufferBuilder::primary(
-            self.command_buffer_allocator.clone(),
-            self.queue.queue_family_index(),
+            self.renderer.command_buffer_allocator.clone(),
+            self.renderer.queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
         )
         .context("Failed to create command buffer builder")?;
 
-        builder
-            .bind_pipeline_ray_tracing(self.raytracing_pipeline.clone())
-            .context("Failed to bind raytracing pipeline")?
-            .bind_descriptor_sets(
-                PipelineBindPoint::RayTracing,
-                self.raytracing_pipeline.layout().clone(),
-                0,
-                descriptor_set,
-            )
-            .context("Failed to bind descriptor sets")?
-            .push_constants(self.raytracing_pipeline.layout().clone(), 0, push_constants)
-            .context("Failed to push constants")?;
-
-        unsafe {
-            builder
-                .trace_rays(
-                    self.shader_binding_table.addresses().clone(),
-                    self.swapchain_images[image_index as usize].extent(),
-                )
-                .context("Failed to record trace rays command")?;
-        }
+        self.renderer
+            .record(&mut builder, self.storage_images[image_index as usize].clone())?;
 
-        let storage_image = self.storage_images[image_index as usize].image();
+        let storage_image = self.storage_images[image_index as usize].clone();
         let swapchain_image = &self.swapchain_images[image_index as usize];
 
+        // The edge-aware upscaler writes a window sized image, which the blit then only copies
+        let blit_source = match self.upscaled_images.get(image_index as usize) {
+            Some(upscaled) if storage_image.image().extent() != swapchain_image.extent() => {
+                self.renderer
+                    .record_upscale(&mut builder, storage_image, upscaled.clone())?;
+                upscaled.image().clone()
+            }
+            _ => storage_image.image().clone(),
+        };
+
         // Blit from storage image to swapchain image
         builder
             .blit_image(BlitImageInfo {
@@ -294,8 +340,8 @@ impl GraphicsState {
                     src_offsets: [
                         [0, 0, 0],
                         [
-                            storage_image.extent()[0] as u32,
-                            storage_image.extent()[1] as u32,
+                            blit_source.extent()[0] as u32,
+                            blit_source.extent()[1] as u32,
                             1,
                         ],
                     ],
@@ -316,34 +362,89 @@ impl GraphicsState {
                 }]
                 .into(),
                 filter: Filter::Linear,
-                ..BlitImageInfo::images(storage_image.clone(), swapchain_image.clone())
+                ..BlitImageInfo::images(blit_source, swapchain_image.clone())
             })
             .context("Failed to blit image")?;
 
+        if self.overlay.visible {
+            self.overlay
+                .set_text(self.stats.summary().to_vec(), &mut builder, self.renderer.memory_allocator.clone())?;
+            self.overlay.record(&mut builder, swapchain_image.clone())?;
+        }
+        self.renderer.record_frame_end(&mut builder)?;
+
         let command_buffer = builder.build().context("Failed to build command buffer")?;
 
-        let future = now(self.device.clone())
+        let previous_frame = match self.previous_slot.and_then(|previous| self.frame_fences[previous].clone()) {
+            Some(fence) => fence.boxed_send_sync(),
+            None => now(self.renderer.device.clone()).boxed_send_sync(),
+        };
+
+        let future = previous_frame
             .join(acquire_future)
-            .then_execute(self.queue.clone(), command_buffer)
+            .then_execute(self.renderer.queue.clone(), command_buffer)
             .context("Failed to execute command buffer")?
             .then_swapchain_present(
-                self.queue.clone(),
+                self.renderer.queue.clone(),
                 SwapchainPresentInfo::swapchain_image_index(self.swapchain.clone(), image_index),
             )
-            .then_signal_fence_and_flush()
-            .context("Failed to signal fence and flush")?;
+            .then_signal_fence_and_flush();
+
+        self.frame_fences[slot] = match future {
+            Ok(fence) => Some(Arc::new(fence)),
+            Err(Validated::Error(VulkanError::OutOfDate)) => {
+                self.recreate_swapchain = true;
+                None
+            }
+            Err(e) => Err(e).context("Failed to signal fence and flush")?,
+        };
+        self.previous_slot = Some(slot);
 
-        future.wait(None).context("Failed to wait for future")?;
+        let [width, height, _] = self.storage_images[0].image().extent();
+        self.pending_frames[slot] = self.frame_fences[slot].is_some().then(|| FrameInfo {
+            interval: delta_time * 1e3,
+            cpu: record_start.elapsed().as_secs_f32() * 1e3,
+            width,
+            height,
+            rays_per_sample: 2 * self.renderer.settings().max_bounces,
+        });
 
         Ok(())
     }
 
-    fn new(window: Arc<Window>, required_extensions: InstanceExtensions) -> Result<Self> {
+    // Traced resolution follows the swapchain extent and the render scale
+    fn resize_render_targets(&mut self) -> Result<()> {
+        let extent = render_extent(self.swapchain_images[0].extent(), self.render_scale);
+        self.storage_images =
+            create_storage_images(self.swapchain_images.len(), extent, self.renderer.memory_allocator.clone())?;
+        self.renderer.resize(extent)
+    }
+
+    // Writes the frame on screen to the working directory, named after the time it was taken
+    fn save_screenshot(&mut self, format: OutputFormat) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the Unix epoch")?
+            .as_millis();
+        let path = PathBuf::from(format!("screenshot_{timestamp}.{}", format.extension()));
+
+        let mut screenshot = self.renderer.capture(format)?;
+        screenshot.metadata.extend(self.camera.metadata());
+        screenshot.save(&path, format)?;
+        Ok(path)
+    }
+
+    fn new(window: Arc<Window>, required_extensions: InstanceExtensions, config: &Config) -> Result<Self> {
         let vulkan_library = VulkanLibrary::new().context("Failed to load Vulkan library")?;
+        // Needed for colour spaces other than sRGB, HDR output falls back to SDR without it
+        let colorspace_extension = InstanceExtensions {
+            ext_swapchain_colorspace: vulkan_library.supported_extensions().ext_swapchain_colorspace,
+            ..InstanceExtensions::empty()
+        };
         let instance = Instance::new(
             vulkan_library,
             vulkano::instance::InstanceCreateInfo {
-                enabled_extensions: required_extensions,
+                enabled_extensions: required_extensions | colorspace_extension,
                 ..Default::default()
             },
         )
@@ -352,69 +453,10 @@ impl GraphicsState {
         let surface = Surface::from_window(instance.clone(), window.clone())
             .context("Failed to create Surface from window")?;
 
-        let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
-            khr_ray_tracing_pipeline: true,
-            khr_acceleration_structure: true,
-            khr_deferred_host_operations: true,
-            khr_buffer_device_address: true,
-            khr_spirv_1_4: true,
-            khr_shader_float_controls: true,
-            khr_ray_tracing_position_fetch: true,
-            ..DeviceExtensions::empty()
-        };
+        let (device, queue) = create_device(&instance, Some(&surface), config.device.as_ref(), config.backend)?;
+        let physical_device = device.physical_device().clone();
 
-        let device_features = DeviceFeatures {
-            ray_tracing_pipeline: true,
-            acceleration_structure: true,
-            buffer_device_address: true,
-            ray_tracing_position_fetch: true,
-            ..Default::default()
-        };
-
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()
-            .context("Failed to enumerate physical devices")?
-            .filter(|p| p.supported_extensions().contains(&device_extensions))
-            .filter_map(|p| {
-                p.queue_family_properties()
-                    .iter()
-                    .enumerate()
-                    .position(|(i, q)| {
-                        q.queue_flags
-                            .intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
-                            && p.surface_support(i as u32, &surface).unwrap_or(false)
-                    })
-                    .map(|q| (p, q as u32))
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                _ => 4,
-            })
-            .context("No suitable device found")?;
-
-        let (device, mut queues) = Device::new(
-            physical_device.clone(),
-            DeviceCreateInfo {
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
-                enabled_extensions: device_extensions,
-                enabled_features: device_features,
-                ..Default::default()
-            },
-        )
-        .context("Failed to create logical device")?;
-
-        let queue = queues
-            .next()
-            .context("Failed to extract first queue out of queues")?;
-
-        let (swapchain, swapchain_images) = {
+        let (swapchain, swapchain_images, output_encoding) = {
             let caps = physical_device
                 .surface_capabilities(&surface, Default::default())
                 .context("Failed to get surface capabilities")?;
@@ -442,198 +484,43 @@ impl GraphicsState {
                 println!("  {:?}", format);
             }
 
-            let image_format = available_image_formats
-                .iter()
-                .find(|(format, _)| *format == Format::B8G8R8A8_SRGB)
-                .cloned()
-                .unwrap_or(available_image_formats[0]).0;
+            let (image_format, image_color_space, encoding) =
+                choose_surface_format(&available_image_formats, config.display, config.paper_white)?;
+            println!("Swapchain format: {image_format:?} {image_color_space:?}");
+
+            let present_modes = physical_device
+                .surface_present_modes(&surface, Default::default())
+                .context("Failed to get surface present modes")?;
+            let present_mode = if present_modes.contains(&config.present_mode.into()) {
+                config.present_mode.into()
+            } else {
+                println!("The surface does not support {:?} presentation, falling back to FIFO", config.present_mode);
+                swapchain::PresentMode::Fifo
+            };
+
+            // Mailbox needs an image to spare for replacing the queued one
+            let min_image_count = if present_mode == swapchain::PresentMode::Mailbox {
+                caps.min_image_count + 1
+            } else {
+                caps.min_image_count
+            };
 
-            Swapchain::new(
+            let (swapchain, images) = Swapchain::new(
                 device.clone(),
                 surface.clone(),
                 SwapchainCreateInfo {
-                    min_image_count: caps.min_image_count,
-                    image_format: Format::R16G16B16A16_SFLOAT,
+                    min_image_count: caps.max_image_count.map_or(min_image_count, |max| min_image_count.min(max)),
+                    image_format,
+                    image_color_space,
                     image_extent: dimensions.into(),
                     image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
                     composite_alpha,
+                    present_mode,
                     ..Default::default()
                 },
             )
-            .context("Failed to create swapchain")?
-        };
-
-        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
-
-        let storage_images = create_storage_images(&swapchain_images, memory_allocator.clone())?;
-
-        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
-            device.clone(),
-            Default::default(),
-        ));
-
-        let raytracing_pipeline = {
-            let raygen = rgen::load(device.clone())
-                .context("Failed to load raygen shader module")?
-                .entry_point("main")
-                .context("Failed to set entry point")?;
-
-            let closest_hit = rchit::load(device.clone())
-                .context("Failed to load closest hit shader module")?
-                .entry_point("main")
-                .context("Failed to set entry point")?;
-
-            let miss = rmiss::load(device.clone())
-                .context("Failed to load miss shader module")?
-                .entry_point("main")
-                .context("Failed to set entry point")?;
-
-            let shadow_miss = srmiss::load(device.clone())
-                .context("Failed to load shadow miss shader module")?
-                .entry_point("main")
-                .context("Failed to set entry point")?;
-
-            let stages = [
-                PipelineShaderStageCreateInfo::new(raygen),
-                PipelineShaderStageCreateInfo::new(miss),
-                PipelineShaderStageCreateInfo::new(closest_hit),
-                PipelineShaderStageCreateInfo::new(shadow_miss),
-            ];
-
-            let groups = [
-                RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
-                RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
-                RayTracingShaderGroupCreateInfo::TrianglesHit {
-                    closest_hit_shader: Some(2),
-                    any_hit_shader: None,
-                },
-                RayTracingShaderGroupCreateInfo::General { general_shader: 3 },
-            ];
-
-            let layout = PipelineLayout::new(
-                device.clone(),
-                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
-                    .into_pipeline_layout_create_info(device.clone())
-                    .context("Failed to create pipeline layout")?,
-            )
-            .context("Failed to create pipeline layout")?;
-
-            RayTracingPipeline::new(
-                device.clone(),
-                None,
-                RayTracingPipelineCreateInfo {
-                    stages: stages.into_iter().collect(),
-                    groups: groups.into_iter().collect(),
-                    max_pipeline_ray_recursion_depth: RAY_RECURSION_DEPTH,
-                    ..RayTracingPipelineCreateInfo::layout(layout)
-                },
-            )
-            .context("Failed to create raytracing pipeline")?
-        };
-
-        let mut vertices = [
-            MyVertex {
-                position: [0.0, 1.5, 0.0],
-            },
-            MyVertex {
-                position: [-0.5, 0.75, 0.0],
-            },
-            MyVertex {
-                position: [0.25, 0.9, 0.0],
-            },
-            MyVertex {
-                position: [-0.5, 1.0, 0.0],
-            },
-            MyVertex {
-                position: [-0.1, 0.8, 0.7],
-            },
-            MyVertex {
-                position: [0.55, 0.9, -0.4],
-            },
-            MyVertex {
-                position: [-10.0, 0.0, -10.0],
-            },
-            MyVertex {
-                position: [10.0, 0.0, -10.0],
-            },
-            MyVertex {
-                position: [10.0, 0.0, 10.0],
-            },
-            MyVertex {
-                position: [-10.0, 0.0, -10.0],
-            },
-            MyVertex {
-                position: [10.0, 0.0, 10.0],
-            },
-            MyVertex {
-                position: [-10.0, 0.0, 10.0],
-            }, //
-            MyVertex {
-                position: [-10.0, 0.0, -5.0],
-            },
-            MyVertex {
-                position: [10.0, 0.0, -5.0],
-            },
-            MyVertex {
-                position: [10.0, 10.0, -5.0],
-            },
-            MyVertex {
-                position: [-10.0, 0.0, -5.0],
-            },
-            MyVertex {
-                position: [10.0, 10.0, -5.0],
-            },
-            MyVertex {
-                position: [-10.0, 10.0, -5.0],
-            },
-
-        ];
-
-        let offset = Vec3::new(0.0, 0.0, 0.0);
-
-        for vertex in &mut vertices {
-            let pos = Vec3::from(vertex.position) + offset;
-            vertex.position = pos.to_array();
-        }
-
-        let vertex_buffer = Buffer::from_iter(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER
-                    | BufferUsage::SHADER_DEVICE_ADDRESS
-                    | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            vertices,
-        )
-        .context("Failed to create vertex buffer")?;
-
-        let blas = unsafe {
-            build_acceleration_structure_triangles(
-                &vertex_buffer,
-                memory_allocator.clone(),
-                &command_buffer_allocator,
-                device.clone(),
-                queue.clone(),
-            )
-        };
-
-        let tlas = unsafe {
-            build_top_level_acceleration_structure(
-                vec![AccelerationStructureInstance {
-                    acceleration_structure_reference: blas.device_address().into(),
-                    ..Default::default()
-                }],
-                memory_allocator.clone(),
-                &command_buffer_allocator,
-                device.clone(),
-                queue.clone(),
-            )
+            .context("Failed to create swapchain")?;
+            (swapchain, images, encoding)
         };
 
         let size = window.inner_size();
@@ -642,33 +529,30 @@ impl GraphicsState {
 
         let controller = CameraController::new(1.0, 0.1);
 
-        let camera_unfiorm = camera.get_ray_tracing_uniforms();
-
-        let camera_buffer = Buffer::from_data(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            camera_unfiorm,
-        )
-        .context("Failed to create camera buffer")?;
-
-        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-            device.clone(),
-            Default::default(),
-        ));
-
-        let shader_binding_table = Arc::new(
-            ShaderBindingTable::new(memory_allocator.clone(), &raytracing_pipeline)
-                .context("Failed to create shader binding table")?,
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            config.load_scene()?,
+            config.integrator,
+            config.render,
+            camera.get_ray_tracing_uniforms(),
+            render_extent(swapchain_images[0].extent(), config.render_scale),
+        )?;
+
+        renderer.set_output_encoding(output_encoding);
+        let frames_in_flight = renderer.frames_in_flight();
+        println!(
+            "Rendering on {} with the {:?} backend",
+            physical_device.properties().device_name,
+            renderer.backend()
         );
 
+        let storage_images = create_storage_images(
+            swapchain_images.len(),
+            render_extent(swapchain_images[0].extent(), config.render_scale),
+            renderer.memory_allocator.clone(),
+        )?;
+        let upscaled_images = create_upscaled_images(&swapchain_images, config.upscaler, &renderer)?;
 
         //let sky_img = image::open("assets/sky/golden_gate_hills_4k.hdr")
         //    .context("Failed to load sky image")?
@@ -678,29 +562,34 @@ impl GraphicsState {
 
         //let raw_hdr_data: Vec<[f32; 4]> = sky_img.pixels().map(|p| p.0).collect();
 
-        let start_time = Instant::now();
-
         Ok(Self {
             //instance,
             window,
             //surface,
-            device,
-            queue,
             swapchain,
             swapchain_images,
             storage_images,
-            command_buffer_allocator,
-            memory_allocator,
+            upscaled_images,
+            upscaler: config.upscaler,
+            renderer,
             camera,
-            raytracing_pipeline,
-            descriptor_set_allocator,
-            tlas,
-            camera_buffer,
-            shader_binding_table,
             controller,
             last_frame_time: Instant::now(),
             recreate_swapchain: false,
-            time: start_time,
+            frame_fences: vec![None; frames_in_flight],
+            previous_slot: None,
+            pending_frames: vec![None; frames_in_flight],
+            stats: Stats::new(config.stats_log.as_deref())?,
+            overlay: Overlay::new(config.overlay, output_encoding),
+            frame_interval: config.max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+            next_frame: Instant::now(),
+            render_scale: config.render_scale,
+            dynamic_resolution: config
+                .frame_budget
+                .map(|budget| DynamicResolution::new(budget, config.render_scale)),
+            minimized: false,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
         })
     }
 
@@ -713,8 +602,17 @@ impl GraphicsState {
                 true
             }
             WindowEvent::Resized(size) => {
-                self.camera.resize(size.width, size.height);
-                // Handle Vulkan swapchain recreation here
+                let minimized = size.width == 0 || size.height == 0;
+                // Frame timing restarts after a pause, so the camera does not jump
+                if self.minimized && !minimized {
+                    self.last_frame_time = Instant::now();
+                    self.next_frame = self.last_frame_time;
+                }
+                self.minimized = minimized;
+                if !minimized {
+                    self.camera.resize(size.width, size.height);
+                    self.recreate_swapchain = true;
+                }
                 true
             }
 
@@ -727,10 +625,112 @@ impl GraphicsState {
                     },
                 ..
             } => {
+                if *key_code == KeyCode::KeyI && *state == ElementState::Pressed {
+                    let integrator = match self.renderer.integrator() {
+                        Integrator::Rgb => Integrator::Spectral,
+                        Integrator::Spectral => Integrator::Rgb,
+                    };
+                    if let Err(e) = self.renderer.set_integrator(integrator) {
+                        eprintln!("Failed to switch integrator: {e:?}");
+                    }
+                    return true;
+                }
+
+                if *key_code == KeyCode::KeyG && *state == ElementState::Pressed {
+                    let working_space = match self.renderer.settings().working_space {
+                        WorkingSpace::Rec709 => WorkingSpace::Acescg,
+                        WorkingSpace::Acescg => WorkingSpace::Rec709,
+                    };
+                    if let Err(e) = self.renderer.set_working_space(working_space) {
+                        eprintln!("Failed to switch working space: {e:?}");
+                    }
+                    return true;
+                }
+
+                // Display settings, applied without restarting accumulation
+                if *state == ElementState::Pressed {
+                    let settings = self.renderer.settings();
+                    let handled = match key_code {
+                        KeyCode::KeyT => {
+                            self.renderer.set_tonemapper(settings.tonemapper.next());
+                            true
+                        }
+                        KeyCode::Minus => {
+                            self.renderer.set_exposure(settings.exposure - 0.5);
+                            true
+                        }
+                        KeyCode::Equal => {
+                            self.renderer.set_exposure(settings.exposure + 0.5);
+                            true
+                        }
+                        KeyCode::KeyX => {
+                            self.renderer.set_auto_exposure(!settings.auto_exposure);
+                            true
+                        }
+                        _ => false,
+                    };
+                    if handled {
+                        return true;
+                    }
+                }
+
+                if *key_code == KeyCode::KeyJ && *state == ElementState::Pressed {
+                    let enabled = !self.renderer.settings().taa;
+                    if let Err(e) = self.renderer.set_taa(enabled) {
+                        eprintln!("Failed to toggle TAA: {e:?}");
+                    }
+                    return true;
+                }
+
+                if *key_code == KeyCode::KeyN && *state == ElementState::Pressed {
+                    let enabled = !self.renderer.settings().denoise;
+                    if let Err(e) = self.renderer.set_denoise(enabled) {
+                        eprintln!("Failed to toggle denoiser: {e:?}");
+                    }
+                    return true;
+                }
+
+                if *key_code == KeyCode::KeyV && *state == ElementState::Pressed {
+                    let view = self.renderer.settings().debug_view.next();
+                    if let Err(e) = self.renderer.set_debug_view(view) {
+                        eprintln!("Failed to switch debug view: {e:?}");
+                    }
+                    return true;
+                }
+
+                if *key_code == KeyCode::F3 && *state == ElementState::Pressed {
+                    self.overlay.visible = !self.overlay.visible;
+                    return true;
+                }
+
+                // F12 saves what is on screen, Shift+F12 the linear radiance behind it
+                if *key_code == KeyCode::F12 && *state == ElementState::Pressed {
+                    let format = if self.modifiers.shift_key() { OutputFormat::Exr } else { OutputFormat::Png };
+                    match self.save_screenshot(format) {
+                        Ok(path) => println!("Saved {}", path.display()),
+                        Err(e) => eprintln!("Failed to save screenshot: {e:?}"),
+                    }
+                    return true;
+                }
+
                 self.controller.process_keyboard(*key_code, *state)
+                    || self.controller.process_camera_key(*key_code, *state, &mut self.camera)
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                false
             }
             WindowEvent::MouseInput { button, state, .. } => {
-                if *button == winit::event::MouseButton::Right && *state == ElementState::Pressed {
+                if *button == winit::event::MouseButton::Middle {
+                    if *state == ElementState::Pressed {
+                        self.focus_at_cursor();
+                    }
+                    true
+                } else if *button == winit::event::MouseButton::Right && *state == ElementState::Pressed {
                     self.controller.set_mouse_captured(true);
                     //window.set_cursor_visible(false);
                     let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
@@ -760,6 +760,28 @@ impl GraphicsState {
         }
     }
 
+    // Click to focus, traces a pick ray through the cursor against the scene
+    fn focus_at_cursor(&mut self) {
+        let size = self.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        let uv = Vec2::new(
+            self.cursor_position.x as f32 / size.width as f32,
+            self.cursor_position.y as f32 / size.height as f32,
+        );
+        let (origin, direction) = self.camera.primary_ray(uv);
+
+        if let Some(t) = self.renderer.scene.intersect(origin, direction) {
+            self.camera.focus_on(direction, t);
+        }
+    }
+
+    fn add_volume(&mut self, path: &Path) -> Result<()> {
+        self.renderer.add_volume(path)
+    }
+
     fn handle_device_event(&mut self, event: &DeviceEvent) {
         if let DeviceEvent::MouseMotion { delta } = event {
             self.controller
@@ -768,8 +790,8 @@ impl GraphicsState {
     }
 }
 
-#[derive(Default)]
 struct App {
+    config: Config,
     window: Option<Arc<Window>>,
     graphics_state: Option<GraphicsState>,
     error: Option<Error>,
@@ -780,14 +802,19 @@ impl ApplicationHandler for App {
         let result = (|| -> Result<()> {
             let window = Arc::new(
                 event_loop
-                    .create_window(Window::default_attributes().with_transparent(true).with_title("Vulkan Pathtracer"))
+                    .create_window(
+                        Window::default_attributes()
+                            .with_transparent(true)
+                            .with_title("Vulkan Pathtracer")
+                            .with_inner_size(PhysicalSize::new(self.config.width, self.config.height)),
+                    )
                     .context("Failed to create window")?,
             );
 
             let required_extensions = Surface::required_extensions(event_loop)
                 .context("Failed to get required extensions")?;
 
-            self.graphics_state = Some(GraphicsState::new(window.clone(), required_extensions)?);
+            self.graphics_state = Some(GraphicsState::new(window.clone(), required_extensions, &self.config)?);
 
             self.window = Some(window);
 
@@ -816,34 +843,28 @@ impl ApplicationHandler for App {
                 event_loop.exit();
             }
 
+            WindowEvent::DroppedFile(path) => {
+                if let Some(graphics_state) = &mut self.graphics_state
+                    && let Err(e) = graphics_state.add_volume(&path)
+                {
+                    eprintln!("Failed to load volume {}: {e:#}", path.display());
+                }
+            }
+
+            // Redraws stop while minimized, restoring the window starts them again
             WindowEvent::Resized(_) => {
-                if let Some(graphics_state) = &mut self.graphics_state {
-                    //graphics_state.recreate_swapchain = true;
+                if let (Some(graphics_state), Some(window)) = (&self.graphics_state, &self.window)
+                    && !graphics_state.minimized
+                {
+                    window.request_redraw();
                 }
             }
 
             WindowEvent::RedrawRequested => {
-                // Static variables to track frame count and time
-                static mut LAST_TIME: Option<Instant> = None;
-                static mut FRAME_COUNT: u32 = 0;
-
-                unsafe {
-                    let now = Instant::now();
-                    if let Some(last_time) = LAST_TIME {
-                        FRAME_COUNT += 1;
-                        let elapsed = now.duration_since(last_time);
-                        if elapsed >= Duration::from_secs(1) {
-                            let fps = FRAME_COUNT as f64 / elapsed.as_secs_f64();
-                            println!("FPS: {:.2}", fps);
-                            LAST_TIME = Some(now);
-                            FRAME_COUNT = 0;
-                        }
-                    } else {
-                        LAST_TIME = Some(now);
-                        FRAME_COUNT = 0;
-                    }
-                }
                 if let Some(graphics_state) = self.graphics_state.as_mut() {
+                    if graphics_state.minimized {
+                        return;
+                    }
                     if let Err(e) = graphics_state.update() {
                         self.error = Some(e);
                         event_loop.exit();
@@ -878,7 +899,31 @@ impl ApplicationHandler for App {
 }
 
 fn main() {
-    if let Err(e) = run() {
+    let config = match Cli::parse().validate() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(2);
+        }
+    };
+
+    // Offline renders run unattended, so errors only go to stderr
+    if let Mode::Headless(options) = &config.mode {
+        if let Err(e) = headless::render(&config, options) {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Mode::ListDevices = config.mode {
+        if let Err(e) = list_devices() {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = run(config) {
         let error_message = format!("{e:#}");
         eprintln!("Error: {error_message}");
 
@@ -892,11 +937,23 @@ fn main() {
     }
 }
 
-fn run() -> Result<()> {
+fn list_devices() -> Result<()> {
+    let library = VulkanLibrary::new().context("Failed to load Vulkan library")?;
+    let instance = Instance::new(library, vulkano::instance::InstanceCreateInfo::default())
+        .context("Failed to create Vulkan Instance")?;
+    print_devices(&instance)
+}
+
+fn run(config: Config) -> Result<()> {
     let event_loop = EventLoop::new().context("Failed to create event loop")?;
     event_loop.set_control_flow(ControlFlow::Wait);
 
-    let mut app = App::default();
+    let mut app = App {
+        config,
+        window: None,
+        graphics_state: None,
+        error: None,
+    };
     event_loop.run_app(&mut app).context("Event loop error")?;
 
     if let Some(err) = app.error {
@@ -1031,6 +1088,35 @@ unsafe fn build_acceleration_structure_triangles(
     }
 }
 
+unsafe fn build_acceleration_structure_aabbs(
+    aabb_buffer: &Subbuffer<[AabbPositions]>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: &Arc<StandardCommandBufferAllocator>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+) -> Arc<AccelerationStructure> {
+    let primitive_count = aabb_buffer.len() as u32;
+    let as_geometry_aabbs_data = AccelerationStructureGeometryAabbsData {
+        data: Some(aabb_buffer.clone().into_bytes()),
+        stride: size_of::<AabbPositions>() as _,
+        ..Default::default()
+    };
+
+    let geometries = AccelerationStructureGeometries::Aabbs(vec![as_geometry_aabbs_data]);
+
+    unsafe {
+        build_acceleration_structure_common(
+            geometries,
+            primitive_count,
+            AccelerationStructureType::BottomLevel,
+            memory_allocator,
+            command_buffer_allocator,
+            device,
+            queue,
+        )
+    }
+}
+
 unsafe fn build_top_level_acceleration_structure(
     as_instances: Vec<AccelerationStructureInstance>,
     memory_allocator: Arc<StandardMemoryAllocator>,
@@ -0,0 +1,171 @@
+// Intel Open Image Denoise for final frames, loaded at runtime so builds do not need the SDK.
+// Runs the RT filter on the CPU over the readback beauty, albedo and normal images.
+
+use anyhow::{Context, Result, bail};
+use libloading::Library;
+use std::env;
+use std::ffi::{CStr, OsString, c_char, c_void};
+use std::ptr;
+
+type Device = *mut c_void;
+type Filter = *mut c_void;
+
+// Values of the OIDN C API
+const DEVICE_TYPE_CPU: i32 = 1;
+const ERROR_NONE: i32 = 0;
+const FORMAT_FLOAT3: i32 = 3;
+
+// Overrides the search for the shared library
+const LIBRARY_VARIABLE: &str = "OIDN_LIBRARY";
+
+fn library_candidates() -> Vec<OsString> {
+    if let Some(path) = env::var_os(LIBRARY_VARIABLE) {
+        return vec![path];
+    }
+    let mut candidates = vec![libloading::library_filename("OpenImageDenoise")];
+    if cfg!(target_os = "linux") {
+        candidates.push("libOpenImageDenoise.so.2".into());
+        candidates.push("libOpenImageDenoise.so.1".into());
+    } else if cfg!(target_os = "macos") {
+        candidates.push("libOpenImageDenoise.2.dylib".into());
+    }
+    candidates
+}
+
+fn load_library() -> Result<Library> {
+    let candidates = library_candidates();
+    for candidate in &candidates {
+        // Loading runs the library initializers, OIDN has no unusual ones
+        if let Ok(library) = unsafe { Library::new(candidate) } {
+            return Ok(library);
+        }
+    }
+    bail!(
+        "Intel Open Image Denoise was not found, tried {}. Install OIDN or point {LIBRARY_VARIABLE} at the library",
+        candidates
+            .iter()
+            .map(|candidate| candidate.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// Entry points of the C API used here, resolved once per denoised image
+struct Api {
+    new_device: unsafe extern "C" fn(i32) -> Device,
+    commit_device: unsafe extern "C" fn(Device),
+    get_device_error: unsafe extern "C" fn(Device, *mut *const c_char) -> i32,
+    release_device: unsafe extern "C" fn(Device),
+    new_filter: unsafe extern "C" fn(Device, *const c_char) -> Filter,
+    set_shared_filter_image: unsafe extern "C" fn(Filter, *const c_char, *mut c_void, i32, usize, usize, usize, usize, usize),
+    set_filter_bool: unsafe extern "C" fn(Filter, *const c_char, bool),
+    commit_filter: unsafe extern "C" fn(Filter),
+    execute_filter: unsafe extern "C" fn(Filter),
+    release_filter: unsafe extern "C" fn(Filter),
+}
+
+impl Api {
+    fn load(library: &Library) -> Result<Self> {
+        // The signatures match OpenImageDenoise/oidn.h of OIDN 1.x and 2.x
+        unsafe {
+            Ok(Self {
+                new_device: *library.get(b"oidnNewDevice\0").context("Missing oidnNewDevice")?,
+                commit_device: *library.get(b"oidnCommitDevice\0").context("Missing oidnCommitDevice")?,
+                get_device_error: *library.get(b"oidnGetDeviceError\0").context("Missing oidnGetDeviceError")?,
+                release_device: *library.get(b"oidnReleaseDevice\0").context("Missing oidnReleaseDevice")?,
+                new_filter: *library.get(b"oidnNewFilter\0").context("Missing oidnNewFilter")?,
+                set_shared_filter_image: *library
+                    .get(b"oidnSetSharedFilterImage\0")
+                    .context("Missing oidnSetSharedFilterImage")?,
+                // OIDN 1.x only has the older name
+                set_filter_bool: *library
+                    .get(b"oidnSetFilterBool\0")
+                    .or_else(|_| library.get(b"oidnSetFilter1b\0"))
+                    .context("Missing oidnSetFilterBool and oidnSetFilter1b")?,
+                commit_filter: *library.get(b"oidnCommitFilter\0").context("Missing oidnCommitFilter")?,
+                execute_filter: *library.get(b"oidnExecuteFilter\0").context("Missing oidnExecuteFilter")?,
+                release_filter: *library.get(b"oidnReleaseFilter\0").context("Missing oidnReleaseFilter")?,
+            })
+        }
+    }
+
+    fn check(&self, device: Device) -> Result<()> {
+        let mut message = ptr::null();
+        let error = unsafe { (self.get_device_error)(device, &mut message) };
+        if error == ERROR_NONE {
+            return Ok(());
+        }
+        let message = if message.is_null() {
+            "unknown error".into()
+        } else {
+            unsafe { CStr::from_ptr(message) }.to_string_lossy()
+        };
+        bail!("Open Image Denoise failed with error {error}: {message}")
+    }
+}
+
+// Denoises RGBA pixels in place, guided by RGBA albedo and normal images of the same size.
+// HDR images hold linear radiance, others tonemapped values in [0, 1].
+pub fn denoise(pixels: &mut [f32], albedo: &[f32], normal: &[f32], width: u32, height: u32, hdr: bool) -> Result<()> {
+    let texels = width as usize * height as usize * 4;
+    anyhow::ensure!(
+        pixels.len() == texels && albedo.len() == texels && normal.len() == texels,
+        "Denoiser images do not match the {width}x{height} output"
+    );
+
+    let library = load_library()?;
+    let api = Api::load(&library)?;
+
+    // OIDN only reads the guides, the API takes mutable pointers regardless
+    let mut albedo = albedo.to_vec();
+    let mut normal = normal.to_vec();
+    let mut input = pixels.to_vec();
+
+    let device = unsafe { (api.new_device)(DEVICE_TYPE_CPU) };
+    anyhow::ensure!(!device.is_null(), "Failed to create Open Image Denoise CPU device");
+    let result = (|| {
+        unsafe { (api.commit_device)(device) };
+        api.check(device)?;
+
+        let filter = unsafe { (api.new_filter)(device, c"RT".as_ptr()) };
+        api.check(device)?;
+
+        let images: [(&CStr, *mut f32); 4] = [
+            (c"color", input.as_mut_ptr()),
+            (c"albedo", albedo.as_mut_ptr()),
+            (c"normal", normal.as_mut_ptr()),
+            (c"output", pixels.as_mut_ptr()),
+        ];
+        let result = (|| {
+            for (name, data) in images {
+                unsafe {
+                    (api.set_shared_filter_image)(
+                        filter,
+                        name.as_ptr(),
+                        data.cast(),
+                        FORMAT_FLOAT3,
+                        width as usize,
+                        height as usize,
+                        0,
+                        4 * size_of::<f32>(),
+                        width as usize * 4 * size_of::<f32>(),
+                    );
+                }
+            }
+            unsafe {
+                (api.set_filter_bool)(filter, c"hdr".as_ptr(), hdr);
+                (api.commit_filter)(filter);
+            }
+            api.check(device)?;
+
+            unsafe { (api.execute_filter)(filter) };
+            api.check(device)
+        })();
+
+        unsafe { (api.release_filter)(filter) };
+        result
+    })();
+
+    unsafe { (api.release_device)(device) };
+    result
+}
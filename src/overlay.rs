@@ -0,0 +1,255 @@
+// Text overlay of the interactive view. Lines are rasterized on the CPU with a 5x7 bitmap font
+// into a small image, uploaded when the text changes and blitted onto the swapchain image with
+// nearest filtering, so it stays sharp at any render scale. The blit skips the post-process pass,
+// so the colours are encoded for the swapchain output on the CPU instead.
+
+use crate::renderer::OutputEncoding;
+use anyhow::{Context, Result};
+use glam::Vec3;
+use half::f16;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BlitImageInfo, CopyBufferToImageInfo, ImageBlit, PrimaryAutoCommandBuffer,
+};
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
+use vulkano::image::{Image, ImageAspects, ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 3;
+const PADDING: usize = 4;
+// Screen pixels per overlay pixel, and distance from the top left corner of the window
+const SCALE: u32 = 2;
+const MARGIN: u32 = 8;
+
+// Linear grey relative to paper white, the text stays below the white of the tonemapped image
+const BACKGROUND: f32 = 0.005;
+const FOREGROUND: f32 = 0.6;
+
+// Rows from the top, the lowest five bits from the left. Lowercase letters show as uppercase,
+// characters without a glyph as space
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+// Whether each pixel is covered by text, with the width and height
+fn rasterize(lines: &[String]) -> (Vec<bool>, usize, usize) {
+    let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let width = 2 * PADDING + columns * CELL_WIDTH;
+    let height = 2 * PADDING + lines.len() * CELL_HEIGHT;
+
+    let mut pixels = vec![false; width * height];
+    for (row, line) in lines.iter().enumerate() {
+        for (column, character) in line.chars().enumerate() {
+            for (y, bits) in glyph(character).into_iter().enumerate() {
+                for x in (0..GLYPH_WIDTH).filter(|x| bits & (0x10 >> x) != 0) {
+                    let pixel = (PADDING + row * CELL_HEIGHT + y) * width + PADDING + column * CELL_WIDTH + x;
+                    pixels[pixel] = true;
+                }
+            }
+        }
+    }
+    (pixels, width, height)
+}
+
+// RGBA pixel of a grey as the swapchain image stores it
+fn encode(grey: f32, encoding: OutputEncoding) -> [f16; 4] {
+    let color = encoding.encode(Vec3::splat(grey));
+    [color.x, color.y, color.z, 1.0].map(f16::from_f32)
+}
+
+pub struct Overlay {
+    // Replaced with every text change, frames in flight keep blitting the previous one
+    image: Option<Arc<Image>>,
+    lines: Vec<String>,
+    encoding: OutputEncoding,
+    pub visible: bool,
+}
+
+impl Overlay {
+    // The encoding is the one the post-process pass writes for the swapchain image
+    pub fn new(visible: bool, encoding: OutputEncoding) -> Self {
+        Self {
+            image: None,
+            lines: Vec::new(),
+            encoding,
+            visible,
+        }
+    }
+
+    // Uploads the lines unless they are already shown
+    pub fn set_text(
+        &mut self,
+        lines: Vec<String>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+    ) -> Result<()> {
+        if lines == self.lines {
+            return Ok(());
+        }
+        self.lines = lines;
+        if self.lines.is_empty() {
+            self.image = None;
+            return Ok(());
+        }
+
+        let (coverage, width, height) = rasterize(&self.lines);
+        let (background, foreground) = (encode(BACKGROUND, self.encoding), encode(FOREGROUND, self.encoding));
+        let pixels: Vec<f16> = coverage.into_iter().flat_map(|lit| if lit { foreground } else { background }).collect();
+        let staging = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            pixels,
+        )
+        .context("Failed to create overlay staging buffer")?;
+
+        let image = Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                // Float so scRGB values above one survive, the blit converts to the swapchain format
+                format: Format::R16G16B16A16_SFLOAT,
+                extent: [width as u32, height as u32, 1],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create overlay image")?;
+
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(staging, image.clone()))
+            .context("Failed to upload overlay image")?;
+        self.image = Some(image);
+        Ok(())
+    }
+
+    // Draws the overlay in the top left corner, skipped while hidden or larger than the target
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        target: Arc<Image>,
+    ) -> Result<()> {
+        let Some(image) = self.image.as_ref().filter(|_| self.visible) else {
+            return Ok(());
+        };
+        let [width, height, _] = image.extent();
+        let end = [MARGIN + width * SCALE, MARGIN + height * SCALE];
+        if end[0] > target.extent()[0] || end[1] > target.extent()[1] {
+            return Ok(());
+        }
+
+        let subresource = ImageSubresourceLayers {
+            aspects: ImageAspects::COLOR,
+            mip_level: 0,
+            array_layers: 0..1,
+        };
+        builder
+            .blit_image(BlitImageInfo {
+                src_image_layout: ImageLayout::TransferSrcOptimal,
+                dst_image_layout: ImageLayout::TransferDstOptimal,
+                regions: [ImageBlit {
+                    src_subresource: subresource.clone(),
+                    src_offsets: [[0, 0, 0], [width, height, 1]],
+                    dst_subresource: subresource,
+                    dst_offsets: [[MARGIN, MARGIN, 0], [end[0], end[1], 1]],
+                    ..Default::default()
+                }]
+                .into(),
+                filter: Filter::Nearest,
+                ..BlitImageInfo::images(image.clone(), target)
+            })
+            .context("Failed to blit overlay")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterizes_lines_into_cells() {
+        let (pixels, width, height) = rasterize(&["1.".to_owned(), "-".to_owned()]);
+        assert_eq!((width, height), (2 * PADDING + 2 * CELL_WIDTH, 2 * PADDING + 2 * CELL_HEIGHT));
+
+        let lit = |x: usize, y: usize| pixels[y * width + x];
+        let count = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).filter(|&(x, y)| lit(x, y)).count();
+        // Ten pixels for the one, four for the point and five for the dash
+        assert_eq!(count, 19);
+        // Top of the one and the middle row of the dash on the second line
+        assert!(lit(PADDING + 2, PADDING));
+        assert!(lit(PADDING, PADDING + CELL_HEIGHT + 3));
+        assert!(!lit(0, 0));
+    }
+
+    #[test]
+    fn encodes_text_below_paper_white() {
+        // Paper white of 100 nits is 0.508 in PQ, the text has to stay darker than that
+        let hdr10 = encode(FOREGROUND, OutputEncoding::Hdr10 { paper_white: 100.0 });
+        assert!(hdr10[0].to_f32() > 0.4 && hdr10[0].to_f32() < 0.508);
+        // scRGB keeps paper white above one, 80 nits per unit
+        let scrgb = encode(FOREGROUND, OutputEncoding::Scrgb { paper_white: 240.0 });
+        assert!((scrgb[0].to_f32() - 3.0 * FOREGROUND).abs() < 1e-2);
+        let srgb = encode(FOREGROUND, OutputEncoding::Srgb);
+        assert!((srgb[0].to_f32() - 0.8).abs() < 0.01);
+        assert_eq!(srgb[3].to_f32(), 1.0);
+    }
+}
@@ -0,0 +1,169 @@
+// Adaptive sampling: raygen tracks the sample count and luminance variance of every pixel in
+// the samples AOV, after each frame a compute pass marks the tiles whose error is still above
+// the target. Once every pixel has the minimum number of samples raygen skips converged tiles.
+
+mod adaptive_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/adaptive.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::aov::AovImages;
+use super::tonemap::{create_compute_pipeline, descriptor_set};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+
+// Matches ADAPTIVE_TILE_SIZE in common.glsl
+const TILE_SIZE: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AdaptiveSampling {
+    // Relative error of the worst pixel in a tile below which the tile is done
+    pub target_error: f32,
+    // Samples every pixel receives before any tile is skipped
+    pub min_samples: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct AdaptivePushConstants {
+    target_error: f32,
+}
+
+// One texel per tile, nonzero while the tile needs samples
+fn create_tile_mask(memory_allocator: Arc<StandardMemoryAllocator>, extent: [u32; 3]) -> Result<Arc<ImageView>> {
+    ImageView::new_default(
+        Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32_UINT,
+                extent: [extent[0].div_ceil(TILE_SIZE), extent[1].div_ceil(TILE_SIZE), 1],
+                usage: ImageUsage::STORAGE,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create tile mask image")?,
+    )
+    .context("Failed to create image view for tile mask image")
+}
+
+pub struct AdaptiveSampler {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline: Arc<ComputePipeline>,
+    tile_mask: Arc<ImageView>,
+    active_tiles: Subbuffer<u32>,
+}
+
+impl AdaptiveSampler {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        extent: [u32; 3],
+    ) -> Result<Self> {
+        let pipeline = create_compute_pipeline(
+            device.clone(),
+            adaptive_shader::load(device).context("Failed to load adaptive sampling shader module")?,
+        )?;
+
+        let active_tiles = Buffer::from_data(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            0u32,
+        )
+        .context("Failed to create active tile counter")?;
+
+        let tile_mask = create_tile_mask(memory_allocator.clone(), extent)?;
+
+        Ok(Self {
+            memory_allocator,
+            descriptor_set_allocator,
+            pipeline,
+            tile_mask,
+            active_tiles,
+        })
+    }
+
+    pub fn resize(&mut self, extent: [u32; 3]) -> Result<()> {
+        self.tile_mask = create_tile_mask(self.memory_allocator.clone(), extent)?;
+        Ok(())
+    }
+
+    // Binding 16 of descriptor set 0, see rgen.glsl
+    pub fn descriptor_write(&self) -> WriteDescriptorSet {
+        WriteDescriptorSet::image_view(16, self.tile_mask.clone())
+    }
+
+    // Tiles still above the target error after the last record, valid once it has executed
+    pub fn active_tiles(&self) -> Result<u32> {
+        Ok(*self.active_tiles.read().context("Failed to read active tile counter")?)
+    }
+
+    // Updates the tile mask raygen reads in the next frame
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        aov_images: &AovImages,
+        settings: &AdaptiveSampling,
+    ) -> Result<()> {
+        let set = descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.pipeline,
+            [
+                WriteDescriptorSet::image_view(0, aov_images.samples.clone()),
+                WriteDescriptorSet::image_view(1, self.tile_mask.clone()),
+                WriteDescriptorSet::buffer(2, self.active_tiles.clone()),
+            ],
+        )?;
+
+        builder
+            .fill_buffer(self.active_tiles.clone().into_slice(), 0)
+            .context("Failed to clear active tile counter")?
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("Failed to bind adaptive sampling pipeline")?
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set)
+            .context("Failed to bind descriptor sets")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                AdaptivePushConstants {
+                    target_error: settings.target_error,
+                },
+            )
+            .context("Failed to push constants")?;
+
+        let [width, height, _] = self.tile_mask.image().extent();
+        unsafe {
+            builder
+                .dispatch([width, height, 1])
+                .context("Failed to record adaptive sampling dispatch")?;
+        }
+        Ok(())
+    }
+}
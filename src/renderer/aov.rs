@@ -0,0 +1,134 @@
+// Arbitrary output variables: first hit albedo, normal, depth, IDs and motion vectors written
+// by raygen next to the radiance, for debug views, compositing and denoising
+
+use crate::screenshot::{Channel, Samples};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+
+// Values match the DEBUG_VIEW_ defines in tonemap.glsl
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum DebugView {
+    /// Tonemapped radiance
+    Beauty = 0,
+    /// First hit surface colour
+    Albedo = 1,
+    /// First hit shading normal
+    Normal = 2,
+    /// Distance to the first hit
+    Depth = 3,
+    /// Random colour per instance
+    InstanceId = 4,
+    /// Random colour per material
+    MaterialId = 5,
+    /// Random colour per primitive
+    PrimitiveId = 6,
+    /// Screen space motion to the previous frame
+    Motion = 7,
+    /// Heatmap of the samples each pixel received
+    Samples = 8,
+}
+
+impl DebugView {
+    const ALL: [Self; 9] = [
+        Self::Beauty,
+        Self::Albedo,
+        Self::Normal,
+        Self::Depth,
+        Self::InstanceId,
+        Self::MaterialId,
+        Self::PrimitiveId,
+        Self::Motion,
+        Self::Samples,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+pub struct AovImages {
+    pub albedo: Arc<ImageView>,
+    pub normal: Arc<ImageView>,
+    pub depth: Arc<ImageView>,
+    // Instance, material and primitive index, all ones where nothing was hit
+    pub id: Arc<ImageView>,
+    // Offset in pixels to where the surface was in the previous frame
+    pub motion: Arc<ImageView>,
+    // Sample count, mean luminance and sum of squared luminance deviations. Always full size,
+    // raygen averages the accumulation by the count
+    pub samples: Arc<ImageView>,
+}
+
+fn create_aov_image(
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    format: Format,
+    extent: [u32; 3],
+) -> Result<Arc<ImageView>> {
+    ImageView::new_default(
+        Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create AOV image")?,
+    )
+    .context("Failed to create image view for AOV image")
+}
+
+impl AovImages {
+    // Disabled AOVs still need something bound, a single texel per image is enough
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>, extent: [u32; 3], enabled: bool) -> Result<Self> {
+        let aov_extent = if enabled { extent } else { [1, 1, 1] };
+        Ok(Self {
+            albedo: create_aov_image(memory_allocator.clone(), Format::R16G16B16A16_SFLOAT, aov_extent)?,
+            normal: create_aov_image(memory_allocator.clone(), Format::R16G16B16A16_SFLOAT, aov_extent)?,
+            depth: create_aov_image(memory_allocator.clone(), Format::R32_SFLOAT, aov_extent)?,
+            id: create_aov_image(memory_allocator.clone(), Format::R32G32B32A32_UINT, aov_extent)?,
+            motion: create_aov_image(memory_allocator.clone(), Format::R16G16B16A16_SFLOAT, aov_extent)?,
+            samples: create_aov_image(memory_allocator, Format::R32G32B32A32_SFLOAT, extent)?,
+        })
+    }
+
+    // Bindings 10 to 15 of descriptor set 0, see rgen.glsl
+    pub fn descriptor_writes(&self) -> [WriteDescriptorSet; 6] {
+        [
+            WriteDescriptorSet::image_view(10, self.albedo.clone()),
+            WriteDescriptorSet::image_view(11, self.normal.clone()),
+            WriteDescriptorSet::image_view(12, self.depth.clone()),
+            WriteDescriptorSet::image_view(13, self.id.clone()),
+            WriteDescriptorSet::image_view(14, self.motion.clone()),
+            WriteDescriptorSet::image_view(15, self.samples.clone()),
+        ]
+    }
+}
+
+// Splits interleaved texels into one EXR channel per name, trailing components are dropped
+pub fn split_channels<T: Copy>(
+    texels: &[T],
+    components: usize,
+    names: &[&str],
+    samples: fn(Vec<T>) -> Samples,
+) -> Vec<Channel> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(component, name)| Channel {
+            name: (*name).to_owned(),
+            samples: samples(texels.iter().skip(component).step_by(components).copied().collect()),
+        })
+        .collect()
+}
@@ -0,0 +1,375 @@
+// Ways of tracing the integrator's rays. All of them run integrator.glsl, they only differ in
+// how intersect_closest and occluded find geometry. The ray tracing pipeline uses hit shaders
+// and hardware traversal, the compute backends trace from a compute shader with ray queries
+// against the same acceleration structures or by walking a BVH built on the CPU.
+
+mod rgen {
+    vulkano_shaders::shader! {
+        ty: "raygen",
+        path: "src/shaders/rgen.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod rchit {
+    vulkano_shaders::shader! {
+        ty: "closesthit",
+        path: "src/shaders/rchit.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod rint_volume {
+    vulkano_shaders::shader! {
+        ty: "intersection",
+        path: "src/shaders/rint_volume.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod rchit_volume {
+    vulkano_shaders::shader! {
+        ty: "closesthit",
+        path: "src/shaders/rchit_volume.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod rmiss {
+    vulkano_shaders::shader! {
+        ty: "miss",
+        path: "src/shaders/rmiss.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod srmiss {
+    vulkano_shaders::shader! {
+        ty: "miss",
+        path: "src/shaders/srmiss.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod rquery {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/rquery.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod software {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/software.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::{Integrator, PushConstants, RenderSettings};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::{Device, DeviceExtensions, DeviceFeatures};
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::ray_tracing::{
+    RayTracingPipeline, RayTracingPipelineCreateInfo, RayTracingShaderGroupCreateInfo,
+    ShaderBindingTable,
+};
+use vulkano::pipeline::{
+    ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
+};
+use vulkano::shader::{ShaderModule, SpecializationConstant, SpecializedShaderModule};
+
+// Only the raygen shader traces rays, bounces are iterated there
+const RAY_RECURSION_DEPTH: u32 = 1;
+
+// Workgroup size of the compute backends in each dimension
+const GROUP_SIZE: u32 = 8;
+
+// Ordered from the fastest to the most widely supported
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, clap::ValueEnum)]
+pub enum Backend {
+    /// Ray tracing pipeline with hardware traversal (VK_KHR_ray_tracing_pipeline)
+    Pipeline,
+    /// Compute shader with ray queries against the same acceleration structures (VK_KHR_ray_query)
+    RayQuery,
+    /// Compute shader walking a BVH built on the CPU, needs no ray tracing support
+    Software,
+}
+
+impl Backend {
+    pub const ALL: [Self; 3] = [Self::Pipeline, Self::RayQuery, Self::Software];
+
+    pub fn device_extensions(self) -> DeviceExtensions {
+        let acceleration_structures = DeviceExtensions {
+            khr_acceleration_structure: true,
+            khr_deferred_host_operations: true,
+            khr_buffer_device_address: true,
+            khr_spirv_1_4: true,
+            khr_shader_float_controls: true,
+            ..DeviceExtensions::empty()
+        };
+        match self {
+            Self::Pipeline => DeviceExtensions {
+                khr_ray_tracing_pipeline: true,
+                khr_ray_tracing_position_fetch: true,
+                ..acceleration_structures
+            },
+            Self::RayQuery => DeviceExtensions {
+                khr_ray_query: true,
+                ..acceleration_structures
+            },
+            Self::Software => DeviceExtensions::empty(),
+        }
+    }
+
+    pub fn device_features(self) -> DeviceFeatures {
+        match self {
+            Self::Pipeline => DeviceFeatures {
+                ray_tracing_pipeline: true,
+                acceleration_structure: true,
+                buffer_device_address: true,
+                ray_tracing_position_fetch: true,
+                ..Default::default()
+            },
+            Self::RayQuery => DeviceFeatures {
+                ray_query: true,
+                acceleration_structure: true,
+                buffer_device_address: true,
+                ..Default::default()
+            },
+            Self::Software => DeviceFeatures::default(),
+        }
+    }
+
+    pub fn is_supported(self, physical_device: &PhysicalDevice) -> bool {
+        physical_device.supported_extensions().contains(&self.device_extensions())
+            && physical_device.supported_features().contains(&self.device_features())
+    }
+
+    // The first one whose extensions the device was created with, create_device enables only
+    // those of the chosen backend
+    pub fn enabled_on(device: &Device) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|backend| device.enabled_extensions().contains(&backend.device_extensions()))
+            .unwrap_or(Self::Software)
+    }
+
+    // The software backend uploads its own BVH instead
+    pub fn uses_acceleration_structures(self) -> bool {
+        self != Self::Software
+    }
+}
+
+// Shaders are specialized for the integrator, AOVs and working space, switching any of them
+// requires a new pipeline
+fn specialize(module: Arc<ShaderModule>, integrator: Integrator, settings: &RenderSettings) -> Result<Arc<SpecializedShaderModule>> {
+    module
+        .specialize(
+            [
+                (0, SpecializationConstant::Bool(integrator == Integrator::Spectral)),
+                (1, SpecializationConstant::Bool(settings.aovs)),
+                (2, SpecializationConstant::U32(settings.working_space as u32)),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .context("Failed to specialize tracing shader module")
+}
+
+fn create_raytracing_pipeline(
+    device: Arc<Device>,
+    integrator: Integrator,
+    settings: &RenderSettings,
+) -> Result<Arc<RayTracingPipeline>> {
+    let raygen = specialize(
+        rgen::load(device.clone()).context("Failed to load raygen shader module")?,
+        integrator,
+        settings,
+    )?
+    .entry_point("main")
+    .context("Failed to set entry point")?;
+
+    let closest_hit = rchit::load(device.clone())
+        .context("Failed to load closest hit shader module")?
+        .entry_point("main")
+        .context("Failed to set entry point")?;
+
+    let miss = rmiss::load(device.clone())
+        .context("Failed to load miss shader module")?
+        .entry_point("main")
+        .context("Failed to set entry point")?;
+
+    let shadow_miss = srmiss::load(device.clone())
+        .context("Failed to load shadow miss shader module")?
+        .entry_point("main")
+        .context("Failed to set entry point")?;
+
+    let volume_intersection = rint_volume::load(device.clone())
+        .context("Failed to load volume intersection shader module")?
+        .entry_point("main")
+        .context("Failed to set entry point")?;
+
+    let volume_closest_hit = rchit_volume::load(device.clone())
+        .context("Failed to load volume closest hit shader module")?
+        .entry_point("main")
+        .context("Failed to set entry point")?;
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(raygen),
+        PipelineShaderStageCreateInfo::new(miss),
+        PipelineShaderStageCreateInfo::new(closest_hit),
+        PipelineShaderStageCreateInfo::new(shadow_miss),
+        PipelineShaderStageCreateInfo::new(volume_intersection),
+        PipelineShaderStageCreateInfo::new(volume_closest_hit),
+    ];
+
+    // Hit groups are recorded in order, matching the offsets set in SceneBuffers
+    let groups = [
+        RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
+        RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
+        RayTracingShaderGroupCreateInfo::TrianglesHit {
+            closest_hit_shader: Some(2),
+            any_hit_shader: None,
+        },
+        RayTracingShaderGroupCreateInfo::General { general_shader: 3 },
+        RayTracingShaderGroupCreateInfo::ProceduralHit {
+            closest_hit_shader: Some(5),
+            any_hit_shader: None,
+            intersection_shader: 4,
+        },
+    ];
+
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(device.clone())
+            .context("Failed to create pipeline layout")?,
+    )
+    .context("Failed to create pipeline layout")?;
+
+    RayTracingPipeline::new(
+        device.clone(),
+        None,
+        RayTracingPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            groups: groups.into_iter().collect(),
+            max_pipeline_ray_recursion_depth: RAY_RECURSION_DEPTH,
+            ..RayTracingPipelineCreateInfo::layout(layout)
+        },
+    )
+    .context("Failed to create raytracing pipeline")
+}
+
+fn create_tracing_compute_pipeline(device: Arc<Device>, module: Arc<SpecializedShaderModule>) -> Result<Arc<ComputePipeline>> {
+    let stage = PipelineShaderStageCreateInfo::new(module.entry_point("main").context("Failed to set entry point")?);
+
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+            .into_pipeline_layout_create_info(device.clone())
+            .context("Failed to create pipeline layout")?,
+    )
+    .context("Failed to create pipeline layout")?;
+
+    ComputePipeline::new(device, None, ComputePipelineCreateInfo::stage_layout(stage, layout))
+        .context("Failed to create tracing compute pipeline")
+}
+
+pub enum TracePipeline {
+    RayTracing {
+        pipeline: Arc<RayTracingPipeline>,
+        shader_binding_table: Arc<ShaderBindingTable>,
+    },
+    Compute(Arc<ComputePipeline>),
+}
+
+impl TracePipeline {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        backend: Backend,
+        integrator: Integrator,
+        settings: &RenderSettings,
+    ) -> Result<Self> {
+        let module = match backend {
+            Backend::Pipeline => {
+                let pipeline = create_raytracing_pipeline(device, integrator, settings)?;
+                let shader_binding_table = Arc::new(
+                    ShaderBindingTable::new(memory_allocator, &pipeline)
+                        .context("Failed to create shader binding table")?,
+                );
+                return Ok(Self::RayTracing {
+                    pipeline,
+                    shader_binding_table,
+                });
+            }
+            Backend::RayQuery => rquery::load(device.clone()).context("Failed to load ray query shader module")?,
+            Backend::Software => {
+                software::load(device.clone()).context("Failed to load software tracing shader module")?
+            }
+        };
+        let pipeline = create_tracing_compute_pipeline(device, specialize(module, integrator, settings)?)?;
+        Ok(Self::Compute(pipeline))
+    }
+
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        match self {
+            Self::RayTracing { pipeline, .. } => pipeline.layout(),
+            Self::Compute(pipeline) => pipeline.layout(),
+        }
+    }
+
+    // Traces one sample for every pixel of extent
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        descriptor_set: Arc<DescriptorSet>,
+        push_constants: PushConstants,
+        extent: [u32; 3],
+    ) -> Result<()> {
+        match self {
+            Self::RayTracing {
+                pipeline,
+                shader_binding_table,
+            } => {
+                builder
+                    .bind_pipeline_ray_tracing(pipeline.clone())
+                    .context("Failed to bind raytracing pipeline")?
+                    .bind_descriptor_sets(PipelineBindPoint::RayTracing, pipeline.layout().clone(), 0, descriptor_set)
+                    .context("Failed to bind descriptor sets")?
+                    .push_constants(pipeline.layout().clone(), 0, push_constants)
+                    .context("Failed to push constants")?;
+
+                unsafe {
+                    builder
+                        .trace_rays(shader_binding_table.addresses().clone(), extent)
+                        .context("Failed to record trace rays command")?;
+                }
+            }
+            Self::Compute(pipeline) => {
+                builder
+                    .bind_pipeline_compute(pipeline.clone())
+                    .context("Failed to bind tracing compute pipeline")?
+                    .bind_descriptor_sets(PipelineBindPoint::Compute, pipeline.layout().clone(), 0, descriptor_set)
+                    .context("Failed to bind descriptor sets")?
+                    .push_constants(pipeline.layout().clone(), 0, push_constants)
+                    .context("Failed to push constants")?;
+
+                unsafe {
+                    builder
+                        .dispatch([extent[0].div_ceil(GROUP_SIZE), extent[1].div_ceil(GROUP_SIZE), 1])
+                        .context("Failed to record tracing dispatch")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
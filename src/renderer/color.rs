@@ -0,0 +1,139 @@
+// Colour spaces of the accumulation and encodings of the displayed image, see color.glsl.
+// Scene colours, AOVs and saved images are linear Rec. 709 regardless of the working space.
+
+use crate::screenshot::srgb_oetf;
+use glam::{Mat3, Vec3};
+
+// Primaries the RGB integrator multiplies colours in, values match the COLOR_SPACE_ defines
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum WorkingSpace {
+    /// Linear sRGB primaries
+    Rec709 = 0,
+    /// ACES AP1 primaries, closer to spectral results for saturated colours
+    Acescg = 1,
+}
+
+// Colour space held by the accumulation image
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    Rec709 = 0,
+    Acescg = 1,
+    // CIE XYZ, accumulated by the spectral integrator
+    Xyz = 2,
+}
+
+impl From<WorkingSpace> for ColorSpace {
+    fn from(space: WorkingSpace) -> Self {
+        match space {
+            WorkingSpace::Rec709 => Self::Rec709,
+            WorkingSpace::Acescg => Self::Acescg,
+        }
+    }
+}
+
+impl ColorSpace {
+    pub fn to_rec709(self, color: Vec3) -> Vec3 {
+        match self {
+            Self::Rec709 => color,
+            Self::Acescg => ACESCG_TO_REC709 * color,
+            Self::Xyz => xyz_to_linear_srgb(color),
+        }
+    }
+}
+
+// Swapchain colour space requested on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum DisplayMode {
+    /// sRGB transfer function and primaries
+    Sdr,
+    /// Extended linear sRGB in half floats, values above 1 are brighter than SDR white
+    Scrgb,
+    /// Rec. 2020 primaries with the PQ transfer function
+    Hdr10,
+}
+
+// Encoding the post-process pass writes for the output image, transfer values match the
+// OUTPUT_ defines. HDR encodings place 1.0 of the tonemapped image at paper_white nits.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputEncoding {
+    // Linear Rec. 709, encoded by an sRGB swapchain format or when saving
+    Linear,
+    // sRGB transfer function applied in the shader, for UNORM swapchain formats
+    Srgb,
+    Scrgb { paper_white: f32 },
+    Hdr10 { paper_white: f32 },
+}
+
+impl OutputEncoding {
+    pub fn transfer(self) -> u32 {
+        match self {
+            Self::Linear => 0,
+            Self::Srgb => 1,
+            Self::Scrgb { .. } => 2,
+            Self::Hdr10 { .. } => 3,
+        }
+    }
+
+    pub fn paper_white(self) -> f32 {
+        match self {
+            Self::Scrgb { paper_white } | Self::Hdr10 { paper_white } => paper_white,
+            Self::Linear | Self::Srgb => 80.0,
+        }
+    }
+
+    // Same as encode_output in color.glsl, for colours drawn on the output without the shader
+    pub fn encode(self, color: Vec3) -> Vec3 {
+        match self {
+            Self::Linear => color,
+            Self::Srgb => color.map(srgb_oetf),
+            // 1.0 is 80 nits in scRGB
+            Self::Scrgb { paper_white } => color * (paper_white / 80.0),
+            Self::Hdr10 { paper_white } => {
+                (REC709_TO_REC2020 * color.max(Vec3::ZERO) * (paper_white / 10000.0)).map(pq_oetf)
+            }
+        }
+    }
+}
+
+// Same matrix as REC709_TO_REC2020 in color.glsl
+const REC709_TO_REC2020: Mat3 = Mat3::from_cols_array(&[
+    0.6274040, 0.0690970, 0.0163916,
+    0.3292820, 0.9195400, 0.0880132,
+    0.0433136, 0.0113612, 0.8955950,
+]);
+
+// Same curve as pq_oetf in color.glsl, from luminance in units of 10000 nits
+fn pq_oetf(luminance: f32) -> f32 {
+    const M1: f32 = 0.1593017578125;
+    const M2: f32 = 78.84375;
+    const C1: f32 = 0.8359375;
+    const C2: f32 = 18.8515625;
+    const C3: f32 = 18.6875;
+
+    let y = luminance.clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+// Conversion applied by the post-process pass, from the accumulation to the output image
+#[derive(Clone, Copy, Debug)]
+pub struct ColorTransform {
+    pub input: ColorSpace,
+    pub output: OutputEncoding,
+}
+
+// Same matrix as ACESCG_TO_REC709 in color.glsl
+const ACESCG_TO_REC709: Mat3 = Mat3::from_cols_array(&[
+    1.7048587, -0.13007683, -0.023964073,
+    -0.621716, 1.1407357, -0.12897551,
+    -0.08314265, -0.010658951, 1.1529396,
+]);
+
+// Same conversion as xyz_to_linear_srgb in spectrum.glsl
+fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    const XYZ_TO_SRGB: Mat3 = Mat3::from_cols_array(&[
+        3.2404542, -0.969266, 0.0556434,
+        -1.5371385, 1.8760108, -0.2040259,
+        -0.4985314, 0.0415560, 1.0572252,
+    ]);
+    XYZ_TO_SRGB * (xyz * Vec3::new(0.95047, 1.0, 1.08883))
+}
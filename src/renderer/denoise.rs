@@ -0,0 +1,374 @@
+// Edge-avoiding à-trous wavelet denoiser run between tracing and tonemapping, guided by the
+// albedo, normal and depth AOVs. The accumulation stays untouched, the filter is rerun every
+// frame and tightens as more samples arrive.
+
+mod atrous {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/atrous.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::aov::AovImages;
+use super::color::ColorSpace;
+use super::tonemap::{create_compute_pipeline, descriptor_set};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+
+const GROUP_SIZE: u32 = 16;
+
+// Step widths 1, 2, 4, 8 and 16 pixels, a 125 pixel wide footprint
+const ITERATIONS: u32 = 5;
+
+// Filter parameters of one iteration, see atrous.glsl
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable, Debug)]
+struct AtrousPushConstants {
+    step_width: i32,
+    color_sigma: f32,
+    normal_power: f32,
+    depth_sigma: f32,
+    albedo_sigma: f32,
+    input_space: u32,
+}
+
+impl AtrousPushConstants {
+    // The colour tolerance follows the noise, which falls with the square root of the sample
+    // count, and halves every iteration as the coarser levels are already smoother
+    fn new(iteration: u32, samples: u32, input_space: ColorSpace) -> Self {
+        Self {
+            step_width: 1 << iteration,
+            color_sigma: 1.0 / ((samples.max(1) as f32).sqrt() * (1 << iteration) as f32),
+            normal_power: 64.0,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+            input_space: input_space as u32,
+        }
+    }
+}
+
+fn create_filter_image(memory_allocator: Arc<StandardMemoryAllocator>, extent: [u32; 3]) -> Result<Arc<ImageView>> {
+    ImageView::new_default(
+        Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32G32B32A32_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create denoiser image")?,
+    )
+    .context("Failed to create image view for denoiser image")
+}
+
+pub struct DenoisePass {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline: Arc<ComputePipeline>,
+    // Ping-pong targets of the iterations, allocated on first use
+    images: Option<[Arc<ImageView>; 2]>,
+}
+
+impl DenoisePass {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self> {
+        let pipeline = create_compute_pipeline(
+            device.clone(),
+            atrous::load(device).context("Failed to load denoiser shader module")?,
+        )?;
+
+        Ok(Self {
+            memory_allocator,
+            descriptor_set_allocator,
+            pipeline,
+            images: None,
+        })
+    }
+
+    // Frees the filter images, they are recreated at the new size when next used
+    pub fn release(&mut self) {
+        self.images = None;
+    }
+
+    // Filters the radiance of samples accumulated samples, returns the image holding the result
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        radiance: Arc<ImageView>,
+        aov_images: &AovImages,
+        samples: u32,
+        input_space: ColorSpace,
+    ) -> Result<Arc<ImageView>> {
+        let extent = radiance.image().extent();
+        let images = match &self.images {
+            Some(images) if images[0].image().extent() == extent => images.clone(),
+            _ => {
+                let images = [
+                    create_filter_image(self.memory_allocator.clone(), extent)?,
+                    create_filter_image(self.memory_allocator.clone(), extent)?,
+                ];
+                self.images = Some(images.clone());
+                images
+            }
+        };
+
+        let group_counts = [extent[0].div_ceil(GROUP_SIZE), extent[1].div_ceil(GROUP_SIZE), 1];
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("Failed to bind denoiser pipeline")?;
+
+        let mut input = radiance;
+        for iteration in 0..ITERATIONS {
+            let output = images[iteration as usize % 2].clone();
+            let set = descriptor_set(
+                &self.descriptor_set_allocator,
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::image_view(0, input),
+                    WriteDescriptorSet::image_view(1, output.clone()),
+                    WriteDescriptorSet::image_view(2, aov_images.albedo.clone()),
+                    WriteDescriptorSet::image_view(3, aov_images.normal.clone()),
+                    WriteDescriptorSet::image_view(4, aov_images.depth.clone()),
+                ],
+            )?;
+
+            builder
+                .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set)
+                .context("Failed to bind descriptor sets")?
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    AtrousPushConstants::new(iteration, samples, input_space),
+                )
+                .context("Failed to push constants")?;
+
+            unsafe {
+                builder
+                    .dispatch(group_counts)
+                    .context("Failed to record denoiser dispatch")?;
+            }
+            input = output;
+        }
+
+        Ok(input)
+    }
+}
+
+// CPU reference of atrous.glsl for testing the filter weights
+#[cfg(test)]
+mod reference {
+    use super::{AtrousPushConstants, ITERATIONS};
+    use crate::renderer::color::ColorSpace;
+    use glam::{Vec2, Vec3};
+
+    const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    #[derive(Clone, Copy)]
+    pub struct Guide {
+        pub color: Vec3,
+        pub albedo: Vec3,
+        pub normal: Vec3,
+        pub depth: f32,
+    }
+
+    fn luminance(color: Vec3) -> f32 {
+        color.dot(Vec3::new(0.2126, 0.7152, 0.0722)).max(0.0)
+    }
+
+    pub fn edge_weight(center: &Guide, sample: &Guide, distance: f32, pc: &AtrousPushConstants) -> f32 {
+        if sample.depth <= 0.0 {
+            return 0.0;
+        }
+
+        let w_normal = center.normal.dot(sample.normal).max(0.0).powf(pc.normal_power);
+        let w_depth =
+            (-(center.depth - sample.depth).abs() / (pc.depth_sigma * center.depth * distance + 1e-6)).exp();
+        let w_albedo = (-center.albedo.distance_squared(sample.albedo) / (pc.albedo_sigma * pc.albedo_sigma)).exp();
+
+        let scale = pc.color_sigma * (luminance(center.color) + luminance(sample.color));
+        let w_color = (-center.color.distance_squared(sample.color) / (scale * scale + 1e-6)).exp();
+
+        w_normal * w_depth * w_albedo * w_color
+    }
+
+    fn iterate(pixels: &[Guide], width: usize, height: usize, pc: &AtrousPushConstants) -> Vec<Guide> {
+        let step = pc.step_width as isize;
+        let mut output = pixels.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                let center = &pixels[y * width + x];
+                if center.depth <= 0.0 {
+                    continue;
+                }
+
+                let mut sum = Vec3::ZERO;
+                let mut weight_sum = 0.0;
+                for dy in -2..=2isize {
+                    for dx in -2..=2isize {
+                        let (qx, qy) = (x as isize + dx * step, y as isize + dy * step);
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let sample = &pixels[qy as usize * width + qx as usize];
+                        let distance = Vec2::new((dx * step) as f32, (dy * step) as f32).length();
+                        let weight = KERNEL[dx.unsigned_abs()]
+                            * KERNEL[dy.unsigned_abs()]
+                            * edge_weight(center, sample, distance, pc);
+                        sum += sample.color * weight;
+                        weight_sum += weight;
+                    }
+                }
+                output[y * width + x].color = sum / weight_sum;
+            }
+        }
+        output
+    }
+
+    pub fn denoise(pixels: &[Guide], width: usize, height: usize, samples: u32) -> Vec<Vec3> {
+        let mut pixels = pixels.to_vec();
+        for iteration in 0..ITERATIONS {
+            let pc = AtrousPushConstants::new(iteration, samples, ColorSpace::Rec709);
+            pixels = iterate(&pixels, width, height, &pc);
+        }
+        pixels.iter().map(|pixel| pixel.color).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reference::{Guide, denoise, edge_weight};
+    use super::AtrousPushConstants;
+    use crate::renderer::color::ColorSpace;
+    use glam::Vec3;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 32;
+
+    fn plane(color: Vec3) -> Guide {
+        Guide {
+            color,
+            albedo: Vec3::splat(0.5),
+            normal: Vec3::Z,
+            depth: 4.0,
+        }
+    }
+
+    // Deterministic noise in [0, 2), averaging to 1
+    fn noise(index: usize) -> f32 {
+        let mut state = (index as u32).wrapping_mul(747796405).wrapping_add(2891336453);
+        state = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+        state = (state >> 22) ^ state;
+        state as f32 / u32::MAX as f32 * 2.0
+    }
+
+    fn pc(samples: u32) -> AtrousPushConstants {
+        AtrousPushConstants::new(0, samples, ColorSpace::Rec709)
+    }
+
+    #[test]
+    fn identical_pixels_have_full_weight() {
+        let pixel = plane(Vec3::splat(0.3));
+        assert!((edge_weight(&pixel, &pixel, 0.0, &pc(1)) - 1.0).abs() < 1e-6);
+        assert!((edge_weight(&pixel, &pixel, 2.0, &pc(1)) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn weights_stop_at_edges() {
+        let center = plane(Vec3::ONE);
+        let pc = pc(1);
+
+        let facing_away = Guide { normal: -Vec3::Z, ..center };
+        assert_eq!(edge_weight(&center, &facing_away, 1.0, &pc), 0.0);
+
+        let behind = Guide { depth: 8.0, ..center };
+        assert!(edge_weight(&center, &behind, 1.0, &pc) < 1e-6);
+
+        let other_material = Guide { albedo: Vec3::new(0.9, 0.1, 0.1), ..center };
+        assert!(edge_weight(&center, &other_material, 1.0, &pc) < 1e-6);
+
+        let background = Guide { depth: 0.0, ..center };
+        assert_eq!(edge_weight(&center, &background, 1.0, &pc), 0.0);
+    }
+
+    #[test]
+    fn color_tolerance_tightens_with_samples() {
+        let center = plane(Vec3::splat(1.0));
+        let brighter = plane(Vec3::splat(1.5));
+        let noisy = edge_weight(&center, &brighter, 1.0, &pc(1));
+        let converged = edge_weight(&center, &brighter, 1.0, &pc(256));
+        assert!(noisy > 0.5);
+        assert!(converged < 1e-6);
+    }
+
+    #[test]
+    fn constant_image_is_unchanged() {
+        let pixels = vec![plane(Vec3::new(0.2, 0.4, 0.8)); WIDTH * HEIGHT];
+        for color in denoise(&pixels, WIDTH, HEIGHT, 1) {
+            assert!(color.abs_diff_eq(Vec3::new(0.2, 0.4, 0.8), 1e-5));
+        }
+    }
+
+    #[test]
+    fn background_is_passed_through() {
+        let mut pixels = vec![plane(Vec3::ONE); WIDTH * HEIGHT];
+        pixels[0] = Guide { color: Vec3::new(5.0, 0.0, 0.0), depth: 0.0, ..pixels[0] };
+        assert_eq!(denoise(&pixels, WIDTH, HEIGHT, 1)[0], Vec3::new(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reduces_noise_and_keeps_edges() {
+        // Left half dark, right half bright, told apart by albedo
+        let pixels: Vec<Guide> = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let albedo = if i % WIDTH < WIDTH / 2 { 0.1 } else { 0.8 };
+                Guide {
+                    color: Vec3::splat(albedo * noise(i)),
+                    albedo: Vec3::splat(albedo),
+                    normal: Vec3::Z,
+                    depth: 4.0,
+                }
+            })
+            .collect();
+
+        let denoised = denoise(&pixels, WIDTH, HEIGHT, 1);
+
+        let error = |colors: &mut dyn Iterator<Item = (usize, f32)>| {
+            colors
+                .map(|(i, value)| {
+                    let expected = if i % WIDTH < WIDTH / 2 { 0.1 } else { 0.8 };
+                    (value - expected).powi(2)
+                })
+                .sum::<f32>()
+                / (WIDTH * HEIGHT) as f32
+        };
+        let noisy_error = error(&mut pixels.iter().map(|p| p.color.x).enumerate());
+        let denoised_error = error(&mut denoised.iter().map(|c| c.x).enumerate());
+        assert!(denoised_error < noisy_error * 0.25, "{denoised_error} >= {noisy_error} / 4");
+
+        // No light bleeds across the albedo edge
+        let row = HEIGHT / 2 * WIDTH;
+        assert!(denoised[row + WIDTH / 2 - 1].x < 0.2);
+        assert!(denoised[row + WIDTH / 2].x > 0.5);
+    }
+}
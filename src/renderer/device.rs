@@ -0,0 +1,256 @@
+// Physical device selection and the capability report of --list-devices. Devices are chosen
+// by index or name, every device that can not be used is reported with the reason.
+
+use super::Backend;
+use crate::scene::TIME_SLICES;
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
+use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, DeviceFeatures, Queue, QueueCreateInfo, QueueFlags};
+use vulkano::instance::Instance;
+use vulkano::memory::MemoryHeapFlags;
+use vulkano::swapchain::Surface;
+
+// Index in enumeration order, anything else is matched against the device names
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DeviceSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        if selector.trim().is_empty() {
+            return Err("device name is empty".to_owned());
+        }
+        Ok(selector
+            .parse()
+            .map_or_else(|_| Self::Name(selector.to_owned()), Self::Index))
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "device {index}"),
+            Self::Name(name) => write!(f, "a device named '{name}'"),
+        }
+    }
+}
+
+impl DeviceSelector {
+    // Names match case-insensitively on any part, "rtx" picks "NVIDIA GeForce RTX 4070"
+    fn matches(&self, index: usize, physical_device: &PhysicalDevice) -> bool {
+        match self {
+            Self::Index(requested) => *requested == index,
+            Self::Name(name) => physical_device
+                .properties()
+                .device_name
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+        }
+    }
+}
+
+// Extensions and features the device lacks for a backend
+fn missing_support(backend: Backend, physical_device: &PhysicalDevice) -> String {
+    let extensions = backend.device_extensions().difference(physical_device.supported_extensions());
+    let features = backend.device_features().difference(physical_device.supported_features());
+    match (extensions.is_empty(), features == DeviceFeatures::empty()) {
+        (false, false) => format!("extensions {extensions:?} and features {features:?}"),
+        (false, true) => format!("extensions {extensions:?}"),
+        _ => format!("features {features:?}"),
+    }
+}
+
+// Queue family and backend to use on a device, or why it can not render
+fn check_device(
+    physical_device: &PhysicalDevice,
+    surface: Option<&Arc<Surface>>,
+    backend: Option<Backend>,
+) -> Result<(u32, Backend), String> {
+    if surface.is_some() && !physical_device.supported_extensions().khr_swapchain {
+        return Err("VK_KHR_swapchain is not supported".to_owned());
+    }
+
+    let backend = match backend {
+        Some(backend) if !backend.is_supported(physical_device) => {
+            return Err(format!(
+                "the {backend:?} backend needs the unsupported {}",
+                missing_support(backend, physical_device)
+            ));
+        }
+        Some(backend) => backend,
+        None => Backend::ALL
+            .into_iter()
+            .find(|backend| backend.is_supported(physical_device))
+            .ok_or("no backend is supported")?,
+    };
+
+    // Every pass is a compute or ray tracing dispatch, the window also needs blits
+    let required_flags = if surface.is_some() {
+        QueueFlags::GRAPHICS | QueueFlags::COMPUTE
+    } else {
+        QueueFlags::COMPUTE
+    };
+    let queue_family_index = physical_device
+        .queue_family_properties()
+        .iter()
+        .enumerate()
+        .position(|(i, q)| {
+            q.queue_flags.contains(required_flags)
+                && surface.is_none_or(|surface| physical_device.surface_support(i as u32, surface).unwrap_or(false))
+        })
+        .ok_or(if surface.is_some() {
+            "no queue family supports graphics, compute and presentation to the window"
+        } else {
+            "no queue family supports compute"
+        })?;
+
+    Ok((queue_family_index as u32, backend))
+}
+
+fn device_type_rank(physical_device: &PhysicalDevice) -> u32 {
+    match physical_device.properties().device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        _ => 4,
+    }
+}
+
+// Picks the selected or the most capable device, able to present to the surface when one is
+// given, and the fastest backend it supports unless one is requested
+pub fn create_device(
+    instance: &Arc<Instance>,
+    surface: Option<&Arc<Surface>>,
+    selector: Option<&DeviceSelector>,
+    backend: Option<Backend>,
+) -> Result<(Arc<Device>, Arc<Queue>)> {
+    let physical_devices: Vec<_> = instance
+        .enumerate_physical_devices()
+        .context("Failed to enumerate physical devices")?
+        .enumerate()
+        .filter(|(index, p)| selector.is_none_or(|selector| selector.matches(*index, p)))
+        .collect();
+
+    if let Some(selector) = selector
+        && physical_devices.is_empty()
+    {
+        bail!("Found no {selector}, run with --list-devices to see the available ones");
+    }
+
+    let mut candidates = Vec::new();
+    let mut rejections = Vec::new();
+    for (index, p) in physical_devices {
+        match check_device(&p, surface, backend) {
+            Ok((queue_family_index, backend)) => candidates.push((p, queue_family_index, backend)),
+            Err(reason) => rejections.push(format!("Device {index} ({}): {reason}", p.properties().device_name)),
+        }
+    }
+
+    let Some((physical_device, queue_family_index, backend)) = candidates
+        .into_iter()
+        .min_by_key(|(p, _, backend)| (device_type_rank(p), *backend))
+    else {
+        bail!("No suitable device found\n  {}", rejections.join("\n  "));
+    };
+    for rejection in &rejections {
+        println!("Skipped {rejection}");
+    }
+
+    // vulkano has no motion instance acceleration structures yet, moving instances are
+    // placed once per time slice instead
+    if backend.uses_acceleration_structures() && physical_device.supported_extensions().nv_ray_tracing_motion_blur {
+        println!(
+            "VK_NV_ray_tracing_motion_blur is supported but not exposed by vulkano, using {TIME_SLICES} time slices for instance motion"
+        );
+    }
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            enabled_extensions: DeviceExtensions {
+                khr_swapchain: surface.is_some(),
+                ..backend.device_extensions()
+            },
+            enabled_features: backend.device_features(),
+            ..Default::default()
+        },
+    )
+    .context("Failed to create logical device")?;
+
+    let queue = queues
+        .next()
+        .context("Failed to extract first queue out of queues")?;
+
+    Ok((device, queue))
+}
+
+fn limit(value: Option<impl fmt::Display>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+// Every device with the limits that matter for ray tracing, the output of --list-devices
+pub fn print_devices(instance: &Arc<Instance>) -> Result<()> {
+    let physical_devices = instance
+        .enumerate_physical_devices()
+        .context("Failed to enumerate physical devices")?;
+
+    for (index, p) in physical_devices.enumerate() {
+        let properties = p.properties();
+        println!(
+            "Device {index}: {} ({:?}), Vulkan {}, driver {}",
+            properties.device_name,
+            properties.device_type,
+            properties.api_version,
+            properties.driver_info.as_deref().unwrap_or("unknown"),
+        );
+
+        for backend in Backend::ALL {
+            if backend.is_supported(&p) {
+                println!("  {backend:?} backend: supported");
+            } else {
+                println!("  {backend:?} backend: missing {}", missing_support(backend, &p));
+            }
+        }
+
+        println!(
+            "  Ray tracing pipeline: max recursion depth {}, shader group handle size {}, max dispatch invocations {}",
+            limit(properties.max_ray_recursion_depth),
+            limit(properties.shader_group_handle_size),
+            limit(properties.max_ray_dispatch_invocation_count),
+        );
+        println!(
+            "  Acceleration structures: max geometries {}, max instances {}, max primitives {}, max per descriptor set {}",
+            limit(properties.max_geometry_count),
+            limit(properties.max_instance_count),
+            limit(properties.max_primitive_count),
+            limit(properties.max_descriptor_set_acceleration_structures),
+        );
+
+        for (heap, memory_heap) in p.memory_properties().memory_heaps.iter().enumerate() {
+            let local = if memory_heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL) {
+                ", device local"
+            } else {
+                ""
+            };
+            println!(
+                "  Memory heap {heap}: {:.1} GiB{local}",
+                memory_heap.size as f64 / (1u64 << 30) as f64
+            );
+        }
+    }
+
+    Ok(())
+}
+
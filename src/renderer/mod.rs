@@ -0,0 +1,820 @@
+// Device side of the path tracer: tracing backend, scene, camera and accumulation.
+// Presenting to a window and writing to disk both record their frames through Renderer.
+
+mod adaptive;
+mod aov;
+mod backend;
+mod color;
+mod denoise;
+mod device;
+mod taa;
+mod timing;
+mod tonemap;
+mod upscale;
+
+pub use adaptive::AdaptiveSampling;
+pub use aov::DebugView;
+pub use backend::Backend;
+pub use color::{DisplayMode, OutputEncoding, WorkingSpace};
+pub use device::{DeviceSelector, create_device, print_devices};
+pub use taa::jitter;
+pub use timing::GpuTimings;
+pub use tonemap::Tonemapper;
+
+use adaptive::AdaptiveSampler;
+use aov::{AovImages, split_channels};
+use backend::TracePipeline;
+use color::{ColorSpace, ColorTransform};
+use denoise::DenoisePass;
+use taa::TaaPass;
+use timing::{GpuTimer, Timestamp};
+use tonemap::{TonemapInput, TonemapPass};
+use upscale::UpscalePass;
+use crate::camera::CameraUniform;
+use crate::scene::{Scene, SceneBuffers};
+use crate::screenshot::{Channel, OutputFormat, Samples, Screenshot};
+use anyhow::{Context, Result, bail};
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use half::f16;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::sync::{GpuFuture, now};
+
+// RGB integrates the three primaries directly, spectral carries three sampled wavelengths
+// per path and accumulates CIE XYZ
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Integrator {
+    Rgb,
+    Spectral,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub max_bounces: u32,
+    pub tonemapper: Tonemapper,
+    // Exposure adjustment in stops, applied before tonemapping and on top of auto exposure
+    pub exposure: f32,
+    pub auto_exposure: bool,
+    // Write first hit AOVs, needed for debug views and EXR layers
+    pub aovs: bool,
+    pub debug_view: DebugView,
+    // Primaries of the RGB integrator, the spectral integrator always accumulates CIE XYZ
+    pub working_space: WorkingSpace,
+    // Filter the displayed image guided by the AOVs, which it turns on
+    pub denoise: bool,
+    // Reproject the previous frame along the motion AOV while the camera moves
+    pub taa: bool,
+    // Stop sampling tiles whose noise fell below the target
+    pub adaptive: Option<AdaptiveSampling>,
+    // Frames recorded before the oldest one has to finish, each with its own camera buffer
+    // and descriptor set
+    pub frames_in_flight: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants {
+    max_bounces: u32,
+    time: f32,
+    frame_index: u32,
+    light_count: u32,
+    atmosphere: i32,
+    seed: u32,
+    jitter: [f32; 2],
+    environment: [f32; 3],
+    adaptive: u32,
+}
+
+// Running average of all samples since the camera last moved
+fn create_accumulation_image(extent: [u32; 3], memory_allocator: Arc<StandardMemoryAllocator>) -> Result<Arc<ImageView>> {
+    ImageView::new_default(
+        Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32G32B32A32_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .context("Failed to create accumulation image")?,
+    )
+    .context("Failed to create image view for accumulation image")
+}
+
+// Resources a frame reads while later frames are recorded
+struct FrameResources {
+    camera_buffer: Subbuffer<CameraUniform>,
+    // Built on first use, dropped whenever one of the bound resources is replaced
+    descriptor_set: Option<Arc<DescriptorSet>>,
+}
+
+pub struct Renderer {
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    backend: Backend,
+    trace_pipeline: TracePipeline,
+    integrator: Integrator,
+    settings: RenderSettings,
+    output_encoding: OutputEncoding,
+    pub scene: Scene,
+    scene_buffers: SceneBuffers,
+    frames: Vec<FrameResources>,
+    frame_slot: usize,
+    last_camera_uniforms: CameraUniform,
+    accumulation_image: Arc<ImageView>,
+    aov_images: AovImages,
+    taa: TaaPass,
+    denoise: DenoisePass,
+    tonemap: TonemapPass,
+    adaptive: AdaptiveSampler,
+    upscale: UpscalePass,
+    // None when the queue can not write timestamps
+    timer: Option<GpuTimer>,
+    frame_index: u32,
+    // Frames traced since creation, drives the ray jitter and random seeds
+    sequence_index: u32,
+    time: Instant,
+}
+
+impl Renderer {
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene: Scene,
+        integrator: Integrator,
+        settings: RenderSettings,
+        camera_uniforms: CameraUniform,
+        extent: [u32; 3],
+    ) -> Result<Self> {
+        let backend = Backend::enabled_on(&device);
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+
+        // The denoiser is guided by the AOVs, TAA follows the motion vectors
+        let settings = RenderSettings {
+            aovs: settings.aovs || settings.denoise || settings.taa,
+            ..settings
+        };
+        let trace_pipeline =
+            TracePipeline::new(device.clone(), memory_allocator.clone(), backend, integrator, &settings)?;
+
+        let scene_buffers = SceneBuffers::new(
+            &scene,
+            backend,
+            memory_allocator.clone(),
+            &command_buffer_allocator,
+            device.clone(),
+            queue.clone(),
+        )?;
+
+        let frames: Vec<FrameResources> = (0..settings.frames_in_flight.max(1))
+            .map(|_| {
+                let camera_buffer = Buffer::from_data(
+                    memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::UNIFORM_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    camera_uniforms,
+                )
+                .context("Failed to create camera buffer")?;
+                Ok(FrameResources {
+                    camera_buffer,
+                    descriptor_set: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let accumulation_image = create_accumulation_image(extent, memory_allocator.clone())?;
+        let aov_images = AovImages::new(memory_allocator.clone(), extent, settings.aovs)?;
+        let taa = TaaPass::new(device.clone(), memory_allocator.clone(), descriptor_set_allocator.clone())?;
+        let denoise = DenoisePass::new(device.clone(), memory_allocator.clone(), descriptor_set_allocator.clone())?;
+        let tonemap = TonemapPass::new(device.clone(), memory_allocator.clone(), descriptor_set_allocator.clone())?;
+        let adaptive = AdaptiveSampler::new(
+            device.clone(),
+            memory_allocator.clone(),
+            descriptor_set_allocator.clone(),
+            extent,
+        )?;
+        let upscale = UpscalePass::new(device.clone(), descriptor_set_allocator.clone())?;
+        let timer = GpuTimer::new(&queue, frames.len())?;
+
+        Ok(Self {
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            backend,
+            trace_pipeline,
+            integrator,
+            settings,
+            output_encoding: OutputEncoding::Linear,
+            scene,
+            scene_buffers,
+            frames,
+            frame_slot: 0,
+            last_camera_uniforms: camera_uniforms,
+            accumulation_image,
+            aov_images,
+            taa,
+            denoise,
+            tonemap,
+            adaptive,
+            upscale,
+            timer,
+            frame_index: 0,
+            sequence_index: 0,
+            time: Instant::now(),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) -> Result<()> {
+        self.recreate_pipeline(integrator, self.settings)?;
+        println!("Integrator: {integrator:?}");
+        Ok(())
+    }
+
+    fn recreate_pipeline(&mut self, integrator: Integrator, settings: RenderSettings) -> Result<()> {
+        self.trace_pipeline = TracePipeline::new(
+            self.device.clone(),
+            self.memory_allocator.clone(),
+            self.backend,
+            integrator,
+            &settings,
+        )?;
+        self.integrator = integrator;
+        self.settings = settings;
+        self.taa.release();
+        self.invalidate_descriptor_sets();
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    fn invalidate_descriptor_sets(&mut self) {
+        for frame in &mut self.frames {
+            frame.descriptor_set = None;
+        }
+    }
+
+    // Slot of the per-frame resources the next record uses, the caller has to wait for the
+    // frame last recorded in it before recording
+    pub fn frame_slot(&self) -> usize {
+        self.frame_slot
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn set_aovs(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.settings.aovs {
+            return Ok(());
+        }
+        let extent = self.accumulation_image.image().extent();
+        self.aov_images = AovImages::new(self.memory_allocator.clone(), extent, enabled)?;
+        self.recreate_pipeline(self.integrator, RenderSettings { aovs: enabled, ..self.settings })
+    }
+
+    pub fn set_working_space(&mut self, working_space: WorkingSpace) -> Result<()> {
+        if working_space == self.settings.working_space {
+            return Ok(());
+        }
+        self.recreate_pipeline(self.integrator, RenderSettings { working_space, ..self.settings })?;
+        println!("Working space: {working_space:?}");
+        Ok(())
+    }
+
+    // Has to match the format of the images passed to record
+    pub fn set_output_encoding(&mut self, encoding: OutputEncoding) {
+        self.output_encoding = encoding;
+    }
+
+    fn accumulation_space(&self) -> ColorSpace {
+        match self.integrator {
+            Integrator::Rgb => self.settings.working_space.into(),
+            Integrator::Spectral => ColorSpace::Xyz,
+        }
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    // Views of the first hit need the AOVs, they stay enabled afterwards
+    pub fn set_debug_view(&mut self, view: DebugView) -> Result<()> {
+        if !matches!(view, DebugView::Beauty | DebugView::Samples) {
+            self.set_aovs(true)?;
+        }
+        self.settings.debug_view = view;
+        println!("Debug view: {view:?}");
+        Ok(())
+    }
+
+    pub fn set_taa(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.set_aovs(true)?;
+        }
+        self.taa.release();
+        self.settings.taa = enabled;
+        println!("TAA: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    pub fn set_denoise(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.set_aovs(true)?;
+        } else {
+            self.denoise.release();
+        }
+        self.settings.denoise = enabled;
+        println!("Denoiser: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    // Display settings only affect the post-process pass and keep the accumulation
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.settings.tonemapper = tonemapper;
+        println!("Tonemapper: {tonemapper:?}");
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.settings.exposure = exposure;
+        println!("Exposure: {exposure:+.1} EV");
+    }
+
+    pub fn set_auto_exposure(&mut self, enabled: bool) {
+        self.settings.auto_exposure = enabled;
+        println!("Auto exposure: {}", if enabled { "on" } else { "off" });
+    }
+
+    // Seconds to the power of minus one, infinity adapts within a single frame
+    pub fn set_exposure_adaptation_rate(&mut self, rate: f32) {
+        self.tonemap.set_adaptation_rate(rate);
+    }
+
+    // Restarts accumulation whenever the camera changed, the uniforms are uploaded by record.
+    // Returns whether it changed
+    pub fn set_camera(&mut self, camera_uniforms: CameraUniform) -> bool {
+        if bytemuck::bytes_of(&camera_uniforms) == bytemuck::bytes_of(&self.last_camera_uniforms) {
+            return false;
+        }
+
+        self.last_camera_uniforms = camera_uniforms;
+        self.frame_index = 0;
+        true
+    }
+
+    pub fn resize(&mut self, extent: [u32; 3]) -> Result<()> {
+        self.accumulation_image = create_accumulation_image(extent, self.memory_allocator.clone())?;
+        self.aov_images = AovImages::new(self.memory_allocator.clone(), extent, self.settings.aovs)?;
+        self.adaptive.resize(extent)?;
+        self.taa.release();
+        self.denoise.release();
+        self.invalidate_descriptor_sets();
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    // Re-uploads the scene after it was edited
+    pub fn reload_scene(&mut self) -> Result<()> {
+        self.scene_buffers = SceneBuffers::new(
+            &self.scene,
+            self.backend,
+            self.memory_allocator.clone(),
+            &self.command_buffer_allocator,
+            self.device.clone(),
+            self.queue.clone(),
+        )?;
+        self.invalidate_descriptor_sets();
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    pub fn add_volume(&mut self, path: &Path) -> Result<()> {
+        self.scene.add_nanovdb_volume(path)?;
+        self.reload_scene()
+    }
+
+    // Traces one more sample per pixel into the accumulation image and writes the
+    // displayed result to output, which has to match the accumulation extent
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        output: Arc<ImageView>,
+    ) -> Result<()> {
+        let extent = self.accumulation_image.image().extent();
+        let slot = self.frame_slot;
+        self.frame_slot = (slot + 1) % self.frames.len();
+        self.write_timestamp(builder, slot, Timestamp::Start)?;
+
+        // The previous frame of this slot has finished, so its camera buffer is free again
+        *self.frames[slot]
+            .camera_buffer
+            .write()
+            .context("Failed to write to camera buffer")? = self.last_camera_uniforms;
+        let descriptor_set = match &self.frames[slot].descriptor_set {
+            Some(descriptor_set) => descriptor_set.clone(),
+            None => {
+                let descriptor_set = self.create_descriptor_set(slot)?;
+                self.frames[slot].descriptor_set = Some(descriptor_set.clone());
+                descriptor_set
+            }
+        };
+
+        let push_constants = PushConstants {
+            max_bounces: self.settings.max_bounces,
+            time: self.time.elapsed().as_secs_f32(),
+            frame_index: self.frame_index,
+            light_count: self.scene_buffers.light_count,
+            atmosphere: self.scene_buffers.atmosphere,
+            seed: self.sequence_index,
+            jitter: taa::jitter(self.sequence_index),
+            environment: self.scene_buffers.environment,
+            // The tile mask is only valid once every pixel received the minimum samples
+            adaptive: self
+                .settings
+                .adaptive
+                .is_some_and(|adaptive| self.frame_index >= adaptive.min_samples) as u32,
+        };
+
+        self.trace_pipeline.record(builder, descriptor_set, push_constants, extent)?;
+
+        if let Some(adaptive) = &self.settings.adaptive {
+            self.adaptive.record(builder, &self.aov_images, adaptive)?;
+        }
+
+        if self.settings.taa {
+            self.taa
+                .record(builder, self.accumulation_image.clone(), &self.aov_images, self.frame_index + 1)?;
+        }
+        self.write_timestamp(builder, slot, Timestamp::Trace)?;
+
+        self.frame_index += 1;
+        self.sequence_index = self.sequence_index.wrapping_add(1);
+        self.record_display(builder, output, self.output_encoding, Some(slot))
+    }
+
+    // Ends the timing of the frame last recorded, after its output was presented
+    pub fn record_frame_end(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<()> {
+        let slot = (self.frame_slot + self.frames.len() - 1) % self.frames.len();
+        self.write_timestamp(builder, slot, Timestamp::Blit)
+    }
+
+    // Pass timings of the last frame of the upcoming slot, once the caller waited for it
+    pub fn gpu_timings(&self) -> Result<Option<GpuTimings>> {
+        match &self.timer {
+            Some(timer) => timer.read(self.frame_slot),
+            None => Ok(None),
+        }
+    }
+
+    fn write_timestamp(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        slot: usize,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        match &mut self.timer {
+            Some(timer) => timer.write(builder, slot, timestamp),
+            None => Ok(()),
+        }
+    }
+
+    // Edge-aware scaling of a recorded output to another storage image of any extent
+    pub fn record_upscale(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<ImageView>,
+        output: Arc<ImageView>,
+    ) -> Result<()> {
+        self.upscale.record(builder, input, output)
+    }
+
+    fn create_descriptor_set(&self, slot: usize) -> Result<Arc<DescriptorSet>> {
+        let descriptor_set_layout = self
+            .trace_pipeline
+            .layout()
+            .set_layouts()
+            .first()
+            .context("No descriptor set layout found")?;
+
+        // Each backend declares only the scene bindings it traces with
+        DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            descriptor_set_layout.clone(),
+            self.scene_buffers
+                .descriptor_writes()
+                .into_iter()
+                .chain([
+                    WriteDescriptorSet::buffer(2, self.frames[slot].camera_buffer.clone()),
+                    WriteDescriptorSet::image_view(3, self.accumulation_image.clone()),
+                ])
+                .chain(self.aov_images.descriptor_writes())
+                .chain([self.adaptive.descriptor_write()])
+                .filter(|write| descriptor_set_layout.bindings().contains_key(&write.binding())),
+            [],
+        )
+        .context("Failed to create descriptor set")
+    }
+
+    // Denoises the accumulation or TAA result when enabled and runs the post-process pass into
+    // output, timed in the given frame slot
+    fn record_display(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        output: Arc<ImageView>,
+        encoding: OutputEncoding,
+        timed_slot: Option<usize>,
+    ) -> Result<()> {
+        let resolved = match self.taa.resolved() {
+            Some(resolved) if self.settings.taa => resolved,
+            _ => self.accumulation_image.clone(),
+        };
+        let radiance = if self.settings.denoise {
+            self.denoise.record(
+                builder,
+                resolved,
+                &self.aov_images,
+                self.frame_index,
+                self.accumulation_space(),
+            )?
+        } else {
+            resolved
+        };
+        if let Some(slot) = timed_slot {
+            self.write_timestamp(builder, slot, Timestamp::Denoise)?;
+        }
+
+        self.tonemap.record(
+            builder,
+            TonemapInput {
+                radiance,
+                samples: self.frame_index,
+            },
+            &self.aov_images,
+            output,
+            &self.settings,
+            ColorTransform {
+                input: self.accumulation_space(),
+                output: encoding,
+            },
+        )?;
+        if let Some(slot) = timed_slot {
+            self.write_timestamp(builder, slot, Timestamp::Tonemap)?;
+        }
+        Ok(())
+    }
+
+    fn execute(&self, builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<()> {
+        let command_buffer = builder.build().context("Failed to build command buffer")?;
+        now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)
+            .context("Failed to execute command buffer")?
+            .then_signal_fence_and_flush()
+            .context("Failed to signal fence and flush")?
+            .wait(None)
+            .context("Failed to wait for future")?;
+        Ok(())
+    }
+
+    // Copies an image to the host, waiting for the copy to finish
+    fn read_bytes(&self, image: Arc<Image>) -> Result<Vec<u8>> {
+        let [width, height, _] = image.extent();
+
+        let buffer = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            width as u64 * height as u64 * image.format().block_size(),
+        )
+        .context("Failed to create readback buffer")?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .context("Failed to create command buffer builder")?;
+
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))
+            .context("Failed to copy image to readback buffer")?;
+        self.execute(builder)?;
+
+        let bytes = buffer.read().context("Failed to read readback buffer")?;
+        Ok(bytes.to_vec())
+    }
+
+    // Components of a float image in row order from the top left
+    pub fn read_image(&self, image: Arc<Image>) -> Result<Vec<f32>> {
+        let format = image.format();
+        let bytes = self.read_bytes(image)?;
+        let components = match format {
+            Format::R16G16B16A16_SFLOAT => bytes
+                .chunks_exact(2)
+                .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+                .collect(),
+            Format::R32G32B32A32_SFLOAT | Format::R32_SFLOAT => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            format => bail!("Can not read back images of format {format:?} as floats"),
+        };
+        Ok(components)
+    }
+
+    fn read_image_u32(&self, image: Arc<Image>) -> Result<Vec<u32>> {
+        let format = image.format();
+        if format != Format::R32G32B32A32_UINT {
+            bail!("Can not read back images of format {format:?} as integers");
+        }
+        let bytes = self.read_bytes(image)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    // RGBA albedo and normal images guiding an external denoiser, needs the AOVs enabled
+    pub fn read_denoiser_guides(&self) -> Result<(Vec<f32>, Vec<f32>)> {
+        if !self.settings.aovs {
+            bail!("The denoiser guides need AOVs to be enabled");
+        }
+        let albedo = self.read_image(self.aov_images.albedo.image().clone())?;
+        let normal = self.read_image(self.aov_images.normal.image().clone())?;
+        Ok((albedo, normal))
+    }
+
+    // Tiles adaptive sampling still works on, as of the last executed frame
+    pub fn active_tiles(&self) -> Result<u32> {
+        self.adaptive.active_tiles()
+    }
+
+    // AOV layers of an EXR file, the sample counts only with AOVs or adaptive sampling enabled
+    pub fn read_aovs(&self) -> Result<Vec<Channel>> {
+        let images = &self.aov_images;
+        let mut channels = Vec::new();
+        if self.settings.aovs || self.settings.adaptive.is_some() {
+            let samples = self.read_image(images.samples.image().clone())?;
+            channels.extend(split_channels(&samples, 4, &["samples.Y"], Samples::F32));
+        }
+        if !self.settings.aovs {
+            return Ok(channels);
+        }
+
+        let albedo = self.read_image(images.albedo.image().clone())?;
+        let normal = self.read_image(images.normal.image().clone())?;
+        let depth = self.read_image(images.depth.image().clone())?;
+        let id = self.read_image_u32(images.id.image().clone())?;
+        let motion = self.read_image(images.motion.image().clone())?;
+
+        channels.extend(split_channels(&albedo, 4, &["albedo.R", "albedo.G", "albedo.B"], Samples::F32));
+        channels.extend(split_channels(&normal, 4, &["normal.X", "normal.Y", "normal.Z"], Samples::F32));
+        channels.extend(split_channels(&depth, 1, &["depth.Z"], Samples::F32));
+        channels.extend(split_channels(&id, 4, &["id.instance", "id.material", "id.primitive"], Samples::U32));
+        channels.extend(split_channels(&motion, 4, &["motion.X", "motion.Y"], Samples::F32));
+        Ok(channels)
+    }
+
+    // Linear Rec. 709 radiance accumulated so far as RGBA, converted from the working space
+    // or CIE XYZ
+    pub fn read_radiance(&self) -> Result<Vec<f32>> {
+        let mut pixels = self.read_image(self.accumulation_image.image().clone())?;
+        let space = self.accumulation_space();
+        if space != ColorSpace::Rec709 {
+            for pixel in pixels.chunks_exact_mut(4) {
+                let rgb = space.to_rec709(Vec3::new(pixel[0], pixel[1], pixel[2]));
+                pixel[..3].copy_from_slice(&rgb.to_array());
+            }
+        }
+        Ok(pixels)
+    }
+
+    // Runs the post-process pass into a linear image, independent of the display encoding
+    fn read_display_image(&mut self) -> Result<Vec<f32>> {
+        let extent = self.accumulation_image.image().extent();
+        let image = ImageView::new_default(
+            Image::new(
+                self.memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: Format::R32G32B32A32_SFLOAT,
+                    extent,
+                    usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .context("Failed to create display readback image")?,
+        )
+        .context("Failed to create image view for display readback image")?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .context("Failed to create command buffer builder")?;
+
+        self.record_display(&mut builder, image.clone(), OutputEncoding::Linear, None)?;
+        self.execute(builder)?;
+
+        self.read_image(image.image().clone())
+    }
+
+    // The tonemapped image for PNGs, linear radiance for HDR formats and AOV layers for EXR
+    pub fn capture(&mut self, format: OutputFormat) -> Result<Screenshot> {
+        let [width, height, _] = self.accumulation_image.image().extent();
+        let pixels = if format.is_hdr() {
+            self.read_radiance()?
+        } else {
+            self.read_display_image()?
+        };
+        let channels = if format == OutputFormat::Exr {
+            self.read_aovs()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Screenshot {
+            width,
+            height,
+            pixels,
+            channels,
+            metadata: self.metadata(),
+        })
+    }
+
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![
+            ("Samples per pixel".to_owned(), self.frame_index.to_string()),
+            ("Backend".to_owned(), format!("{:?}", self.backend)),
+            ("Max bounces".to_owned(), self.settings.max_bounces.to_string()),
+            ("Integrator".to_owned(), format!("{:?}", self.integrator)),
+            ("Working space".to_owned(), format!("{:?}", self.settings.working_space)),
+            ("Tonemapper".to_owned(), format!("{:?}", self.settings.tonemapper)),
+            ("Exposure".to_owned(), format!("{} EV", self.settings.exposure)),
+            ("Auto exposure".to_owned(), self.settings.auto_exposure.to_string()),
+        ];
+        if let Some(adaptive) = self.settings.adaptive {
+            metadata.push(("Adaptive target error".to_owned(), adaptive.target_error.to_string()));
+            metadata.push(("Adaptive min samples".to_owned(), adaptive.min_samples.to_string()));
+        }
+        metadata
+    }
+}
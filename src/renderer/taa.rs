@@ -0,0 +1,164 @@
+// Temporal anti-aliasing for the interactive view. Camera rays are jittered by a Halton
+// sequence every frame, the TAA pass reprojects the previous result along the motion AOV
+// so moving views keep their history instead of restarting at one sample.
+
+mod taa_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/taa.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::aov::AovImages;
+use super::create_accumulation_image;
+use super::tonemap::{create_compute_pipeline, descriptor_set};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+
+const GROUP_SIZE: u32 = 16;
+
+// The history counts as this many accumulated samples, a blend factor of 0.1 for a single one
+const HISTORY_SAMPLES: f32 = 9.0;
+// Width of the variance clipping box in standard deviations
+const CLIP_GAMMA: f32 = 1.25;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct TaaPushConstants {
+    samples: u32,
+    history_valid: u32,
+    history_samples: f32,
+    clip_gamma: f32,
+}
+
+// Radical inverse of index in the given base
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Sub-pixel position of the camera rays of a frame, bases 2 and 3 cover the pixel evenly
+pub fn jitter(frame: u32) -> [f32; 2] {
+    [halton(frame + 1, 2), halton(frame + 1, 3)]
+}
+
+pub struct TaaPass {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline: Arc<ComputePipeline>,
+    // Written alternately, the latest one is the resolved frame
+    history: Option<[Arc<ImageView>; 2]>,
+    latest: usize,
+    history_valid: bool,
+}
+
+impl TaaPass {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self> {
+        let pipeline = create_compute_pipeline(
+            device.clone(),
+            taa_shader::load(device).context("Failed to load TAA shader module")?,
+        )?;
+
+        Ok(Self {
+            memory_allocator,
+            descriptor_set_allocator,
+            pipeline,
+            history: None,
+            latest: 0,
+            history_valid: false,
+        })
+    }
+
+    // Drops the history, after resizing or when the accumulation no longer matches it
+    pub fn release(&mut self) {
+        self.history = None;
+        self.history_valid = false;
+    }
+
+    // Result of the last record, None before the first one
+    pub fn resolved(&self) -> Option<Arc<ImageView>> {
+        self.history
+            .as_ref()
+            .filter(|_| self.history_valid)
+            .map(|history| history[self.latest].clone())
+    }
+
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        accumulation: Arc<ImageView>,
+        aov_images: &AovImages,
+        samples: u32,
+    ) -> Result<()> {
+        let extent = accumulation.image().extent();
+        let history = match &self.history {
+            Some(history) => history.clone(),
+            None => {
+                let history = [
+                    create_accumulation_image(extent, self.memory_allocator.clone())?,
+                    create_accumulation_image(extent, self.memory_allocator.clone())?,
+                ];
+                self.history = Some(history.clone());
+                self.history_valid = false;
+                history
+            }
+        };
+
+        let previous = history[self.latest].clone();
+        self.latest = 1 - self.latest;
+
+        let set = descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.pipeline,
+            [
+                WriteDescriptorSet::image_view(0, accumulation),
+                WriteDescriptorSet::image_view(1, aov_images.motion.clone()),
+                WriteDescriptorSet::image_view(2, previous),
+                WriteDescriptorSet::image_view(3, history[self.latest].clone()),
+            ],
+        )?;
+
+        let push_constants = TaaPushConstants {
+            samples,
+            history_valid: self.history_valid as u32,
+            history_samples: HISTORY_SAMPLES,
+            clip_gamma: CLIP_GAMMA,
+        };
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("Failed to bind TAA pipeline")?
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set)
+            .context("Failed to bind descriptor sets")?
+            .push_constants(self.pipeline.layout().clone(), 0, push_constants)
+            .context("Failed to push constants")?;
+
+        unsafe {
+            builder
+                .dispatch([extent[0].div_ceil(GROUP_SIZE), extent[1].div_ceil(GROUP_SIZE), 1])
+                .context("Failed to record TAA dispatch")?;
+        }
+
+        self.history_valid = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+// GPU timestamps around the passes of a frame. Every frame slot has its own query pool, read
+// once the fence of the slot signalled, so results arrive a few frames after they were taken.
+
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::device::Queue;
+use vulkano::query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+use vulkano::sync::PipelineStage;
+
+// Start of a frame and ends of its passes, in recording order
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timestamp {
+    Start,
+    Trace,
+    Denoise,
+    Tonemap,
+    Blit,
+}
+
+const TIMESTAMPS: u32 = 5;
+
+// Milliseconds the GPU spent in each pass of a frame, tracing includes adaptive sampling and
+// TAA, the blit includes upscaling
+#[derive(Clone, Copy, Default, Debug)]
+pub struct GpuTimings {
+    pub trace: f32,
+    pub denoise: f32,
+    pub tonemap: f32,
+    pub blit: f32,
+}
+
+impl GpuTimings {
+    pub fn total(&self) -> f32 {
+        self.trace + self.denoise + self.tonemap + self.blit
+    }
+}
+
+pub struct GpuTimer {
+    pools: Vec<Arc<QueryPool>>,
+    // Whether the pool holds every timestamp of a frame
+    complete: Vec<bool>,
+    // Nanoseconds per tick
+    period: f32,
+    valid_mask: u64,
+}
+
+impl GpuTimer {
+    // None when the queue can not write timestamps
+    pub fn new(queue: &Arc<Queue>, slots: usize) -> Result<Option<Self>> {
+        let device = queue.device();
+        let physical_device = device.physical_device();
+        let Some(valid_bits) =
+            physical_device.queue_family_properties()[queue.queue_family_index() as usize]
+                .timestamp_valid_bits
+                .filter(|&bits| bits > 0)
+        else {
+            return Ok(None);
+        };
+
+        let pools = (0..slots)
+            .map(|_| {
+                QueryPool::new(
+                    device.clone(),
+                    QueryPoolCreateInfo {
+                        query_count: TIMESTAMPS,
+                        ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                    },
+                )
+                .context("Failed to create timestamp query pool")
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Some(Self {
+            pools,
+            complete: vec![false; slots],
+            period: physical_device.properties().timestamp_period,
+            valid_mask: u64::MAX >> (64 - valid_bits.min(64)),
+        }))
+    }
+
+    pub fn write(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        slot: usize,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        let pool = self.pools[slot].clone();
+        unsafe {
+            if timestamp == Timestamp::Start {
+                builder
+                    .reset_query_pool(pool.clone(), 0..TIMESTAMPS)
+                    .context("Failed to reset timestamp queries")?;
+            }
+            builder
+                .write_timestamp(pool, timestamp as u32, PipelineStage::AllCommands)
+                .context("Failed to write timestamp")?;
+        }
+        self.complete[slot] = timestamp == Timestamp::Blit;
+        Ok(())
+    }
+
+    // Timings of the last frame recorded into the slot, which has to have finished executing
+    pub fn read(&self, slot: usize) -> Result<Option<GpuTimings>> {
+        if !self.complete[slot] {
+            return Ok(None);
+        }
+
+        let mut ticks = [0u64; TIMESTAMPS as usize];
+        let available = self.pools[slot]
+            .get_results(0..TIMESTAMPS, &mut ticks, QueryResultFlags::empty())
+            .context("Failed to read timestamp queries")?;
+        if !available {
+            return Ok(None);
+        }
+
+        let milliseconds = |end: Timestamp| {
+            let end = end as usize;
+            (ticks[end].wrapping_sub(ticks[end - 1]) & self.valid_mask) as f32 * self.period / 1e6
+        };
+        Ok(Some(GpuTimings {
+            trace: milliseconds(Timestamp::Trace),
+            denoise: milliseconds(Timestamp::Denoise),
+            tonemap: milliseconds(Timestamp::Tonemap),
+            blit: milliseconds(Timestamp::Blit),
+        }))
+    }
+}
@@ -0,0 +1,365 @@
+// Post-process pass run after accumulation: exposure, tonemapping, debug views and
+// output encoding.
+// Changing these settings does not restart accumulation.
+
+mod tonemap_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/tonemap.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod histogram {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/histogram.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+mod exposure {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/exposure.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::aov::{AovImages, DebugView};
+use super::color::ColorTransform;
+use super::RenderSettings;
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::shader::ShaderModule;
+
+// Values match the TONEMAP_ defines in tonemap.glsl
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Tonemapper {
+    /// Linear output, only clamped by the output format
+    None = 0,
+    /// Fitted ACES filmic curve
+    Aces = 1,
+    /// AgX base look
+    Agx = 2,
+    /// Luminance based Reinhard
+    Reinhard = 3,
+    /// Khronos PBR Neutral
+    PbrNeutral = 4,
+}
+
+impl Tonemapper {
+    const ALL: [Self; 5] = [Self::None, Self::Aces, Self::Agx, Self::Reinhard, Self::PbrNeutral];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+// Workgroup size of all three shaders in each dimension
+const GROUP_SIZE: u32 = 16;
+
+// Luminance range covered by the histogram, in stops
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const LOG_LUMINANCE_RANGE: f32 = 22.0;
+const HISTOGRAM_BINS: usize = 256;
+
+// Rate at which auto exposure approaches the current average, per second
+const DEFAULT_ADAPTATION_RATE: f32 = 1.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct TonemapPushConstants {
+    tonemapper: u32,
+    exposure: f32,
+    auto_exposure: u32,
+    input_space: u32,
+    debug_view: u32,
+    output_encoding: u32,
+    paper_white: f32,
+    max_samples: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct HistogramPushConstants {
+    min_log_luminance: f32,
+    inverse_log_range: f32,
+    input_space: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct ExposurePushConstants {
+    min_log_luminance: f32,
+    log_range: f32,
+    pixel_count: f32,
+    adaptation: f32,
+}
+
+pub(super) fn create_compute_pipeline(device: Arc<Device>, module: Arc<ShaderModule>) -> Result<Arc<ComputePipeline>> {
+    let stage = PipelineShaderStageCreateInfo::new(
+        module.entry_point("main").context("Failed to set entry point")?,
+    );
+
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+            .into_pipeline_layout_create_info(device.clone())
+            .context("Failed to create pipeline layout")?,
+    )
+    .context("Failed to create pipeline layout")?;
+
+    ComputePipeline::new(device, None, ComputePipelineCreateInfo::stage_layout(stage, layout))
+        .context("Failed to create compute pipeline")
+}
+
+pub(super) fn descriptor_set(
+    allocator: &Arc<StandardDescriptorSetAllocator>,
+    pipeline: &Arc<ComputePipeline>,
+    writes: impl IntoIterator<Item = WriteDescriptorSet>,
+) -> Result<Arc<DescriptorSet>> {
+    let layout = pipeline
+        .layout()
+        .set_layouts()
+        .first()
+        .context("No descriptor set layout found")?;
+    DescriptorSet::new(allocator.clone(), layout.clone(), writes, [])
+        .context("Failed to create descriptor set")
+}
+
+// Radiance to display and the samples of its most sampled pixel, which tops the heatmap view
+pub struct TonemapInput {
+    pub radiance: Arc<ImageView>,
+    pub samples: u32,
+}
+
+pub struct TonemapPass {
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    tonemap_pipeline: Arc<ComputePipeline>,
+    histogram_pipeline: Arc<ComputePipeline>,
+    exposure_pipeline: Arc<ComputePipeline>,
+    // Cleared by the exposure shader after reading it
+    histogram: Subbuffer<[u32]>,
+    average_luminance: Subbuffer<f32>,
+    adaptation_rate: f32,
+    last_adaptation: Instant,
+}
+
+impl TonemapPass {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self> {
+        let tonemap_pipeline = create_compute_pipeline(
+            device.clone(),
+            tonemap_shader::load(device.clone()).context("Failed to load tonemap shader module")?,
+        )?;
+        let histogram_pipeline = create_compute_pipeline(
+            device.clone(),
+            histogram::load(device.clone()).context("Failed to load histogram shader module")?,
+        )?;
+        let exposure_pipeline = create_compute_pipeline(
+            device.clone(),
+            exposure::load(device.clone()).context("Failed to load exposure shader module")?,
+        )?;
+
+        let allocation = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+
+        let histogram = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            allocation.clone(),
+            [0u32; HISTOGRAM_BINS],
+        )
+        .context("Failed to create luminance histogram buffer")?;
+
+        // Zero until the first histogram was evaluated, the tonemap shader skips auto exposure until then
+        let average_luminance = Buffer::from_data(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            allocation,
+            0.0f32,
+        )
+        .context("Failed to create average luminance buffer")?;
+
+        Ok(Self {
+            descriptor_set_allocator,
+            tonemap_pipeline,
+            histogram_pipeline,
+            exposure_pipeline,
+            histogram,
+            average_luminance,
+            adaptation_rate: DEFAULT_ADAPTATION_RATE,
+            last_adaptation: Instant::now(),
+        })
+    }
+
+    // Infinity jumps to the measured exposure every frame, for offline renders
+    pub fn set_adaptation_rate(&mut self, rate: f32) {
+        self.adaptation_rate = rate;
+    }
+
+    // Writes the displayed image of the input radiance to output, which has to match its extent
+    // and be encoded as color.output requests
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: TonemapInput,
+        aov_images: &AovImages,
+        output: Arc<ImageView>,
+        settings: &RenderSettings,
+        color: ColorTransform,
+    ) -> Result<()> {
+        let [width, height, _] = output.image().extent();
+        let group_counts = [width.div_ceil(GROUP_SIZE), height.div_ceil(GROUP_SIZE), 1];
+
+        if settings.auto_exposure && settings.debug_view == DebugView::Beauty {
+            let histogram_set = descriptor_set(
+                &self.descriptor_set_allocator,
+                &self.histogram_pipeline,
+                [
+                    WriteDescriptorSet::image_view(0, input.radiance.clone()),
+                    WriteDescriptorSet::buffer(1, self.histogram.clone()),
+                ],
+            )?;
+
+            let histogram_push_constants = HistogramPushConstants {
+                min_log_luminance: MIN_LOG_LUMINANCE,
+                inverse_log_range: 1.0 / LOG_LUMINANCE_RANGE,
+                input_space: color.input as u32,
+            };
+
+            builder
+                .bind_pipeline_compute(self.histogram_pipeline.clone())
+                .context("Failed to bind histogram pipeline")?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    self.histogram_pipeline.layout().clone(),
+                    0,
+                    histogram_set,
+                )
+                .context("Failed to bind descriptor sets")?
+                .push_constants(self.histogram_pipeline.layout().clone(), 0, histogram_push_constants)
+                .context("Failed to push constants")?;
+
+            unsafe {
+                builder
+                    .dispatch(group_counts)
+                    .context("Failed to record histogram dispatch")?;
+            }
+
+            let exposure_set = descriptor_set(
+                &self.descriptor_set_allocator,
+                &self.exposure_pipeline,
+                [
+                    WriteDescriptorSet::buffer(0, self.histogram.clone()),
+                    WriteDescriptorSet::buffer(1, self.average_luminance.clone()),
+                ],
+            )?;
+
+            let delta_time = self.last_adaptation.elapsed().as_secs_f32();
+            self.last_adaptation = Instant::now();
+
+            let exposure_push_constants = ExposurePushConstants {
+                min_log_luminance: MIN_LOG_LUMINANCE,
+                log_range: LOG_LUMINANCE_RANGE,
+                pixel_count: (width * height) as f32,
+                adaptation: if self.adaptation_rate.is_infinite() {
+                    1.0
+                } else {
+                    1.0 - (-delta_time * self.adaptation_rate).exp()
+                },
+            };
+
+            builder
+                .bind_pipeline_compute(self.exposure_pipeline.clone())
+                .context("Failed to bind exposure pipeline")?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    self.exposure_pipeline.layout().clone(),
+                    0,
+                    exposure_set,
+                )
+                .context("Failed to bind descriptor sets")?
+                .push_constants(self.exposure_pipeline.layout().clone(), 0, exposure_push_constants)
+                .context("Failed to push constants")?;
+
+            unsafe {
+                builder
+                    .dispatch([1, 1, 1])
+                    .context("Failed to record exposure dispatch")?;
+            }
+        }
+
+        let tonemap_set = descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.tonemap_pipeline,
+            [
+                WriteDescriptorSet::image_view(0, input.radiance),
+                WriteDescriptorSet::image_view(1, output),
+                WriteDescriptorSet::buffer(2, self.average_luminance.clone()),
+                WriteDescriptorSet::image_view(3, aov_images.albedo.clone()),
+                WriteDescriptorSet::image_view(4, aov_images.normal.clone()),
+                WriteDescriptorSet::image_view(5, aov_images.depth.clone()),
+                WriteDescriptorSet::image_view(6, aov_images.id.clone()),
+                WriteDescriptorSet::image_view(7, aov_images.motion.clone()),
+                WriteDescriptorSet::image_view(8, aov_images.samples.clone()),
+            ],
+        )?;
+
+        let tonemap_push_constants = TonemapPushConstants {
+            tonemapper: settings.tonemapper as u32,
+            exposure: settings.exposure.exp2(),
+            auto_exposure: settings.auto_exposure as u32,
+            input_space: color.input as u32,
+            debug_view: settings.debug_view as u32,
+            output_encoding: color.output.transfer(),
+            paper_white: color.output.paper_white(),
+            max_samples: input.samples as f32,
+        };
+
+        builder
+            .bind_pipeline_compute(self.tonemap_pipeline.clone())
+            .context("Failed to bind tonemap pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.tonemap_pipeline.layout().clone(),
+                0,
+                tonemap_set,
+            )
+            .context("Failed to bind descriptor sets")?
+            .push_constants(self.tonemap_pipeline.layout().clone(), 0, tonemap_push_constants)
+            .context("Failed to push constants")?;
+
+        unsafe {
+            builder
+                .dispatch(group_counts)
+                .context("Failed to record tonemap dispatch")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+// Spatial upscaling of the post-processed frame to the window, for render scales below one.
+// Works on the display encoded output, so edges are detected on the luminance that is shown.
+
+mod upscale_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/upscale.glsl",
+        vulkan_version: "1.3",
+    }
+}
+
+use super::tonemap::{create_compute_pipeline, descriptor_set};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+
+const GROUP_SIZE: u32 = 16;
+
+pub struct UpscalePass {
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline: Arc<ComputePipeline>,
+}
+
+impl UpscalePass {
+    pub fn new(device: Arc<Device>, descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>) -> Result<Self> {
+        let pipeline = create_compute_pipeline(
+            device.clone(),
+            upscale_shader::load(device).context("Failed to load upscale shader module")?,
+        )?;
+
+        Ok(Self {
+            descriptor_set_allocator,
+            pipeline,
+        })
+    }
+
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<ImageView>,
+        output: Arc<ImageView>,
+    ) -> Result<()> {
+        let extent = output.image().extent();
+        let set = descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.pipeline,
+            [
+                WriteDescriptorSet::image_view(0, input),
+                WriteDescriptorSet::image_view(1, output),
+            ],
+        )?;
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("Failed to bind upscale pipeline")?
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set)
+            .context("Failed to bind descriptor sets")?;
+
+        unsafe {
+            builder
+                .dispatch([extent[0].div_ceil(GROUP_SIZE), extent[1].div_ceil(GROUP_SIZE), 1])
+                .context("Failed to record upscale dispatch")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+// Dynamic resolution for the interactive view. While the camera moves the render scale follows
+// a frame time budget, once it rests the full scale returns so the accumulation converges at
+// the highest resolution. Every change resizes the render targets and restarts accumulation.
+
+use std::time::Duration;
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+// Scales are multiples of this, small frame time changes do not resize the render targets
+const SCALE_STEP: f32 = 1.0 / 32.0;
+// Frame times are averaged over roughly this many frames
+const SMOOTHING_FRAMES: f32 = 8.0;
+// Frame times within this fraction of the budget leave the scale alone
+const TOLERANCE: f32 = 0.1;
+// Frames between two changes while moving
+const COOLDOWN_FRAMES: u32 = 8;
+// Frames without camera motion before the view counts as resting, mouse look does not move
+// the camera on every frame
+const REST_FRAMES: u32 = 8;
+
+pub struct DynamicResolution {
+    budget: f32,
+    max_scale: f32,
+    scale: f32,
+    frame_time: Option<f32>,
+    frames_since_change: u32,
+    still_frames: u32,
+}
+
+impl DynamicResolution {
+    pub fn new(budget: Duration, max_scale: f32) -> Self {
+        Self {
+            budget: budget.as_secs_f32(),
+            max_scale,
+            scale: max_scale,
+            frame_time: None,
+            frames_since_change: 0,
+            still_frames: 0,
+        }
+    }
+
+    // Takes the time of the last frame and whether the camera moved for it, returns the
+    // scale to render at when it changed
+    pub fn update(&mut self, frame_time: f32, moving: bool) -> Option<f32> {
+        self.frames_since_change += 1;
+        self.still_frames = if moving { 0 } else { self.still_frames + 1 };
+        let average = self
+            .frame_time
+            .map_or(frame_time, |average| average + (frame_time - average) / SMOOTHING_FRAMES);
+        self.frame_time = Some(average);
+
+        let target = if moving {
+            if self.frames_since_change < COOLDOWN_FRAMES || (average / self.budget - 1.0).abs() <= TOLERANCE {
+                return None;
+            }
+            // Traced pixels and with them the frame time grow with the square of the scale
+            self.scale * (self.budget / average).sqrt()
+        } else if self.still_frames == REST_FRAMES {
+            self.max_scale
+        } else {
+            return None;
+        };
+
+        let scale = if target >= self.max_scale {
+            self.max_scale
+        } else {
+            ((target / SCALE_STEP).floor() * SCALE_STEP).max(MIN_RENDER_SCALE)
+        };
+        if scale == self.scale {
+            return None;
+        }
+
+        // The average is carried over to the new scale until fresh frame times arrive
+        self.frame_time = Some(average * (scale / self.scale).powi(2));
+        self.scale = scale;
+        self.frames_since_change = 0;
+        Some(scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_budget_while_moving() {
+        let mut resolution = DynamicResolution::new(Duration::from_millis(10), 1.0);
+        // Frame time proportional to the traced pixels, 40 ms at full scale
+        let mut scale = 1.0;
+        for _ in 0..200 {
+            if let Some(new_scale) = resolution.update(0.04 * scale * scale, true) {
+                scale = new_scale;
+            }
+        }
+        let frame_time = 0.04 * scale * scale;
+        assert!((0.45..=0.55).contains(&scale), "scale {scale}");
+        assert!((frame_time / 0.01 - 1.0).abs() <= 0.15, "frame time {frame_time}");
+
+        // Cheap frames raise the scale up to the maximum but not beyond
+        for _ in 0..200 {
+            if let Some(new_scale) = resolution.update(0.001, true) {
+                scale = new_scale;
+            }
+        }
+        assert_eq!(scale, 1.0);
+    }
+
+    #[test]
+    fn returns_to_full_scale_at_rest() {
+        let mut resolution = DynamicResolution::new(Duration::from_millis(10), 0.75);
+        let mut scale = 0.75;
+        for _ in 0..100 {
+            if let Some(new_scale) = resolution.update(0.1, true) {
+                scale = new_scale;
+            }
+        }
+        assert_eq!(scale, MIN_RENDER_SCALE);
+
+        let changes: Vec<_> = (0..REST_FRAMES).filter_map(|_| resolution.update(0.1, false)).collect();
+        assert_eq!(changes, [0.75]);
+        assert_eq!(resolution.update(0.1, false), None);
+    }
+}
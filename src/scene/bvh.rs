@@ -0,0 +1,337 @@
+// Bounding volume hierarchy over boxes, used for the triangles of each mesh and for the
+// instances of the scene. Splits at the middle of the widest centroid axis, or at the median
+// where the depth would otherwise exceed MAX_DEPTH. Traversed by the CPU renderer and uploaded
+// as is for the software ray tracing backend.
+
+use glam::{BVec3, Mat4, Vec3};
+
+const MAX_LEAF_SIZE: usize = 4;
+// Deepest level below the root, traversal in software.glsl keeps BVH_STACK_SIZE = MAX_DEPTH + 1
+// nodes pending
+pub const MAX_DEPTH: usize = 31;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |bounds, p| bounds.union(&Self { min: p, max: p }))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Bounds of the transformed corners
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        Self::from_points((0..8).map(|corner| {
+            let p = Vec3::select(BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0), self.max, self.min);
+            transform.transform_point3(p)
+        }))
+    }
+
+    // Slab test, the entry distance when the ray overlaps [t_min, t_max] inside the box
+    pub fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - ray.origin) * ray.inv_direction;
+        let t1 = (self.max - ray.origin) * ray.inv_direction;
+        let near = t0.min(t1).max_element().max(t_min);
+        let far = t0.max(t1).min_element().min(t_max);
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub inv_direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction,
+            inv_direction: direction.recip(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+// Inner nodes keep their second child at offset, the first one follows directly.
+// Leaves hold count primitives starting at offset in the index list.
+pub struct Node {
+    pub bounds: Aabb,
+    pub offset: usize,
+    pub count: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len(), 0);
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize, depth: usize) {
+        let primitives = &mut self.indices[start..end];
+        let node_bounds = primitives.iter().fold(Aabb::EMPTY, |b, &i| b.union(&bounds[i]));
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            offset: start,
+            count: end - start,
+        });
+        if end - start <= MAX_LEAF_SIZE || depth == MAX_DEPTH {
+            return;
+        }
+
+        let centroids = Aabb::from_points(primitives.iter().map(|&i| bounds[i].center()));
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        // Middle of the centroid bounds, the median when everything ends up on one side or when
+        // only halving the primitives at every level still fits below MAX_DEPTH
+        let split = centroids.center()[axis];
+        let median_only = median_levels(end - start) >= MAX_DEPTH - depth;
+        let mut middle = if median_only {
+            start
+        } else {
+            start + partition(primitives, |&i| bounds[i].center()[axis] < split)
+        };
+        if middle == start || middle == end {
+            middle = start + primitives.len() / 2;
+            primitives.select_nth_unstable_by(middle - start, |&a, &b| {
+                bounds[a].center()[axis].total_cmp(&bounds[b].center()[axis])
+            });
+        }
+
+        self.build(bounds, start, middle, depth + 1);
+        let second = self.nodes.len();
+        self.build(bounds, middle, end, depth + 1);
+        self.nodes[node].offset = second;
+        self.nodes[node].count = 0;
+    }
+
+    // Depth first with the root at index 0, empty without primitives
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    // Levels below the root, 0 for a single leaf
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((index, level)) = stack.pop() {
+            let Some(node) = self.nodes.get(index) else {
+                continue;
+            };
+            depth = depth.max(level);
+            if node.count == 0 {
+                stack.push((node.offset, level + 1));
+                stack.push((index + 1, level + 1));
+            }
+        }
+        depth
+    }
+
+    // Primitives in the order the leaves refer to them
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    // Closest primitive hit within [t_min, t_max], intersect returns the distance to a primitive
+    pub fn closest(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        mut intersect: impl FnMut(usize, f32) -> Option<f32>,
+    ) -> Option<(usize, f32)> {
+        let mut closest = None;
+        self.traverse(ray, t_min, &mut t_max, |primitive, t_max| {
+            if let Some(t) = intersect(primitive, *t_max) {
+                *t_max = t;
+                closest = Some((primitive, t));
+            }
+            false
+        });
+        closest
+    }
+
+    // Whether any primitive is hit within [t_min, t_max], stops at the first one
+    pub fn any(&self, ray: &Ray, t_min: f32, mut t_max: f32, mut intersect: impl FnMut(usize, f32) -> bool) -> bool {
+        let mut hit = false;
+        self.traverse(ray, t_min, &mut t_max, |primitive, t_max| {
+            hit = intersect(primitive, *t_max);
+            hit
+        });
+        hit
+    }
+
+    // Visits the primitives of all leaves the ray enters before t_max, until visit returns true
+    fn traverse(&self, ray: &Ray, t_min: f32, t_max: &mut f32, mut visit: impl FnMut(usize, &mut f32) -> bool) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.intersect(ray, t_min, *t_max).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &primitive in &self.indices[node.offset..node.offset + node.count] {
+                    if visit(primitive, t_max) {
+                        return;
+                    }
+                }
+            } else {
+                stack.push(node.offset);
+                stack.push(index + 1);
+            }
+        }
+    }
+}
+
+// Levels of median splits until count primitives fit into leaves
+fn median_levels(count: usize) -> usize {
+    count.div_ceil(MAX_LEAF_SIZE).next_power_of_two().trailing_zeros() as usize
+}
+
+// Moves the elements matching the predicate to the front, returns how many there are
+fn partition<T>(items: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit boxes on a jittered grid, hit distance is the box entry
+    fn boxes() -> Vec<Aabb> {
+        let mut boxes = Vec::new();
+        for i in 0..200u32 {
+            let offset = Vec3::new((i % 10) as f32, ((i / 10) % 5) as f32, (i / 50) as f32) * 3.0
+                + Vec3::new((i * 7 % 5) as f32, (i * 3 % 7) as f32, (i * 11 % 3) as f32) * 0.1;
+            boxes.push(Aabb {
+                min: offset,
+                max: offset + Vec3::ONE,
+            });
+        }
+        boxes
+    }
+
+    #[test]
+    fn closest_matches_brute_force() {
+        let boxes = boxes();
+        let bvh = Bvh::new(&boxes);
+
+        for i in 0..500u32 {
+            let origin = Vec3::new(-5.0, (i % 23) as f32 * 0.7, (i % 17) as f32 * 0.6);
+            let direction = Vec3::new(1.0, ((i % 13) as f32 - 6.0) * 0.05, ((i % 7) as f32 - 3.0) * 0.08).normalize();
+            let ray = Ray::new(origin, direction);
+
+            let expected = boxes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, b)| b.intersect(&ray, 0.0, 100.0).map(|t| (index, t)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let found = bvh.closest(&ray, 0.0, 100.0, |index, t_max| {
+                boxes[index].intersect(&ray, 0.0, t_max)
+            });
+
+            assert_eq!(found.map(|(_, t)| t), expected.map(|(_, t)| t), "ray {i}");
+            assert_eq!(bvh.any(&ray, 0.0, 100.0, |index, t_max| boxes[index].intersect(&ray, 0.0, t_max).is_some()), expected.is_some());
+        }
+    }
+
+    #[test]
+    fn handles_empty_and_coincident_primitives() {
+        let ray = Ray::new(Vec3::new(0.5, 0.5, -1.0), Vec3::Z);
+        assert!(Bvh::new(&[]).closest(&ray, 0.0, 10.0, |_, _| Some(1.0)).is_none());
+
+        // All centroids equal, the median split has to terminate
+        let unit = Aabb {
+            min: Vec3::ZERO,
+            max: Vec3::ONE,
+        };
+        let bvh = Bvh::new(&[unit; 33]);
+        let mut visited = 0;
+        bvh.any(&ray, 0.0, 10.0, |_, _| {
+            visited += 1;
+            false
+        });
+        assert_eq!(visited, 33);
+    }
+
+    #[test]
+    fn limits_depth_of_skewed_distributions() {
+        // Every middle split peels off a single box, which would need a level per box
+        let boxes: Vec<_> = (0..100)
+            .map(|i| {
+                let min = Vec3::new(2f32.powi(i), 0.0, 0.0);
+                Aabb { min, max: min + Vec3::ONE }
+            })
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        assert!(bvh.depth() <= MAX_DEPTH, "depth {}", bvh.depth());
+
+        let mut indices = bvh.indices().to_vec();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..boxes.len()).collect::<Vec<_>>());
+
+        let ray = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::X);
+        let mut visited = Vec::new();
+        bvh.any(&ray, 0.0, f32::MAX, |index, t_max| {
+            visited.push(index);
+            boxes[index].intersect(&ray, 0.0, t_max).is_none()
+        });
+        visited.sort_unstable();
+        assert_eq!(visited, indices);
+    }
+}
@@ -0,0 +1,598 @@
+use super::{Aabb, Bvh, DensityGrid, Scene, bvh};
+use crate::renderer::Backend;
+use crate::{
+    MyVertex, build_acceleration_structure_aabbs, build_acceleration_structure_triangles,
+    build_top_level_acceleration_structure,
+};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::Packed24_8;
+use vulkano::acceleration_structure::{
+    AabbPositions, AccelerationStructure, AccelerationStructureInstance,
+};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::{Device, Queue};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+
+// Instance mask bits, shadow rays only test against solid geometry. Each time slice has its
+// own solid and medium boundary bit, static instances are visible in all of them.
+pub const MASK_SOLID: u8 = 0x55;
+pub const MASK_MEDIUM_BOUNDARY: u8 = 0xaa;
+
+// Moving instances are placed once per slice at evenly spaced times of the frame interval,
+// rays pick a slice from their time, see time_slice_mask in common.glsl
+pub const TIME_SLICES: u32 = 4;
+
+fn time_slice_mask(slice: u32) -> u8 {
+    0x3 << (2 * slice)
+}
+
+// Hit group records, triangles use the built-in intersection and volumes their bounding box
+const HIT_GROUP_TRIANGLES: u32 = 0;
+const HIT_GROUP_VOLUME: u32 = 1;
+
+// Marks leaves in the count of a GPU BVH node, see software.glsl
+const BVH_LEAF: u32 = 0x8000_0000;
+
+// Storage buffer layouts matching scene.glsl and software.glsl (std430)
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuInstance {
+    material: u32,
+    interior_medium: i32,
+    first_vertex: u32,
+    bvh_root: u32,
+    previous_transform: [[f32; 4]; 4],
+    transform: [[f32; 4]; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuMaterial {
+    base_color: [f32; 3],
+    roughness: f32,
+    metallic: f32,
+    _pad: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuMedium {
+    sigma_a: [f32; 3],
+    g: f32,
+    sigma_s: [f32; 3],
+    grid: i32,
+    temperature_grid: i32,
+    temperature_scale: f32,
+    emission_scale: f32,
+    _pad: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuDensityGrid {
+    bounds_min: [f32; 3],
+    offset: u32,
+    bounds_max: [f32; 3],
+    max_density: f32,
+    resolution: [u32; 3],
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuPointLight {
+    position: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    _pad: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuBvhNode {
+    bounds_min: [f32; 3],
+    // Second child of inner nodes, first index of leaves
+    offset: u32,
+    bounds_max: [f32; 3],
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuPlacement {
+    world_to_object: [[f32; 4]; 4],
+    instance: u32,
+    mask: u32,
+    volume: u32,
+    _pad: u32,
+}
+
+// Two level BVH of the software backend. The top level over the placed instances comes first
+// in the node list, followed by one bottom level per mesh.
+struct SoftwareBvh {
+    nodes: Subbuffer<[GpuBvhNode]>,
+    indices: Subbuffer<[u32]>,
+    placements: Subbuffer<[GpuPlacement]>,
+}
+
+pub struct SceneBuffers {
+    // Only built for the backends tracing against acceleration structures
+    pub tlas: Option<Arc<AccelerationStructure>>,
+    // Bottom level structures are referenced by device address only, keep them alive
+    _blases: Vec<Arc<AccelerationStructure>>,
+    software_bvh: Option<SoftwareBvh>,
+    instances: Subbuffer<[GpuInstance]>,
+    // Mesh triangles one after another, for the backends without vertex fetch in hit shaders
+    vertices: Subbuffer<[[f32; 4]]>,
+    materials: Subbuffer<[GpuMaterial]>,
+    media: Subbuffer<[GpuMedium]>,
+    grids: Subbuffer<[GpuDensityGrid]>,
+    voxels: Subbuffer<[f32]>,
+    lights: Subbuffer<[GpuPointLight]>,
+    pub light_count: u32,
+    pub atmosphere: i32,
+    pub environment: [f32; 3],
+}
+
+fn instance_transform(transform: &Mat4) -> [[f32; 4]; 3] {
+    let rows = transform.transpose().to_cols_array_2d();
+    [rows[0], rows[1], rows[2]]
+}
+
+// Vulkan does not allow empty buffers, so an unused zeroed element is uploaded instead
+fn storage_buffer<T: BufferContents + Pod>(
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    mut data: Vec<T>,
+) -> Result<Subbuffer<[T]>> {
+    if data.is_empty() {
+        data.push(T::zeroed());
+    }
+
+    Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data,
+    )
+    .context("Failed to create scene storage buffer")
+}
+
+// Instances as placed in the top level, moving ones once per time slice with only that slice's
+// mask bits. Volumes follow separately.
+fn placed_instances(scene: &Scene) -> Vec<(usize, Mat4, u8)> {
+    let mut placed = Vec::new();
+    for (index, instance) in scene.instances.iter().enumerate() {
+        let mask = if instance.interior_medium.is_some() {
+            MASK_MEDIUM_BOUNDARY
+        } else {
+            MASK_SOLID
+        };
+
+        if instance.previous_transform.is_some() {
+            for slice in 0..TIME_SLICES {
+                let time = slice as f32 / (TIME_SLICES - 1) as f32;
+                placed.push((index, instance.transform_at(time), mask & time_slice_mask(slice)));
+            }
+        } else {
+            placed.push((index, instance.transform, mask));
+        }
+    }
+    placed
+}
+
+fn build_acceleration_structures(
+    scene: &Scene,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: &Arc<StandardCommandBufferAllocator>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+) -> Result<(Arc<AccelerationStructure>, Vec<Arc<AccelerationStructure>>)> {
+    let blases = scene
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let vertex_buffer = Buffer::from_iter(
+                memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::VERTEX_BUFFER
+                        | BufferUsage::SHADER_DEVICE_ADDRESS
+                        | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                mesh.positions.iter().map(|&position| MyVertex { position }),
+            )
+            .context("Failed to create vertex buffer")?;
+
+            Ok(unsafe {
+                build_acceleration_structure_triangles(
+                    &vertex_buffer,
+                    memory_allocator.clone(),
+                    command_buffer_allocator,
+                    device.clone(),
+                    queue.clone(),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut as_instances: Vec<_> = placed_instances(scene)
+        .into_iter()
+        .map(|(index, transform, mask)| AccelerationStructureInstance {
+            transform: instance_transform(&transform),
+            instance_custom_index_and_mask: Packed24_8::new(index as u32, mask),
+            instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(HIT_GROUP_TRIANGLES, 0),
+            acceleration_structure_reference: blases[scene.instances[index].mesh].device_address().into(),
+        })
+        .collect();
+
+    // Volumes get one box each, placed in world space by their density grid bounds
+    let mut volume_blases = Vec::with_capacity(scene.volumes.len());
+    for (volume, &medium) in scene.volumes.iter().enumerate() {
+        let density = scene.media[medium]
+            .density
+            .as_ref()
+            .context("Volume medium has no density grid")?;
+
+        let aabb_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::SHADER_DEVICE_ADDRESS
+                    | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            [AabbPositions {
+                min: density.bounds_min.to_array(),
+                max: density.bounds_max.to_array(),
+            }],
+        )
+        .context("Failed to create volume bounds buffer")?;
+
+        let blas = unsafe {
+            build_acceleration_structure_aabbs(
+                &aabb_buffer,
+                memory_allocator.clone(),
+                command_buffer_allocator,
+                device.clone(),
+                queue.clone(),
+            )
+        };
+
+        as_instances.push(AccelerationStructureInstance {
+            instance_custom_index_and_mask: Packed24_8::new(
+                (scene.instances.len() + volume) as u32,
+                MASK_MEDIUM_BOUNDARY,
+            ),
+            instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(HIT_GROUP_VOLUME, 0),
+            acceleration_structure_reference: blas.device_address().into(),
+            ..Default::default()
+        });
+        volume_blases.push(blas);
+    }
+
+    let tlas = unsafe {
+        build_top_level_acceleration_structure(
+            as_instances,
+            memory_allocator,
+            command_buffer_allocator,
+            device,
+            queue,
+        )
+    };
+    Ok((tlas, blases.into_iter().chain(volume_blases).collect()))
+}
+
+// Appends the nodes of a BVH to the shared lists with offsets into them, returns its root.
+// An empty BVH becomes a leaf without primitives.
+fn append_bvh(bvh: &Bvh, nodes: &mut Vec<GpuBvhNode>, indices: &mut Vec<u32>) -> u32 {
+    // Deeper subtrees would overflow the traversal stack of software.glsl
+    assert!(bvh.depth() <= bvh::MAX_DEPTH, "BVH of depth {} is too deep to traverse", bvh.depth());
+    let root = nodes.len();
+    let first_index = indices.len();
+    indices.extend(bvh.indices().iter().map(|&index| index as u32));
+
+    if bvh.nodes().is_empty() {
+        nodes.push(GpuBvhNode {
+            bounds_min: [0.0; 3],
+            offset: 0,
+            bounds_max: [0.0; 3],
+            count: BVH_LEAF,
+        });
+    }
+    nodes.extend(bvh.nodes().iter().map(|node| {
+        let (offset, count) = if node.count > 0 {
+            (first_index + node.offset, BVH_LEAF | node.count as u32)
+        } else {
+            (root + node.offset, 0)
+        };
+        GpuBvhNode {
+            bounds_min: node.bounds.min.to_array(),
+            offset: offset as u32,
+            bounds_max: node.bounds.max.to_array(),
+            count,
+        }
+    }));
+    root as u32
+}
+
+// Returns the BVH and the root node of every mesh
+fn build_software_bvh(scene: &Scene, memory_allocator: Arc<StandardMemoryAllocator>) -> Result<(SoftwareBvh, Vec<u32>)> {
+    let triangle_bounds = |positions: &[[f32; 3]]| -> Vec<Aabb> {
+        positions
+            .chunks_exact(3)
+            .map(|triangle| Aabb::from_points(triangle.iter().map(|&p| Vec3::from(p))))
+            .collect()
+    };
+    let mesh_bounds: Vec<Aabb> = scene
+        .meshes
+        .iter()
+        .map(|mesh| Aabb::from_points(mesh.positions.iter().map(|&p| Vec3::from(p))))
+        .collect();
+
+    let mut placements = Vec::new();
+    let mut bounds = Vec::new();
+    for (index, transform, mask) in placed_instances(scene) {
+        let mesh = scene.instances[index].mesh;
+        if scene.meshes[mesh].positions.is_empty() {
+            continue;
+        }
+        placements.push(GpuPlacement {
+            world_to_object: transform.inverse().to_cols_array_2d(),
+            instance: index as u32,
+            mask: mask as u32,
+            volume: 0,
+            _pad: 0,
+        });
+        bounds.push(mesh_bounds[mesh].transformed(&transform));
+    }
+    for (volume, &medium) in scene.volumes.iter().enumerate() {
+        let density = scene.media[medium]
+            .density
+            .as_ref()
+            .context("Volume medium has no density grid")?;
+        placements.push(GpuPlacement {
+            world_to_object: Mat4::IDENTITY.to_cols_array_2d(),
+            instance: (scene.instances.len() + volume) as u32,
+            mask: MASK_MEDIUM_BOUNDARY as u32,
+            volume: 1,
+            _pad: 0,
+        });
+        bounds.push(Aabb {
+            min: density.bounds_min,
+            max: density.bounds_max,
+        });
+    }
+
+    let mut nodes = Vec::new();
+    let mut indices = Vec::new();
+    append_bvh(&Bvh::new(&bounds), &mut nodes, &mut indices);
+    let mesh_roots = scene
+        .meshes
+        .iter()
+        .map(|mesh| append_bvh(&Bvh::new(&triangle_bounds(&mesh.positions)), &mut nodes, &mut indices))
+        .collect();
+
+    let bvh = SoftwareBvh {
+        nodes: storage_buffer(memory_allocator.clone(), nodes)?,
+        indices: storage_buffer(memory_allocator.clone(), indices)?,
+        placements: storage_buffer(memory_allocator, placements)?,
+    };
+    Ok((bvh, mesh_roots))
+}
+
+impl SceneBuffers {
+    pub fn new(
+        scene: &Scene,
+        backend: Backend,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: &Arc<StandardCommandBufferAllocator>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<Self> {
+        let (tlas, blases) = if backend.uses_acceleration_structures() {
+            let (tlas, blases) =
+                build_acceleration_structures(scene, memory_allocator.clone(), command_buffer_allocator, device, queue)?;
+            (Some(tlas), blases)
+        } else {
+            (None, Vec::new())
+        };
+        let (software_bvh, mesh_roots) = if backend == Backend::Software {
+            let (bvh, roots) = build_software_bvh(scene, memory_allocator.clone())?;
+            (Some(bvh), roots)
+        } else {
+            (None, vec![0; scene.meshes.len()])
+        };
+
+        let first_vertices: Vec<u32> = scene
+            .meshes
+            .iter()
+            .scan(0, |first, mesh| {
+                let current = *first;
+                *first += mesh.positions.len() as u32;
+                Some(current)
+            })
+            .collect();
+        let vertices = scene
+            .meshes
+            .iter()
+            .flat_map(|mesh| mesh.positions.iter().map(|&[x, y, z]| [x, y, z, 1.0]))
+            .collect();
+
+        let instances = scene
+            .instances
+            .iter()
+            .map(|instance| GpuInstance {
+                material: instance.material as u32,
+                interior_medium: instance.interior_medium.map_or(-1, |m| m as i32),
+                first_vertex: first_vertices[instance.mesh],
+                bvh_root: mesh_roots[instance.mesh],
+                previous_transform: instance.previous_transform.unwrap_or(instance.transform).to_cols_array_2d(),
+                transform: instance.transform.to_cols_array_2d(),
+            })
+            .chain(scene.volumes.iter().map(|&medium| GpuInstance {
+                material: 0,
+                interior_medium: medium as i32,
+                first_vertex: 0,
+                bvh_root: 0,
+                previous_transform: Mat4::IDENTITY.to_cols_array_2d(),
+                transform: Mat4::IDENTITY.to_cols_array_2d(),
+            }))
+            .collect();
+
+        let materials = scene
+            .materials
+            .iter()
+            .map(|material| GpuMaterial {
+                base_color: material.base_color.to_array(),
+                roughness: material.roughness,
+                metallic: material.metallic,
+                _pad: [0.0; 3],
+            })
+            .collect();
+
+        let mut grids = Vec::new();
+        let mut voxels = Vec::new();
+        let media = scene
+            .media
+            .iter()
+            .map(|medium| {
+                let mut upload = |grid: &Option<DensityGrid>| match grid {
+                    Some(grid) => {
+                        grids.push(GpuDensityGrid {
+                            bounds_min: grid.bounds_min.to_array(),
+                            offset: voxels.len() as u32,
+                            bounds_max: grid.bounds_max.to_array(),
+                            max_density: grid.max_density(),
+                            resolution: grid.resolution,
+                            _pad: 0,
+                        });
+                        voxels.extend_from_slice(&grid.values);
+                        grids.len() as i32 - 1
+                    }
+                    None => -1,
+                };
+
+                GpuMedium {
+                    sigma_a: medium.sigma_a.to_array(),
+                    g: medium.g,
+                    sigma_s: medium.sigma_s.to_array(),
+                    grid: upload(&medium.density),
+                    temperature_grid: upload(&medium.temperature),
+                    temperature_scale: medium.temperature_scale,
+                    emission_scale: medium.emission_scale,
+                    _pad: 0.0,
+                }
+            })
+            .collect();
+
+        let lights = scene
+            .lights
+            .iter()
+            .map(|light| GpuPointLight {
+                position: light.position.to_array(),
+                intensity: light.intensity,
+                color: light.color.to_array(),
+                _pad: 0.0,
+            })
+            .collect();
+
+        Ok(Self {
+            tlas,
+            _blases: blases,
+            software_bvh,
+            instances: storage_buffer(memory_allocator.clone(), instances)?,
+            vertices: storage_buffer(memory_allocator.clone(), vertices)?,
+            materials: storage_buffer(memory_allocator.clone(), materials)?,
+            media: storage_buffer(memory_allocator.clone(), media)?,
+            grids: storage_buffer(memory_allocator.clone(), grids)?,
+            voxels: storage_buffer(memory_allocator.clone(), voxels)?,
+            lights: storage_buffer(memory_allocator, lights)?,
+            light_count: scene.lights.len() as u32,
+            atmosphere: scene.atmosphere.map_or(-1, |m| m as i32),
+            environment: scene.environment.to_array(),
+        })
+    }
+
+    // Scene bindings of descriptor set 0, see scene.glsl and the backend's shader
+    pub fn descriptor_writes(&self) -> Vec<WriteDescriptorSet> {
+        let mut writes = vec![
+            WriteDescriptorSet::buffer(4, self.instances.clone()),
+            WriteDescriptorSet::buffer(5, self.materials.clone()),
+            WriteDescriptorSet::buffer(6, self.media.clone()),
+            WriteDescriptorSet::buffer(7, self.grids.clone()),
+            WriteDescriptorSet::buffer(8, self.voxels.clone()),
+            WriteDescriptorSet::buffer(9, self.lights.clone()),
+            WriteDescriptorSet::buffer(17, self.vertices.clone()),
+        ];
+        if let Some(tlas) = &self.tlas {
+            writes.push(WriteDescriptorSet::acceleration_structure(0, tlas.clone()));
+        }
+        if let Some(bvh) = &self.software_bvh {
+            writes.extend([
+                WriteDescriptorSet::buffer(18, bvh.nodes.clone()),
+                WriteDescriptorSet::buffer(19, bvh.indices.clone()),
+                WriteDescriptorSet::buffer(20, bvh.placements.clone()),
+            ]);
+        }
+        writes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Preset;
+
+    #[test]
+    fn moving_instances_are_placed_per_time_slice() {
+        let scene = Preset::MotionBlur.build();
+        let placed = placed_instances(&scene);
+        let moving: Vec<_> = scene
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|(_, instance)| instance.previous_transform.is_some())
+            .collect();
+        assert!(!moving.is_empty());
+        assert_eq!(placed.len(), scene.instances.len() + moving.len() * (TIME_SLICES as usize - 1));
+
+        for (index, instance) in moving {
+            let slices: Vec<_> = placed.iter().filter(|(i, _, _)| *i == index).collect();
+            assert_eq!(slices.len(), TIME_SLICES as usize);
+            for (slice, &&(_, transform, mask)) in slices.iter().enumerate() {
+                assert_eq!(mask, MASK_SOLID & time_slice_mask(slice as u32));
+                let time = slice as f32 / (TIME_SLICES - 1) as f32;
+                assert!(transform.abs_diff_eq(instance.transform_at(time), 1e-5));
+            }
+            // The first slice starts at the previous transform and the last ends at the current one
+            assert!(slices[0].1.abs_diff_eq(instance.previous_transform.unwrap(), 1e-5));
+            assert!(slices[TIME_SLICES as usize - 1].1.abs_diff_eq(instance.transform, 1e-5));
+        }
+
+        // Halfway through, the second sphere has covered half of its 0.4 units
+        let sphere = &scene.instances[2];
+        let start = sphere.previous_transform.unwrap().w_axis.x;
+        assert!((sphere.transform_at(0.5).w_axis.x - start - 0.2).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,125 @@
+use glam::Vec3;
+
+// Participating medium, coefficients are per unit length and scaled by the grid density
+pub struct Medium {
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    // Henyey-Greenstein asymmetry, -1 is full back scattering and 1 full forward scattering
+    pub g: f32,
+    // Homogeneous when None
+    pub density: Option<DensityGrid>,
+    // Blackbody emission from a grid of temperatures, in Kelvin after temperature_scale
+    pub temperature: Option<DensityGrid>,
+    pub temperature_scale: f32,
+    pub emission_scale: f32,
+}
+
+impl Medium {
+    pub fn new(sigma_a: Vec3, sigma_s: Vec3, g: f32, density: Option<DensityGrid>) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            g,
+            density,
+            temperature: None,
+            temperature_scale: 1.0,
+            emission_scale: 1.0,
+        }
+    }
+}
+
+// Dense voxel grid spanning an axis aligned box in world space, x varies fastest
+pub struct DensityGrid {
+    pub resolution: [u32; 3],
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub values: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn new(resolution: [u32; 3], bounds_min: Vec3, bounds_max: Vec3, values: Vec<f32>) -> Self {
+        assert_eq!(
+            values.len(),
+            (resolution[0] * resolution[1] * resolution[2]) as usize,
+            "density grid value count does not match its resolution"
+        );
+
+        Self {
+            resolution,
+            bounds_min,
+            bounds_max,
+            values,
+        }
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.values.iter().copied().fold(0.0, f32::max)
+    }
+
+    // Cloudy ball of fractal value noise that fades out towards the box faces
+    pub fn noise_sphere(bounds_min: Vec3, bounds_max: Vec3, resolution: u32, seed: u32) -> Self {
+        let n = resolution.max(2);
+        let mut values = Vec::with_capacity((n * n * n) as usize);
+
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let p = (Vec3::new(x as f32, y as f32, z as f32) + 0.5) / n as f32;
+                    let radius = (p - 0.5).length() * 2.0;
+                    let falloff = (1.0 - radius).clamp(0.0, 1.0);
+
+                    let mut noise = 0.0;
+                    let mut amplitude = 0.5;
+                    let mut frequency = 4.0;
+                    for octave in 0..4 {
+                        noise += amplitude * value_noise(p * frequency, seed.wrapping_add(octave));
+                        amplitude *= 0.5;
+                        frequency *= 2.0;
+                    }
+
+                    values.push((falloff * 2.0 * noise).clamp(0.0, 1.0));
+                }
+            }
+        }
+
+        Self::new([n, n, n], bounds_min, bounds_max, values)
+    }
+}
+
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> f32 {
+    let mut h = seed
+        .wrapping_mul(0x9e37_79b9)
+        .wrapping_add((x as u32).wrapping_mul(0x85eb_ca6b))
+        .wrapping_add((y as u32).wrapping_mul(0xc2b2_ae35))
+        .wrapping_add((z as u32).wrapping_mul(0x27d4_eb2f));
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+fn value_noise(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let w = f * f * (3.0 - 2.0 * f);
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let corner = |dx: i32, dy: i32, dz: i32| hash(x + dx, y + dy, z + dz, seed);
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), w.x),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), w.x),
+            w.y,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), w.x),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), w.x),
+            w.y,
+        ),
+        w.z,
+    )
+}
@@ -0,0 +1,356 @@
+mod bvh;
+mod gpu;
+mod medium;
+mod preset;
+mod vdb;
+
+pub use bvh::{Aabb, Bvh, Ray};
+pub use gpu::{SceneBuffers, TIME_SLICES};
+pub use medium::{DensityGrid, Medium};
+pub use preset::Preset;
+
+use anyhow::Result;
+use glam::{Mat4, Vec3};
+use std::f32::consts::PI;
+use std::path::Path;
+
+// Triangle soup, three consecutive positions per triangle
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<[f32; 3]>) -> Self {
+        Self { positions }
+    }
+
+    // Axis aligned box from min to max, wound counter-clockwise seen from outside
+    pub fn cuboid(min: Vec3, max: Vec3) -> Self {
+        let corner = |x: bool, y: bool, z: bool| {
+            [
+                if x { max.x } else { min.x },
+                if y { max.y } else { min.y },
+                if z { max.z } else { min.z },
+            ]
+        };
+
+        // Each face as four corners in counter-clockwise order
+        let faces = [
+            [corner(true, false, false), corner(true, true, false), corner(true, true, true), corner(true, false, true)],
+            [corner(false, false, false), corner(false, false, true), corner(false, true, true), corner(false, true, false)],
+            [corner(false, true, false), corner(false, true, true), corner(true, true, true), corner(true, true, false)],
+            [corner(false, false, false), corner(true, false, false), corner(true, false, true), corner(false, false, true)],
+            [corner(false, false, true), corner(true, false, true), corner(true, true, true), corner(false, true, true)],
+            [corner(false, false, false), corner(false, true, false), corner(true, true, false), corner(true, false, false)],
+        ];
+
+        let positions = faces
+            .iter()
+            .flat_map(|[a, b, c, d]| [*a, *b, *c, *a, *c, *d])
+            .collect();
+
+        Self { positions }
+    }
+
+    // Two triangles spanning four corners in order
+    pub fn quad(corners: [[f32; 3]; 4]) -> Self {
+        let [a, b, c, d] = corners;
+        Self::new(vec![a, b, c, a, c, d])
+    }
+
+    // UV sphere, wound counter-clockwise seen from outside so it can bound a medium
+    pub fn sphere(center: Vec3, radius: f32, rings: u32, segments: u32) -> Self {
+        let point = |ring: u32, segment: u32| {
+            let theta = PI * ring as f32 / rings as f32;
+            let phi = 2.0 * PI * segment as f32 / segments as f32;
+            (center + radius * Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())).to_array()
+        };
+
+        let mut positions = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = point(ring, segment);
+                let b = point(ring, segment + 1);
+                let c = point(ring + 1, segment + 1);
+                let d = point(ring + 1, segment);
+                positions.extend([a, b, c, a, c, d]);
+            }
+        }
+        Self { positions }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Material {
+    pub base_color: Vec3,
+    // Perceptual roughness, squared for the GGX distribution
+    pub roughness: f32,
+    // Blends from a dielectric with 4% specular reflectance to a metal tinted by the base colour
+    pub metallic: f32,
+}
+
+impl Material {
+    pub fn diffuse(base_color: Vec3) -> Self {
+        Self {
+            base_color,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec3::splat(0.8),
+            roughness: 1.0,
+            metallic: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+pub struct Instance {
+    pub mesh: usize,
+    pub transform: Mat4,
+    // Transform at the start of the frame interval, None for static instances
+    pub previous_transform: Option<Mat4>,
+    pub material: usize,
+    // When set the mesh only bounds this medium and its surface is not shaded
+    pub interior_medium: Option<usize>,
+}
+
+impl Instance {
+    // Interpolated transform at a time in [0, 1] of the frame interval
+    pub fn transform_at(&self, time: f32) -> Mat4 {
+        let Some(previous) = self.previous_transform else {
+            return self.transform;
+        };
+
+        let (scale_a, rotation_a, translation_a) = previous.to_scale_rotation_translation();
+        let (scale_b, rotation_b, translation_b) = self.transform.to_scale_rotation_translation();
+        Mat4::from_scale_rotation_translation(
+            scale_a.lerp(scale_b, time),
+            rotation_a.slerp(rotation_b, time),
+            translation_a.lerp(translation_b, time),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub media: Vec<Medium>,
+    pub instances: Vec<Instance>,
+    pub lights: Vec<PointLight>,
+    // Media bounded by the box of their density grid instead of a mesh
+    pub volumes: Vec<usize>,
+    // Medium filling all space outside of mesh interiors
+    pub atmosphere: Option<usize>,
+    // Radiance arriving from every direction where rays leave the scene
+    pub environment: Vec3,
+}
+
+impl Scene {
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn add_medium(&mut self, medium: Medium) -> usize {
+        self.media.push(medium);
+        self.media.len() - 1
+    }
+
+    pub fn add_instance(&mut self, instance: Instance) -> usize {
+        self.instances.push(instance);
+        self.instances.len() - 1
+    }
+
+    // Instance of a new mesh that neither moves nor bounds a medium
+    pub(crate) fn add_static(&mut self, mesh: Mesh, transform: Mat4, material: usize) -> usize {
+        let mesh = self.add_mesh(mesh);
+        self.add_instance(Instance {
+            mesh,
+            transform,
+            previous_transform: None,
+            material,
+            interior_medium: None,
+        })
+    }
+
+    pub fn add_volume(&mut self, medium: Medium) -> usize {
+        assert!(medium.density.is_some(), "volumes need a density grid to bound them");
+        let medium = self.add_medium(medium);
+        self.volumes.push(medium);
+        medium
+    }
+
+    // Distance to the closest solid surface along a ray, medium boundaries are ignored
+    pub fn intersect(&self, origin: Vec3, direction: Vec3) -> Option<f32> {
+        self.instances
+            .iter()
+            .filter(|instance| instance.interior_medium.is_none())
+            .flat_map(|instance| {
+                self.meshes[instance.mesh]
+                    .positions
+                    .chunks_exact(3)
+                    .filter_map(move |triangle| {
+                        let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                            .map(|p| instance.transform.transform_point3(Vec3::from(p)));
+                        intersect_triangle(origin, direction, a, b, c)
+                    })
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    // Smoke from the "density" grid of a NanoVDB file, glowing if it has a "temperature" grid
+    pub fn add_nanovdb_volume(&mut self, path: &Path) -> Result<usize> {
+        let mut grids = vdb::load_nanovdb(path)?;
+
+        let density = match grids.iter().position(|g| g.name == "density") {
+            Some(index) => grids.swap_remove(index),
+            None if !grids.is_empty() => grids.swap_remove(0),
+            None => anyhow::bail!("{} contains no float grids", path.display()),
+        };
+        let temperature = grids
+            .into_iter()
+            .find(|g| g.name == "temperature")
+            .map(|g| g.grid);
+
+        println!(
+            "Loaded volume '{}' with {:?} voxels{}",
+            density.name,
+            density.grid.resolution,
+            if temperature.is_some() { " and temperature" } else { "" }
+        );
+
+        let mut medium = Medium::new(Vec3::splat(0.5), Vec3::splat(4.0), 0.2, Some(density.grid));
+        medium.temperature = temperature;
+
+        Ok(self.add_volume(medium))
+    }
+
+    // Adds the contents of a scene file, currently a NanoVDB volume
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        let is_nanovdb = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nvdb") || ext.eq_ignore_ascii_case("vdb"));
+        anyhow::ensure!(
+            is_nanovdb,
+            "Unsupported scene file {}, expected a NanoVDB volume (.nvdb)",
+            path.display()
+        );
+
+        self.add_nanovdb_volume(path)?;
+        Ok(())
+    }
+
+    // Floor, back wall and a small triangle sculpture lit by a red and a blue light,
+    // with a puff of smoke next to the sculpture
+    pub fn default_scene() -> Self {
+        let mut scene = Scene::default();
+
+        let world = scene.add_mesh(Mesh::new(vec![
+            [0.0, 1.5, 0.0],
+            [-0.5, 0.75, 0.0],
+            [0.25, 0.9, 0.0],
+            [-0.5, 1.0, 0.0],
+            [-0.1, 0.8, 0.7],
+            [0.55, 0.9, -0.4],
+            [-10.0, 0.0, -10.0],
+            [10.0, 0.0, -10.0],
+            [10.0, 0.0, 10.0],
+            [-10.0, 0.0, -10.0],
+            [10.0, 0.0, 10.0],
+            [-10.0, 0.0, 10.0],
+            [-10.0, 0.0, -5.0],
+            [10.0, 0.0, -5.0],
+            [10.0, 10.0, -5.0],
+            [-10.0, 0.0, -5.0],
+            [10.0, 10.0, -5.0],
+            [-10.0, 10.0, -5.0],
+        ]));
+
+        let white = scene.add_material(Material::default());
+
+        scene.add_instance(Instance {
+            mesh: world,
+            transform: Mat4::IDENTITY,
+            previous_transform: None,
+            material: white,
+            interior_medium: None,
+        });
+
+        let smoke_min = Vec3::new(1.0, 0.05, -1.5);
+        let smoke_max = Vec3::new(2.5, 1.5, 0.0);
+        let smoke_box = scene.add_mesh(Mesh::cuboid(smoke_min, smoke_max));
+        let smoke = scene.add_medium(Medium::new(
+            Vec3::splat(0.5),
+            Vec3::splat(6.0),
+            0.3,
+            Some(DensityGrid::noise_sphere(smoke_min, smoke_max, 48, 1)),
+        ));
+
+        scene.add_instance(Instance {
+            mesh: smoke_box,
+            transform: Mat4::IDENTITY,
+            previous_transform: None,
+            material: white,
+            interior_medium: Some(smoke),
+        });
+
+        scene.lights = vec![
+            PointLight {
+                position: Vec3::new(3.0, 1.0, 0.0),
+                color: Vec3::new(1.0, 0.1, 0.1),
+                intensity: 5.0,
+            },
+            PointLight {
+                position: Vec3::new(-3.0, 1.0, 0.0),
+                color: Vec3::new(0.1, 0.1, 1.0),
+                intensity: 5.0,
+            },
+        ];
+
+        scene
+    }
+}
+
+// Möller-Trumbore, returns the ray distance of a hit in front of the origin
+fn intersect_triangle(origin: Vec3, direction: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    (t > 1e-4).then_some(t)
+}
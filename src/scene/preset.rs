@@ -0,0 +1,234 @@
+// Built-in scenes selectable by name, framed for the default camera at (0, 2, 5) looking down -Z.
+// Besides the default scene they are classic validation setups with known expected results.
+
+use super::{Instance, Material, Mesh, PointLight, Scene};
+use glam::{Mat4, Vec3};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Preset {
+    /// Floor, back wall and a triangle sculpture next to a puff of smoke
+    Default,
+    /// Red and green walled box with two white blocks under a single light
+    CornellBox,
+    /// Gold spheres from smooth to rough left to right, dielectric to metal bottom to top
+    SphereGrid,
+    /// White spheres in uniform light, they vanish where the BSDF conserves energy
+    Furnace,
+    /// Long corridor lit by 64 coloured lights along the ceiling
+    Corridor,
+    /// Spheres sliding sideways at increasing speeds and a spinning block, blurred by instance motion
+    MotionBlur,
+}
+
+impl Preset {
+    pub fn build(self) -> Scene {
+        match self {
+            Self::Default => Scene::default_scene(),
+            Self::CornellBox => cornell_box(),
+            Self::SphereGrid => sphere_grid(),
+            Self::Furnace => furnace(),
+            Self::Corridor => corridor(),
+            Self::MotionBlur => motion_blur(),
+        }
+    }
+}
+
+fn cornell_box() -> Scene {
+    let mut scene = Scene::default();
+    let white = scene.add_material(Material::diffuse(Vec3::splat(0.73)));
+    let red = scene.add_material(Material::diffuse(Vec3::new(0.63, 0.065, 0.05)));
+    let green = scene.add_material(Material::diffuse(Vec3::new(0.14, 0.45, 0.091)));
+
+    let (x0, x1, y0, y1, z0, z1) = (-2.0, 2.0, 0.0, 4.0, -3.0, 1.0);
+    let walls = [
+        Mesh::quad([[x0, y0, z0], [x1, y0, z0], [x1, y0, z1], [x0, y0, z1]]),
+        Mesh::quad([[x0, y1, z0], [x0, y1, z1], [x1, y1, z1], [x1, y1, z0]]),
+        Mesh::quad([[x0, y0, z0], [x0, y1, z0], [x1, y1, z0], [x1, y0, z0]]),
+    ];
+    let positions = walls.into_iter().flat_map(|wall| wall.positions).collect();
+    scene.add_static(Mesh::new(positions), Mat4::IDENTITY, white);
+    scene.add_static(
+        Mesh::quad([[x0, y0, z0], [x0, y0, z1], [x0, y1, z1], [x0, y1, z0]]),
+        Mat4::IDENTITY,
+        red,
+    );
+    scene.add_static(
+        Mesh::quad([[x1, y0, z0], [x1, y1, z0], [x1, y1, z1], [x1, y0, z1]]),
+        Mat4::IDENTITY,
+        green,
+    );
+
+    let block = |height: f32| Mesh::cuboid(Vec3::new(-0.6, 0.0, -0.6), Vec3::new(0.6, height, 0.6));
+    scene.add_static(
+        block(2.4),
+        Mat4::from_translation(Vec3::new(-0.7, 0.0, -1.8)) * Mat4::from_rotation_y(0.3),
+        white,
+    );
+    scene.add_static(
+        block(1.2),
+        Mat4::from_translation(Vec3::new(0.8, 0.0, -0.4)) * Mat4::from_rotation_y(-0.3),
+        white,
+    );
+
+    scene.lights.push(PointLight {
+        position: Vec3::new(0.0, 3.2, -1.0),
+        color: Vec3::new(1.0, 0.85, 0.6),
+        intensity: 10.0,
+    });
+    scene
+}
+
+// Five by five spheres in front of a grey backdrop, both parameters step by 0.25
+fn sphere_grid() -> Scene {
+    let mut scene = Scene::default();
+    let backdrop = scene.add_material(Material::diffuse(Vec3::splat(0.3)));
+    let positions = [
+        Mesh::quad([[-10.0, 0.0, -3.0], [-10.0, 0.0, 10.0], [10.0, 0.0, 10.0], [10.0, 0.0, -3.0]]),
+        Mesh::quad([[-10.0, 0.0, -3.0], [10.0, 0.0, -3.0], [10.0, 10.0, -3.0], [-10.0, 10.0, -3.0]]),
+    ]
+    .into_iter()
+    .flat_map(|quad| quad.positions)
+    .collect();
+    scene.add_static(Mesh::new(positions), Mat4::IDENTITY, backdrop);
+
+    let sphere = scene.add_mesh(Mesh::sphere(Vec3::ZERO, 0.4, 16, 32));
+    for row in 0..5 {
+        for column in 0..5 {
+            let material = scene.add_material(Material {
+                base_color: Vec3::new(1.0, 0.77, 0.34),
+                roughness: column as f32 / 4.0,
+                metallic: row as f32 / 4.0,
+            });
+            scene.add_instance(Instance {
+                mesh: sphere,
+                transform: Mat4::from_translation(Vec3::new((column as f32 - 2.0) * 0.9, 0.4 + row as f32 * 0.9, -1.0)),
+                previous_transform: None,
+                material,
+                interior_medium: None,
+            });
+        }
+    }
+
+    scene.lights = vec![
+        PointLight {
+            position: Vec3::new(-2.5, 4.5, 3.0),
+            color: Vec3::ONE,
+            intensity: 40.0,
+        },
+        PointLight {
+            position: Vec3::new(3.0, 1.0, 4.0),
+            color: Vec3::new(0.6, 0.7, 1.0),
+            intensity: 15.0,
+        },
+    ];
+    scene.environment = Vec3::splat(0.1);
+    scene
+}
+
+// Smooth to rough spheres with a white base colour, dielectric in the bottom row and metallic in
+// the top one. Without lights every surface should show exactly the environment radiance.
+fn furnace() -> Scene {
+    let mut scene = Scene::default();
+    let sphere = scene.add_mesh(Mesh::sphere(Vec3::ZERO, 0.45, 16, 32));
+    for row in 0..2 {
+        for column in 0..5 {
+            let material = scene.add_material(Material {
+                base_color: Vec3::ONE,
+                roughness: column as f32 / 4.0,
+                metallic: row as f32,
+            });
+            scene.add_instance(Instance {
+                mesh: sphere,
+                transform: Mat4::from_translation(Vec3::new((column as f32 - 2.0) * 1.1, 1.4 + row as f32 * 1.2, 0.0)),
+                previous_transform: None,
+                material,
+                interior_medium: None,
+            });
+        }
+    }
+
+    scene.environment = Vec3::splat(0.5);
+    scene
+}
+
+// Instance motion over the frame interval, the left sphere stands still as a sharp reference
+fn motion_blur() -> Scene {
+    let mut scene = Scene::default();
+    let floor = scene.add_material(Material::diffuse(Vec3::splat(0.5)));
+    scene.add_static(
+        Mesh::quad([[-10.0, 0.0, -10.0], [-10.0, 0.0, 10.0], [10.0, 0.0, 10.0], [10.0, 0.0, -10.0]]),
+        Mat4::IDENTITY,
+        floor,
+    );
+
+    let sphere = scene.add_mesh(Mesh::sphere(Vec3::ZERO, 0.35, 16, 32));
+    let red = scene.add_material(Material::diffuse(Vec3::new(0.8, 0.1, 0.1)));
+    for i in 0..4 {
+        let position = Vec3::new(-2.25 + i as f32 * 1.5, 0.35, -1.0);
+        let distance = i as f32 * 0.4;
+        scene.add_instance(Instance {
+            mesh: sphere,
+            transform: Mat4::from_translation(position),
+            previous_transform: (i > 0).then(|| Mat4::from_translation(position - Vec3::X * distance)),
+            material: red,
+            interior_medium: None,
+        });
+    }
+
+    let block = scene.add_mesh(Mesh::cuboid(Vec3::splat(-0.4), Vec3::splat(0.4)));
+    let white = scene.add_material(Material::diffuse(Vec3::splat(0.8)));
+    let center = Mat4::from_translation(Vec3::new(0.0, 1.8, -1.5));
+    scene.add_instance(Instance {
+        mesh: block,
+        transform: center * Mat4::from_rotation_y(0.6),
+        previous_transform: Some(center),
+        material: white,
+        interior_medium: None,
+    });
+
+    scene.lights.push(PointLight {
+        position: Vec3::new(0.0, 4.0, 2.0),
+        color: Vec3::ONE,
+        intensity: 30.0,
+    });
+    scene.environment = Vec3::splat(0.1);
+    scene
+}
+
+// Stress test for direct lighting, every shading point samples all lights
+fn corridor() -> Scene {
+    let mut scene = Scene::default();
+    let walls = scene.add_material(Material::diffuse(Vec3::splat(0.6)));
+    let floor = scene.add_material(Material {
+        base_color: Vec3::splat(0.3),
+        roughness: 0.3,
+        metallic: 0.0,
+    });
+
+    let (x0, x1, y0, y1, z0, z1) = (-1.5, 1.5, 0.0, 3.0, -45.0, 8.0);
+    scene.add_static(
+        Mesh::quad([[x0, y0, z0], [x0, y0, z1], [x1, y0, z1], [x1, y0, z0]]),
+        Mat4::IDENTITY,
+        floor,
+    );
+    let positions = [
+        Mesh::quad([[x0, y1, z0], [x1, y1, z0], [x1, y1, z1], [x0, y1, z1]]),
+        Mesh::quad([[x0, y0, z0], [x0, y1, z0], [x0, y1, z1], [x0, y0, z1]]),
+        Mesh::quad([[x1, y0, z0], [x1, y0, z1], [x1, y1, z1], [x1, y1, z0]]),
+        Mesh::quad([[x0, y0, z0], [x1, y0, z0], [x1, y1, z0], [x0, y1, z0]]),
+    ]
+    .into_iter()
+    .flat_map(|quad| quad.positions)
+    .collect();
+    scene.add_static(Mesh::new(positions), Mat4::IDENTITY, walls);
+
+    let colors = [Vec3::new(1.0, 0.8, 0.5), Vec3::new(0.5, 0.7, 1.0), Vec3::new(1.0, 0.4, 0.6)];
+    scene.lights = (0..64)
+        .map(|i| PointLight {
+            position: Vec3::new(if i % 2 == 0 { -1.1 } else { 1.1 }, 2.8, 6.0 - (i / 2) as f32 * 1.5),
+            color: colors[i % colors.len()],
+            intensity: 1.5,
+        })
+        .collect();
+    scene
+}
@@ -0,0 +1,400 @@
+// Reader for uncompressed NanoVDB files (.nvdb) holding float grids.
+//
+// OpenVDB files use a compressed, versioned tree serialization, convert them first with
+// `nanovdb_convert input.vdb output.nvdb`. Sparse trees are densified into a DensityGrid
+// spanning the active index bounding box.
+
+use super::DensityGrid;
+use anyhow::{Context, Result, bail, ensure};
+use glam::{DVec3, IVec3, Vec3};
+use std::path::Path;
+
+const MAGIC_NUMBER: u64 = 0x304244566f6e614e; // "NanoVDB0"
+const MAGIC_FILE: u64 = 0x324244566f6e614e; // "NanoVDB2"
+const MAGIC_GRID: u64 = 0x314244566f6e614e; // "NanoVDB1"
+
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_META_DATA_SIZE: usize = 176;
+const GRID_DATA_SIZE: usize = 672;
+
+const GRID_TYPE_FLOAT: u32 = 1;
+const CODEC_NONE: u16 = 0;
+
+// Node layouts of a float tree, see NanoVDB.h
+const LEAF_SIZE: usize = 2144;
+const LEAF_VALUE_MASK: usize = 16;
+const LEAF_VALUES: usize = 96;
+const LOWER_SIZE: usize = 33856;
+const UPPER_SIZE: usize = 270400;
+const INTERNAL_VALUE_MASK: usize = 32;
+const ROOT_BACKGROUND: usize = 28;
+const ROOT_TABLE_SIZE: usize = 24;
+const ROOT_TILES: usize = 64;
+const ROOT_TILE_SIZE: usize = 32;
+// Voxels per axis of a root tile, the extent of an upper node
+const ROOT_TILE_DIM: i32 = 4096;
+
+// Largest dense grid we are willing to allocate
+const MAX_VOXELS: u64 = 1 << 28;
+
+pub struct VdbGrid {
+    pub name: String,
+    pub grid: DensityGrid,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.bytes
+            .get(offset..offset + len)
+            .context("NanoVDB file is truncated")
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.slice(offset, 2)?.try_into()?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.slice(offset, 4)?.try_into()?))
+    }
+
+    fn i32(&self, offset: usize) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.slice(offset, 4)?.try_into()?))
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.slice(offset, 8)?.try_into()?))
+    }
+
+    fn f32(&self, offset: usize) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.slice(offset, 4)?.try_into()?))
+    }
+
+    fn f64(&self, offset: usize) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.slice(offset, 8)?.try_into()?))
+    }
+
+    fn coord(&self, offset: usize) -> Result<IVec3> {
+        Ok(IVec3::new(self.i32(offset)?, self.i32(offset + 4)?, self.i32(offset + 8)?))
+    }
+
+    fn mask_bit(&self, mask_offset: usize, n: usize) -> Result<bool> {
+        Ok(self.u64(mask_offset + (n >> 6) * 8)? & (1 << (n & 63)) != 0)
+    }
+}
+
+// Dense destination covering the inclusive index bounding box
+struct DenseGrid {
+    min: IVec3,
+    size: IVec3,
+    values: Vec<f32>,
+}
+
+impl DenseGrid {
+    fn fill(&mut self, origin: IVec3, dim: i32, value: f32) {
+        let lo = origin.max(self.min) - self.min;
+        let hi = (origin + dim).min(self.min + self.size) - self.min;
+        for z in lo.z..hi.z {
+            for y in lo.y..hi.y {
+                for x in lo.x..hi.x {
+                    self.values[(x + self.size.x * (y + self.size.y * z)) as usize] = value;
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, ijk: IVec3, value: f32) {
+        let p = ijk - self.min;
+        if p.cmpge(IVec3::ZERO).all() && p.cmplt(self.size).all() {
+            self.values[(p.x + self.size.x * (p.y + self.size.y * p.z)) as usize] = value;
+        }
+    }
+}
+
+// Origin of a root tile from its key, 21 bits per axis of the coordinate divided by 4096
+fn root_key_origin(key: u64) -> IVec3 {
+    let axis = |shift: u32| ((((key >> shift) & 0x1f_ffff) as u32) << 12) as i32;
+    IVec3::new(axis(42), axis(21), axis(0))
+}
+
+// Active constant tiles of the root, each spanning a whole upper node
+fn read_root_tiles(reader: &Reader, root: usize, dense: &mut DenseGrid) -> Result<()> {
+    let tile_count = reader.u32(root + ROOT_TABLE_SIZE)? as usize;
+    for tile in 0..tile_count {
+        let base = root + ROOT_TILES + tile * ROOT_TILE_SIZE;
+        let child = reader.u64(base + 8)?;
+        let active = reader.u32(base + 16)? != 0;
+        if child == 0 && active {
+            let value = reader.f32(base + 20)?;
+            dense.fill(root_key_origin(reader.u64(base)?), ROOT_TILE_DIM, value);
+        }
+    }
+    Ok(())
+}
+
+// Active tiles of internal nodes with 2^log2_dim children per axis of child_dim voxels each
+fn read_internal_tiles(
+    reader: &Reader,
+    first: usize,
+    count: usize,
+    node_size: usize,
+    log2_dim: u32,
+    child_dim: i32,
+    dense: &mut DenseGrid,
+) -> Result<()> {
+    let table_size = 1usize << (3 * log2_dim);
+    let mask_size = table_size / 8;
+    let child_mask = INTERNAL_VALUE_MASK + mask_size;
+    let table = (child_mask + mask_size + 16).next_multiple_of(32);
+    let node_dim = child_dim << log2_dim;
+
+    for node in 0..count {
+        let base = first + node * node_size;
+        let origin = reader.coord(base)? & !(node_dim - 1);
+
+        for n in 0..table_size {
+            if !reader.mask_bit(base + INTERNAL_VALUE_MASK, n)? || reader.mask_bit(base + child_mask, n)? {
+                continue;
+            }
+
+            let local = IVec3::new(
+                (n >> (2 * log2_dim)) as i32,
+                ((n >> log2_dim) & ((1 << log2_dim) - 1)) as i32,
+                (n & ((1 << log2_dim) - 1)) as i32,
+            );
+            let value = reader.f32(base + table + n * 8)?;
+            dense.fill(origin + local * child_dim, child_dim, value);
+        }
+    }
+    Ok(())
+}
+
+fn read_float_grid(reader: &Reader, grid: usize, index_min: IVec3, index_max: IVec3) -> Result<DensityGrid> {
+    let magic = reader.u64(grid)?;
+    ensure!(
+        magic == MAGIC_NUMBER || magic == MAGIC_GRID,
+        "NanoVDB grid has an invalid magic number {magic:#x}"
+    );
+
+    // Index to world map, only scale and translation are supported
+    let map = grid + 296;
+    let mat: Vec<f64> = (0..9).map(|i| reader.f64(map + 88 + i * 8)).collect::<Result<_>>()?;
+    let translation = DVec3::new(reader.f64(map + 232)?, reader.f64(map + 240)?, reader.f64(map + 248)?);
+    ensure!(
+        [1, 2, 3, 5, 6, 7].iter().all(|&i| mat[i] == 0.0),
+        "NanoVDB grids with rotated or sheared transforms are not supported"
+    );
+    let scale = DVec3::new(mat[0], mat[4], mat[8]);
+
+    let size = index_max - index_min + 1;
+    ensure!(size.cmpgt(IVec3::ZERO).all(), "NanoVDB grid has no active voxels");
+    let voxel_count = size.x as u64 * size.y as u64 * size.z as u64;
+    ensure!(
+        voxel_count <= MAX_VOXELS,
+        "NanoVDB grid of {}x{}x{} voxels is too large to densify",
+        size.x,
+        size.y,
+        size.z
+    );
+
+    let tree = grid + GRID_DATA_SIZE;
+    let leaves = tree + reader.u64(tree)? as usize;
+    let lower = tree + reader.u64(tree + 8)? as usize;
+    let upper = tree + reader.u64(tree + 16)? as usize;
+    let root = tree + reader.u64(tree + 24)? as usize;
+    let leaf_count = reader.u32(tree + 32)? as usize;
+    let lower_count = reader.u32(tree + 36)? as usize;
+    let upper_count = reader.u32(tree + 40)? as usize;
+
+    // Voxels without an active value keep the background
+    let mut dense = DenseGrid {
+        min: index_min,
+        size,
+        values: vec![reader.f32(root + ROOT_BACKGROUND)?; voxel_count as usize],
+    };
+
+    // Coarse tiles first so finer nodes overwrite them
+    read_root_tiles(reader, root, &mut dense)?;
+    read_internal_tiles(reader, upper, upper_count, UPPER_SIZE, 5, 128, &mut dense)?;
+    read_internal_tiles(reader, lower, lower_count, LOWER_SIZE, 4, 8, &mut dense)?;
+
+    for leaf in 0..leaf_count {
+        let base = leaves + leaf * LEAF_SIZE;
+        let origin = reader.coord(base)? & !7;
+        for n in 0..512 {
+            if reader.mask_bit(base + LEAF_VALUE_MASK, n)? {
+                let local = IVec3::new((n >> 6) as i32, ((n >> 3) & 7) as i32, (n & 7) as i32);
+                dense.set(origin + local, reader.f32(base + LEAF_VALUES + n * 4)?);
+            }
+        }
+    }
+
+    // Voxel centers sit on integer index coordinates
+    let to_world = |ijk: DVec3| (ijk * scale + translation).as_vec3();
+    let corner_a = to_world(index_min.as_dvec3() - 0.5);
+    let corner_b = to_world(index_max.as_dvec3() + 0.5);
+
+    Ok(DensityGrid::new(
+        [size.x as u32, size.y as u32, size.z as u32],
+        Vec3::min(corner_a, corner_b),
+        Vec3::max(corner_a, corner_b),
+        dense.values,
+    ))
+}
+
+pub fn load_nanovdb(path: &Path) -> Result<Vec<VdbGrid>> {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("vdb")) {
+        bail!(
+            "OpenVDB files can not be loaded directly, convert {} with `nanovdb_convert` first",
+            path.display()
+        );
+    }
+
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_nanovdb(&bytes).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+fn parse_nanovdb(bytes: &[u8]) -> Result<Vec<VdbGrid>> {
+    let reader = Reader { bytes };
+    let mut grids = Vec::new();
+    let mut offset = 0;
+
+    // A file is a sequence of segments, each a header, the grid meta data and the grids
+    while offset < bytes.len() {
+        let magic = reader.u64(offset)?;
+        ensure!(magic == MAGIC_NUMBER || magic == MAGIC_FILE, "Not a NanoVDB file");
+        let grid_count = reader.u16(offset + 12)? as usize;
+        let codec = reader.u16(offset + 14)?;
+        ensure!(
+            codec == CODEC_NONE,
+            "Compressed NanoVDB files are not supported, save them without a codec"
+        );
+        offset += FILE_HEADER_SIZE;
+
+        let mut metas = Vec::with_capacity(grid_count);
+        for _ in 0..grid_count {
+            let grid_size = reader.u64(offset)? as usize;
+            let grid_type = reader.u32(offset + 32)?;
+            let index_min = reader.coord(offset + 88)?;
+            let index_max = reader.coord(offset + 100)?;
+            let name_size = reader.u32(offset + 136)? as usize;
+            offset += FILE_META_DATA_SIZE;
+
+            let name = reader.slice(offset, name_size)?;
+            let name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(name)).into_owned();
+            offset += name_size;
+
+            metas.push((name, grid_size, grid_type, index_min, index_max));
+        }
+
+        for (name, grid_size, grid_type, index_min, index_max) in metas {
+            if grid_type == GRID_TYPE_FLOAT {
+                let grid = read_float_grid(&reader, offset, index_min, index_max)
+                    .with_context(|| format!("Failed to read grid '{name}'"))?;
+                grids.push(VdbGrid { name, grid });
+            } else {
+                println!("Skipping NanoVDB grid '{name}' of unsupported type {grid_type}");
+            }
+            offset += grid_size;
+        }
+    }
+
+    Ok(grids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE_HEADER: usize = 64;
+    const ROOT: usize = TREE_HEADER;
+    const LEAF: usize = ROOT + ROOT_TILES + ROOT_TILE_SIZE;
+    const GRID_SIZE: usize = GRID_DATA_SIZE + LEAF + LEAF_SIZE;
+
+    fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    // Unit voxels offset by one in x, one leaf at the origin and an active root tile next to it
+    fn grid(background: f32, voxel: f32, tile: f32) -> Vec<u8> {
+        let mut bytes = vec![0; GRID_SIZE];
+        put(&mut bytes, 0, &MAGIC_GRID.to_le_bytes());
+        for i in [0, 4, 8] {
+            put(&mut bytes, 296 + 88 + i * 8, &1.0f64.to_le_bytes());
+        }
+        put(&mut bytes, 296 + 232, &1.0f64.to_le_bytes());
+
+        let tree = GRID_DATA_SIZE;
+        put(&mut bytes, tree, &(LEAF as u64).to_le_bytes());
+        put(&mut bytes, tree + 24, &(ROOT as u64).to_le_bytes());
+        put(&mut bytes, tree + 32, &1u32.to_le_bytes());
+
+        let root = tree + ROOT;
+        put(&mut bytes, root + ROOT_TABLE_SIZE, &1u32.to_le_bytes());
+        put(&mut bytes, root + ROOT_BACKGROUND, &background.to_le_bytes());
+        let root_tile = root + ROOT_TILES;
+        put(&mut bytes, root_tile, &(1u64 << 42).to_le_bytes());
+        put(&mut bytes, root_tile + 16, &1u32.to_le_bytes());
+        put(&mut bytes, root_tile + 20, &tile.to_le_bytes());
+
+        // Voxels (0, 0, 0) and (1, 2, 3) are active
+        let leaf = tree + LEAF;
+        for n in [0, (1 << 6) | (2 << 3) | 3] {
+            bytes[leaf + LEAF_VALUE_MASK + n / 8] |= 1 << (n % 8);
+            put(&mut bytes, leaf + LEAF_VALUES + n * 4, &(voxel + n as f32).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn meta(name: &str, index_max: IVec3) -> Vec<u8> {
+        let mut bytes = vec![0; FILE_META_DATA_SIZE];
+        put(&mut bytes, 0, &(GRID_SIZE as u64).to_le_bytes());
+        put(&mut bytes, 32, &GRID_TYPE_FLOAT.to_le_bytes());
+        for (i, value) in index_max.to_array().into_iter().enumerate() {
+            put(&mut bytes, 100 + i * 4, &value.to_le_bytes());
+        }
+        put(&mut bytes, 136, &(name.len() as u32 + 1).to_le_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn reads_leaves_root_tiles_and_background() {
+        let index_max = IVec3::new(4096, 3, 3);
+        let mut bytes = vec![0; FILE_HEADER_SIZE];
+        put(&mut bytes, 0, &MAGIC_FILE.to_le_bytes());
+        put(&mut bytes, 12, &2u16.to_le_bytes());
+        bytes.extend(meta("density", index_max));
+        bytes.extend(meta("temperature", index_max));
+        bytes.extend(grid(0.0, 0.5, 0.25));
+        bytes.extend(grid(300.0, 1000.0, 1500.0));
+
+        let grids = parse_nanovdb(&bytes).unwrap();
+        let names: Vec<_> = grids.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["density", "temperature"]);
+
+        let resolution = [4097, 4, 4];
+        let value = |grid: &DensityGrid, [x, y, z]: [usize; 3]| grid.values[x + 4097 * (y + 4 * z)];
+        for (grid, background, voxel, tile) in [(&grids[0].grid, 0.0, 0.5, 0.25), (&grids[1].grid, 300.0, 1000.0, 1500.0)] {
+            assert_eq!(grid.resolution, resolution);
+            assert_eq!(grid.bounds_min, Vec3::new(0.5, -0.5, -0.5));
+            assert_eq!(grid.bounds_max, Vec3::new(4097.5, 3.5, 3.5));
+            assert_eq!(value(grid, [0, 0, 0]), voxel);
+            assert_eq!(value(grid, [1, 2, 3]), voxel + 83.0);
+            assert_eq!(value(grid, [1, 1, 1]), background);
+            assert_eq!(value(grid, [4095, 3, 3]), background);
+            assert_eq!(value(grid, [4096, 0, 0]), tile);
+            assert_eq!(value(grid, [4096, 3, 3]), tile);
+        }
+    }
+
+    #[test]
+    fn decodes_root_keys() {
+        let key = (3u64 << 42) | (1 << 21) | 0x1f_ffff;
+        assert_eq!(root_key_origin(key), IVec3::new(3 * 4096, 4096, -4096));
+    }
+}
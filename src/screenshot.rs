@@ -0,0 +1,177 @@
+// Writing rendered images to disk with the camera and render settings as metadata.
+// PNGs hold the tonemapped output in sRGB, OpenEXR and Radiance HDR files linear radiance.
+// EXR files also carry the AOVs as extra layers.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Tonemapped 8-bit PNG
+    Png,
+    /// Linear OpenEXR
+    Exr,
+    /// Linear Radiance RGBE
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "exr" => Some(Self::Exr),
+            "hdr" => Some(Self::Hdr),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+            Self::Hdr => "hdr",
+        }
+    }
+
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Self::Exr | Self::Hdr)
+    }
+}
+
+pub enum Samples {
+    F32(Vec<f32>),
+    U32(Vec<u32>),
+}
+
+// Additional EXR channel, named layer.channel like albedo.R
+pub struct Channel {
+    pub name: String,
+    pub samples: Samples,
+}
+
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    // Linear Rec. 709 RGBA rows from the top left, tonemapped for PNGs
+    pub pixels: Vec<f32>,
+    // Only written to EXR files
+    pub channels: Vec<Channel>,
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Screenshot {
+    pub fn save(&self, path: &Path, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Png => self.save_png(path),
+            OutputFormat::Exr => self.save_exr(path),
+            OutputFormat::Hdr => self.save_hdr(path),
+        }
+        .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    // Metadata goes into tEXt chunks, colours are encoded with the sRGB transfer function
+    fn save_png(&self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        for (key, value) in &self.metadata {
+            encoder.add_text_chunk(key.clone(), value.clone())?;
+        }
+
+        let bytes: Vec<u8> = self
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let v = if i % 4 == 3 { v } else { srgb_oetf(v) };
+                (v.clamp(0.0, 1.0) * 255.0).round() as u8
+            })
+            .collect();
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&bytes)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    // Metadata goes into custom text attributes of the layer header
+    fn save_exr(&self, path: &Path) -> Result<()> {
+        use exr::prelude::*;
+
+        let rgba = ["R", "G", "B", "A"].into_iter().enumerate().map(|(component, name)| {
+            let samples = self.pixels.iter().skip(component).step_by(4).copied().collect();
+            AnyChannel::new(name, FlatSamples::F32(samples))
+        });
+        let aovs = self.channels.iter().map(|channel| {
+            let samples = match &channel.samples {
+                Samples::F32(samples) => FlatSamples::F32(samples.clone()),
+                Samples::U32(samples) => FlatSamples::U32(samples.clone()),
+            };
+            AnyChannel::new(channel.name.as_str(), samples)
+        });
+
+        let mut attributes = LayerAttributes::named("rgba");
+        attributes.software_name = Some(Text::from(concat!("vulkano_pathtracer ", env!("CARGO_PKG_VERSION"))));
+        for (key, value) in &self.metadata {
+            attributes
+                .other
+                .insert(Text::from(key.as_str()), AttributeValue::Text(Text::from(value.as_str())));
+        }
+
+        let layer = Layer::new(
+            (self.width as usize, self.height as usize),
+            attributes,
+            Encoding::SMALL_LOSSLESS,
+            AnyChannels::sort(rgba.chain(aovs).collect()),
+        );
+
+        Image::from_layer(layer).write().to_file(path)?;
+        Ok(())
+    }
+
+    // Uncompressed RGBE scanlines, metadata goes into header comments
+    fn save_hdr(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "#?RADIANCE")?;
+        for (key, value) in &self.metadata {
+            writeln!(file, "# {key}: {value}")?;
+        }
+        writeln!(file, "FORMAT=32-bit_rle_rgbe")?;
+        writeln!(file)?;
+        writeln!(file, "-Y {} +X {}", self.height, self.width)?;
+
+        for pixel in self.pixels.chunks_exact(4) {
+            file.write_all(&rgbe(pixel[0], pixel[1], pixel[2]))?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+// Same curve as srgb_oetf in color.glsl
+pub fn srgb_oetf(linear: f32) -> f32 {
+    let x = linear.clamp(0.0, 1.0);
+    if x > 0.0031308 {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    } else {
+        12.92 * x
+    }
+}
+
+// Shared exponent encoding, the mantissas keep 8 bits relative to the largest component
+fn rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let max = r.max(g).max(b);
+    if !max.is_finite() || max <= 1e-32 {
+        return [0; 4];
+    }
+
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    let mantissa = |v: f32| (v.max(0.0) * scale).min(255.0) as u8;
+    [mantissa(r), mantissa(g), mantissa(b), (exponent + 128) as u8]
+}
@@ -0,0 +1,53 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+
+// Decides which tiles keep receiving samples. One workgroup per tile estimates the error of
+// every pixel from the luminance variance raygen tracks, a tile stays active while its worst
+// pixel is above the target. Active tiles are counted so the host knows when to stop.
+
+layout(local_size_x = ADAPTIVE_TILE_SIZE, local_size_y = ADAPTIVE_TILE_SIZE) in;
+
+layout(binding = 0, set = 0, rgba32f) uniform readonly image2D sample_stats;
+layout(binding = 1, set = 0, r32ui) uniform writeonly uimage2D tile_mask;
+layout(binding = 2, set = 0, std430) buffer ActiveTiles { uint active_tiles; };
+
+layout(push_constant) uniform PushConstants {
+	float target_error;
+} pc;
+
+shared float tile_error[ADAPTIVE_TILE_SIZE * ADAPTIVE_TILE_SIZE];
+
+// Standard error of the mean relative to the square root of the mean, which roughly follows
+// how visible noise is after tonemapping in dark and bright regions alike
+float pixel_error(vec4 stats) {
+	float count = stats.x;
+	if (count < 2.0) {
+		return 1e30;
+	}
+	float variance = stats.z / (count - 1.0);
+	return sqrt(variance / count) / sqrt(max(stats.y, 0.0) + 1e-4);
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	uint index = gl_LocalInvocationIndex;
+	tile_error[index] = all(lessThan(pixel, imageSize(sample_stats))) ? pixel_error(imageLoad(sample_stats, pixel)) : 0.0;
+	barrier();
+
+	for (uint stride = ADAPTIVE_TILE_SIZE * ADAPTIVE_TILE_SIZE / 2; stride > 0; stride >>= 1) {
+		if (index < stride) {
+			tile_error[index] = max(tile_error[index], tile_error[index + stride]);
+		}
+		barrier();
+	}
+
+	if (index == 0) {
+		bool needs_samples = tile_error[0] > pc.target_error;
+		imageStore(tile_mask, ivec2(gl_WorkGroupID.xy), uvec4(needs_samples ? 1 : 0));
+		if (needs_samples) {
+			atomicAdd(active_tiles, 1);
+		}
+	}
+}
@@ -0,0 +1,102 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "spectrum.glsl"
+#include "color.glsl"
+
+// One iteration of the edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), run
+// between tracing and tonemapping. The 5x5 B3 spline kernel is spread by step_width and
+// weighted by the first hit AOVs so edges and textures survive. Mirrored by the CPU
+// reference in renderer/denoise.rs.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0, set = 0, rgba32f) uniform readonly image2D input_image;
+layout(binding = 1, set = 0, rgba32f) uniform writeonly image2D output_image;
+layout(binding = 2, set = 0, rgba16f) uniform readonly image2D aov_albedo;
+layout(binding = 3, set = 0, rgba16f) uniform readonly image2D aov_normal;
+layout(binding = 4, set = 0, r32f) uniform readonly image2D aov_depth;
+
+layout(push_constant) uniform PushConstants {
+	int step_width;
+	float color_sigma; // relative to the luminance of both pixels
+	float normal_power;
+	float depth_sigma; // relative to the center depth, per pixel of distance
+	float albedo_sigma;
+	uint input_space; // COLOR_SPACE_ of the accumulation
+} pc;
+
+const float KERNEL[3] = float[](3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+struct Guide {
+	vec3 color;
+	float luminance;
+	vec3 albedo;
+	vec3 normal;
+	float depth;
+};
+
+Guide load_guide(ivec2 pixel) {
+	Guide guide;
+	guide.color = imageLoad(input_image, pixel).rgb;
+	guide.luminance = max(luminance_in(guide.color, pc.input_space), 0.0);
+	guide.albedo = imageLoad(aov_albedo, pixel).rgb;
+	guide.normal = imageLoad(aov_normal, pixel).xyz;
+	guide.depth = imageLoad(aov_depth, pixel).r;
+	return guide;
+}
+
+// Product of the edge stopping functions, 1 for identical pixels
+float edge_weight(Guide center, Guide sample_, float distance) {
+	if (sample_.depth <= 0.0) {
+		return 0.0;
+	}
+
+	float w_normal = pow(max(dot(center.normal, sample_.normal), 0.0), pc.normal_power);
+	float w_depth = exp(-abs(center.depth - sample_.depth) / (pc.depth_sigma * center.depth * distance + 1e-6));
+
+	vec3 albedo_difference = center.albedo - sample_.albedo;
+	float w_albedo = exp(-dot(albedo_difference, albedo_difference) / (pc.albedo_sigma * pc.albedo_sigma));
+
+	vec3 color_difference = center.color - sample_.color;
+	float scale = pc.color_sigma * (center.luminance + sample_.luminance);
+	float w_color = exp(-dot(color_difference, color_difference) / (scale * scale + 1e-6));
+
+	return w_normal * w_depth * w_albedo * w_color;
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(input_image);
+	if (any(greaterThanEqual(pixel, size))) {
+		return;
+	}
+
+	vec4 center_value = imageLoad(input_image, pixel);
+	Guide center = load_guide(pixel);
+	// Nothing to guide the filter where camera rays escaped
+	if (center.depth <= 0.0) {
+		imageStore(output_image, pixel, center_value);
+		return;
+	}
+
+	vec3 sum = vec3(0.0);
+	float weight_sum = 0.0;
+	for (int y = -2; y <= 2; y++) {
+		for (int x = -2; x <= 2; x++) {
+			ivec2 offset = ivec2(x, y) * pc.step_width;
+			ivec2 q = pixel + offset;
+			if (any(lessThan(q, ivec2(0))) || any(greaterThanEqual(q, size))) {
+				continue;
+			}
+
+			Guide sample_ = load_guide(q);
+			float weight = KERNEL[abs(x)] * KERNEL[abs(y)] * edge_weight(center, sample_, length(vec2(offset)));
+			sum += sample_.color * weight;
+			weight_sum += weight;
+		}
+	}
+
+	imageStore(output_image, pixel, vec4(sum / weight_sum, center_value.a));
+}
@@ -0,0 +1,95 @@
+// Metallic-roughness surfaces: a Lambertian base under a GGX specular layer with Schlick
+// Fresnel and height-correlated Smith shadowing, mirrored by cpu/bsdf.rs.
+// Directions are in the shading frame with the normal as z, wo points towards the viewer.
+
+// Smallest GGX alpha, smoother surfaces become numerically unstable
+#define MIN_ALPHA 0.001
+
+struct Bsdf {
+	vec3 albedo; // base colour in the representation of the path
+	float alpha;
+	vec3 f0; // specular reflectance at normal incidence
+	float diffuse_weight;
+};
+
+Bsdf make_bsdf(vec3 albedo, float roughness, float metallic) {
+	return Bsdf(albedo, max(roughness * roughness, MIN_ALPHA), mix(vec3(0.04), albedo, metallic), 1.0 - metallic);
+}
+
+vec3 fresnel_schlick(vec3 f0, float cos_theta) {
+	return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+float ggx_distribution(float cos_h, float alpha) {
+	float a2 = alpha * alpha;
+	float d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+float smith_lambda(float cos_theta, float alpha) {
+	float cos2 = cos_theta * cos_theta;
+	float tan2 = max(1.0 - cos2, 0.0) / cos2;
+	return 0.5 * (sqrt(1.0 + alpha * alpha * tan2) - 1.0);
+}
+
+// BSDF times the cosine of wi. The base is weighted by the Fresnel transmission in both
+// directions, which keeps it reciprocal and the albedo at most one.
+vec3 bsdf_eval(Bsdf bsdf, vec3 wo, vec3 wi) {
+	if (wo.z <= 0.0 || wi.z <= 0.0) {
+		return vec3(0.0);
+	}
+
+	vec3 h = normalize(wo + wi);
+	float g2 = 1.0 / (1.0 + smith_lambda(wo.z, bsdf.alpha) + smith_lambda(wi.z, bsdf.alpha));
+	vec3 specular = fresnel_schlick(bsdf.f0, dot(wo, h)) * ggx_distribution(h.z, bsdf.alpha) * g2 / (4.0 * wo.z * wi.z);
+	vec3 transmission = (1.0 - fresnel_schlick(bsdf.f0, wo.z)) * (1.0 - fresnel_schlick(bsdf.f0, wi.z));
+	vec3 diffuse = bsdf.diffuse_weight * bsdf.albedo / PI * transmission;
+	return (specular + diffuse) * wi.z;
+}
+
+// Chance of sampling the specular lobe, by its reflectance relative to the base
+float specular_probability(Bsdf bsdf, vec3 wo) {
+	float specular = max3(fresnel_schlick(bsdf.f0, wo.z));
+	float diffuse = bsdf.diffuse_weight * max3(bsdf.albedo) * (1.0 - specular);
+	return specular + diffuse > 0.0 ? specular / (specular + diffuse) : 1.0;
+}
+
+// Visible normal of the GGX distribution seen from wo (Heitz 2018)
+vec3 sample_ggx_visible_normal(vec3 wo, float alpha, vec2 u) {
+	vec3 v = normalize(vec3(alpha * wo.x, alpha * wo.y, wo.z));
+	float length2 = v.x * v.x + v.y * v.y;
+	vec3 t1 = length2 > 0.0 ? vec3(-v.y, v.x, 0.0) * inversesqrt(length2) : vec3(1.0, 0.0, 0.0);
+	vec3 t2 = cross(v, t1);
+
+	float r = sqrt(u.x);
+	float phi = 2.0 * PI * u.y;
+	float p1 = r * cos(phi);
+	float s = 0.5 * (1.0 + v.z);
+	float p2 = (1.0 - s) * sqrt(max(0.0, 1.0 - p1 * p1)) + s * r * sin(phi);
+	vec3 n = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * v;
+	return normalize(vec3(alpha * n.x, alpha * n.y, max(0.0, n.z)));
+}
+
+float bsdf_pdf(Bsdf bsdf, vec3 wo, vec3 wi) {
+	if (wo.z <= 0.0 || wi.z <= 0.0) {
+		return 0.0;
+	}
+
+	vec3 h = normalize(wo + wi);
+	float specular = ggx_distribution(h.z, bsdf.alpha) / ((1.0 + smith_lambda(wo.z, bsdf.alpha)) * 4.0 * wo.z);
+	return mix(wi.z / PI, specular, specular_probability(bsdf, wo));
+}
+
+// Picks a lobe with u_lobe and a direction in it with u, weight is the BSDF times the cosine
+// over the combined pdf. False when the direction points below the surface.
+bool bsdf_sample(Bsdf bsdf, vec3 wo, float u_lobe, vec2 u, out vec3 wi, out vec3 weight) {
+	if (u_lobe < specular_probability(bsdf, wo)) {
+		wi = reflect(-wo, sample_ggx_visible_normal(wo, bsdf.alpha, u));
+	} else {
+		wi = sample_cosine_hemisphere(u);
+	}
+
+	float pdf = bsdf_pdf(bsdf, wo, wi);
+	weight = pdf > 0.0 ? bsdf_eval(bsdf, wo, wi) / pdf : vec3(0.0);
+	return pdf > 0.0;
+}
@@ -0,0 +1,81 @@
+// Colour spaces of the accumulation and transfer functions of the display output.
+// Expects spectrum.glsl to be included first.
+
+// WorkingSpace in renderer/color.rs, the spectral integrator accumulates CIE XYZ instead
+#define COLOR_SPACE_REC709 0
+#define COLOR_SPACE_ACESCG 1
+#define COLOR_SPACE_XYZ 2
+
+// OutputEncoding in renderer/color.rs
+#define OUTPUT_LINEAR 0 // encoded by the sRGB swapchain format or when saving
+#define OUTPUT_SRGB 1
+#define OUTPUT_SCRGB 2
+#define OUTPUT_HDR10 3
+
+// Linear Rec. 709 and AP1 primaries, including the D65 to D60 Bradford adaptation
+const mat3 REC709_TO_ACESCG = mat3(
+	0.6130974024, 0.0701937225, 0.0206155929,
+	0.3395231462, 0.9163538791, 0.1095697729,
+	0.0473794514, 0.0134523985, 0.8698146342
+);
+const mat3 ACESCG_TO_REC709 = mat3(
+	1.7048586763, -0.1300768242, -0.0239640729,
+	-0.6217160219, 1.1407357748, -0.1289755083,
+	-0.0831426544, -0.0106589506, 1.1529395812
+);
+const mat3 REC709_TO_REC2020 = mat3(
+	0.6274040, 0.0690970, 0.0163916,
+	0.3292820, 0.9195400, 0.0880132,
+	0.0433136, 0.0113612, 0.8955950
+);
+
+vec3 to_rec709(vec3 color, uint space) {
+	switch (space) {
+	case COLOR_SPACE_ACESCG:
+		return ACESCG_TO_REC709 * color;
+	case COLOR_SPACE_XYZ:
+		return xyz_to_linear_srgb(color);
+	}
+	return color;
+}
+
+float luminance_in(vec3 color, uint space) {
+	switch (space) {
+	case COLOR_SPACE_ACESCG:
+		return dot(color, vec3(0.2722287168, 0.6740817658, 0.0536895174));
+	case COLOR_SPACE_XYZ:
+		return color.y;
+	}
+	return luminance(color);
+}
+
+vec3 srgb_oetf(vec3 linear) {
+	vec3 x = clamp(linear, 0.0, 1.0);
+	return mix(12.92 * x, 1.055 * pow(x, vec3(1.0 / 2.4)) - 0.055, greaterThan(x, vec3(0.0031308)));
+}
+
+// SMPTE ST 2084 inverse EOTF, luminance relative to 10000 nits
+vec3 pq_oetf(vec3 luminance) {
+	const float m1 = 0.1593017578125;
+	const float m2 = 78.84375;
+	const float c1 = 0.8359375;
+	const float c2 = 18.8515625;
+	const float c3 = 18.6875;
+
+	vec3 y = pow(clamp(luminance, 0.0, 1.0), vec3(m1));
+	return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// Display referred linear Rec. 709 with 1.0 at paper white, to the swapchain encoding
+vec3 encode_output(vec3 color, uint encoding, float paper_white_nits) {
+	switch (encoding) {
+	case OUTPUT_SRGB:
+		return srgb_oetf(color);
+	case OUTPUT_SCRGB:
+		// 1.0 is 80 nits in scRGB
+		return color * (paper_white_nits / 80.0);
+	case OUTPUT_HDR10:
+		return pq_oetf(REC709_TO_REC2020 * max(color, vec3(0.0)) * (paper_white_nits / 10000.0));
+	}
+	return color;
+}
@@ -0,0 +1,89 @@
+// Definitions shared by all ray tracing stages
+
+#define PI 3.14159265358979
+
+// Instance masks, one solid and one medium boundary bit per time slice (scene/gpu.rs)
+#define MASK_SOLID 0x55
+#define MASK_MEDIUM_BOUNDARY 0xaa
+#define MASK_ALL 0xff
+#define TIME_SLICES 4
+
+#define HIT_KIND_VOLUME_ENTER 0
+#define HIT_KIND_VOLUME_EXIT 1
+
+#define NO_HIT -1.0
+#define NO_ID 0xffffffffu // AOV instance, material and primitive where nothing was hit
+#define RAY_EPSILON 0.0001
+#define T_FAR 10000.0
+#define ADAPTIVE_TILE_SIZE 16 // pixels per side of the tiles adaptive sampling decides on
+
+struct HitPayload {
+	vec3 position;
+	float t; // NO_HIT when the ray escaped
+	vec3 normal; // geometric normal, flipped to face the incoming ray
+	uint instance;
+	uint primitive;
+	uint front_face;
+	vec3 object_position; // hit in object space, for motion vectors
+};
+
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski, Olano)
+uint pcg(inout uint state) {
+	state = state * 747796405u + 2891336453u;
+	uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+	return (word >> 22u) ^ word;
+}
+
+uint rng_seed(uvec2 pixel, uint frame) {
+	uint state = pixel.x * 1973u + pixel.y * 9277u + frame * 26699u;
+	pcg(state);
+	return state;
+}
+
+float rand(inout uint state) {
+	return float(pcg(state)) * (1.0 / 4294967296.0);
+}
+
+vec2 rand2(inout uint state) {
+	return vec2(rand(state), rand(state));
+}
+
+// Orthonormal basis with n as the z axis (Duff et al. 2017)
+mat3 basis(vec3 n) {
+	float s = n.z >= 0.0 ? 1.0 : -1.0;
+	float a = -1.0 / (s + n.z);
+	float b = n.x * n.y * a;
+	vec3 t = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+	vec3 bt = vec3(b, s + n.y * n.y * a, -n.y);
+	return mat3(t, bt, n);
+}
+
+vec3 sample_cosine_hemisphere(vec2 u) {
+	float r = sqrt(u.x);
+	float phi = 2.0 * PI * u.y;
+	return vec3(r * cos(phi), r * sin(phi), sqrt(max(0.0, 1.0 - u.x)));
+}
+
+// Mask bits of the time slice used for a ray at time in [0, 1], moving instances are
+// interpolated between slices by picking the neighbours with probability of their weight
+uint time_slice_mask(float time, float u) {
+	float position = clamp(time, 0.0, 1.0) * float(TIME_SLICES - 1);
+	uint slice = uint(position);
+	if (u < fract(position)) {
+		slice++;
+	}
+	return 0x3u << (2u * slice);
+}
+
+float max3(vec3 v) {
+	return max(v.x, max(v.y, v.z));
+}
+
+float average(vec3 v) {
+	return (v.x + v.y + v.z) / 3.0;
+}
+
+// Relative luminance of linear Rec. 709
+float luminance(vec3 rgb) {
+	return dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+}
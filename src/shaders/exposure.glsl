@@ -0,0 +1,48 @@
+#version 460
+
+// Average luminance from the histogram of histogram.glsl, adapted over time so the
+// exposure follows the scene smoothly. Clears the histogram for the next frame.
+
+#define HISTOGRAM_BINS 256
+
+layout(local_size_x = HISTOGRAM_BINS) in;
+
+layout(binding = 0, set = 0, std430) buffer Histogram { uint histogram[HISTOGRAM_BINS]; };
+layout(binding = 1, set = 0, std430) buffer Exposure { float average_luminance; };
+
+layout(push_constant) uniform PushConstants {
+	float min_log_luminance;
+	float log_range;
+	float pixel_count;
+	float adaptation; // fraction of the way to the new average, 1 jumps immediately
+} pc;
+
+shared float weighted_bins[HISTOGRAM_BINS];
+
+void main() {
+	uint bin = gl_LocalInvocationIndex;
+	uint count = histogram[bin];
+	weighted_bins[bin] = float(count) * float(bin);
+	histogram[bin] = 0;
+	barrier();
+
+	for (uint stride = HISTOGRAM_BINS / 2; stride > 0; stride >>= 1) {
+		if (bin < stride) {
+			weighted_bins[bin] += weighted_bins[bin + stride];
+		}
+		barrier();
+	}
+
+	// Thread 0 holds the count of black pixels in bin 0
+	if (bin == 0) {
+		float lit_pixels = pc.pixel_count - float(count);
+		if (lit_pixels < 1.0) {
+			return;
+		}
+
+		float average_bin = weighted_bins[0] / lit_pixels;
+		float t = (average_bin - 0.5) / float(HISTOGRAM_BINS - 2);
+		float target = exp2(pc.min_log_luminance + t * pc.log_range);
+		average_luminance = average_luminance > 0.0 ? mix(average_luminance, target, pc.adaptation) : target;
+	}
+}
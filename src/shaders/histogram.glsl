@@ -0,0 +1,46 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "spectrum.glsl"
+#include "color.glsl"
+
+// Log luminance histogram of the accumulated radiance for auto exposure, see exposure.glsl
+
+#define HISTOGRAM_BINS 256
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0, set = 0, rgba32f) uniform readonly image2D accumulation_image;
+layout(binding = 1, set = 0, std430) buffer Histogram { uint histogram[HISTOGRAM_BINS]; };
+
+layout(push_constant) uniform PushConstants {
+	float min_log_luminance;
+	float inverse_log_range;
+	uint input_space; // COLOR_SPACE_ of the accumulation
+} pc;
+
+shared uint local_histogram[HISTOGRAM_BINS];
+
+// Bin 0 collects black pixels, which are left out of the average
+uint luminance_bin(float y) {
+	if (y < 1e-5) {
+		return 0;
+	}
+	float t = clamp((log2(y) - pc.min_log_luminance) * pc.inverse_log_range, 0.0, 1.0);
+	return uint(t * float(HISTOGRAM_BINS - 2)) + 1;
+}
+
+void main() {
+	local_histogram[gl_LocalInvocationIndex] = 0;
+	barrier();
+
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	if (all(lessThan(pixel, imageSize(accumulation_image)))) {
+		vec3 color = imageLoad(accumulation_image, pixel).rgb;
+		atomicAdd(local_histogram[luminance_bin(luminance_in(color, pc.input_space))], 1);
+	}
+	barrier();
+
+	atomicAdd(histogram[gl_LocalInvocationIndex], local_histogram[gl_LocalInvocationIndex]);
+}
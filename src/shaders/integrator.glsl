@@ -0,0 +1,394 @@
+// Path tracing integrator shared by all backends, see renderer/backend.rs. The including
+// shader declares the acceleration structure or BVH bindings and implements intersect_closest
+// and occluded on top of them, then calls render_pixel once per pixel.
+
+#include "medium.glsl"
+#include "spectrum.glsl"
+#include "color.glsl"
+#include "bsdf.glsl"
+
+// Chosen at pipeline creation, see Integrator in renderer/mod.rs
+layout(constant_id = 0) const bool SPECTRAL = false;
+// First hit auxiliary buffers, bindings 10 to 14 are placeholders when disabled
+layout(constant_id = 1) const bool AOVS = false;
+// Primaries of the RGB accumulation, scene colours are given in Rec. 709
+layout(constant_id = 2) const uint WORKING_SPACE = COLOR_SPACE_REC709;
+
+layout(binding = 2, set = 0) uniform CameraProperties
+{
+	mat4 view_inverse;
+	mat4 proj_inverse;
+	vec4 lens; // aperture radius, focus distance, bokeh blades, blade rotation
+	mat4 previous_view_inverse;
+	vec4 shutter; // open and close time within the frame interval
+	mat4 view_proj;
+	mat4 previous_view_proj;
+} cam;
+layout(binding = 3, set = 0, rgba32f) uniform image2D accumulation_image;
+
+// Averaged over all samples like the radiance, IDs are taken from the first sample
+layout(binding = 10, set = 0, rgba16f) uniform image2D aov_albedo;
+layout(binding = 11, set = 0, rgba16f) uniform image2D aov_normal;
+layout(binding = 12, set = 0, r32f) uniform image2D aov_depth;
+layout(binding = 13, set = 0, rgba32ui) uniform uimage2D aov_id; // instance, material, primitive
+layout(binding = 14, set = 0, rgba16f) uniform image2D aov_motion; // pixels to the previous frame
+
+// Sample count, mean luminance and sum of squared luminance deviations of every pixel
+layout(binding = 15, set = 0, rgba32f) uniform image2D sample_stats;
+// Nonzero for tiles that still need samples, written by adaptive.glsl
+layout(binding = 16, set = 0, r32ui) uniform readonly uimage2D tile_mask;
+
+layout(push_constant) uniform PushConstants {
+	uint max_bounces;
+	float time;
+	uint frame_index;
+	uint light_count;
+	int atmosphere;
+	uint seed; // counts every traced frame, unlike frame_index which restarts with accumulation
+	vec2 jitter; // sub-pixel position of the camera rays, from a Halton sequence
+	vec3 environment; // radiance of escaped rays, Rec. 709
+	uint adaptive; // skip pixels of converged tiles
+} pc;
+
+// Closest hit within (RAY_EPSILON, t_max) of the instances in mask, written to hit_value
+void intersect_closest(vec3 origin, vec3 direction, float t_max, uint mask);
+// Whether any instance in mask is hit within (RAY_EPSILON, t_max)
+bool occluded(vec3 origin, vec3 direction, float t_max, uint mask);
+
+HitPayload hit_value;
+
+// Size of the traced image, for projecting points to pixels
+uvec2 image_size;
+
+// Time slice of the current path, restricts every ray to instances placed at that time
+uint ray_time_mask = MASK_ALL;
+
+// Wavelengths carried by the current path in spectral mode
+vec3 path_wavelengths = RGB_WAVELENGTHS;
+
+vec3 to_working_space(vec3 rgb) {
+	return WORKING_SPACE == COLOR_SPACE_ACESCG ? REC709_TO_ACESCG * rgb : rgb;
+}
+
+// Colours are uplifted to the path wavelengths in spectral mode and converted to the working space otherwise
+vec3 color(vec3 rgb) {
+	return SPECTRAL ? rgb_to_spectrum(max(rgb, vec3(0.0)), path_wavelengths) : to_working_space(rgb);
+}
+
+Medium load_medium(int index) {
+	Medium medium = media[index];
+	medium.sigma_a = color(medium.sigma_a);
+	medium.sigma_s = color(medium.sigma_s);
+	return medium;
+}
+
+// Medium boundaries crossed along one path or shadow ray before giving up
+#define MAX_SEGMENTS 32
+
+// Auxiliary values of the first shaded event along the camera path
+struct FirstHit {
+	vec3 albedo;
+	vec3 normal;
+	float depth; // along the view axis, 0 when nothing was hit
+	uvec3 id;
+	vec2 motion;
+};
+
+void trace_closest(vec3 origin, vec3 direction, float t_max, uint mask) {
+	intersect_closest(origin, direction, t_max, mask & ray_time_mask);
+}
+
+int medium_after_crossing(HitPayload hit, int interior) {
+	return hit.front_face != 0 ? interior : pc.atmosphere;
+}
+
+// Visibility of a light through solid geometry and every medium along the way
+vec3 transmittance(vec3 origin, vec3 direction, float distance, int medium, inout uint rng) {
+	if (occluded(origin, direction, distance, MASK_SOLID & ray_time_mask)) {
+		return vec3(0.0);
+	}
+
+	vec3 result = vec3(1.0);
+	float t = 0.0;
+	for (int i = 0; i < MAX_SEGMENTS; i++) {
+		vec3 segment_origin = origin + direction * t;
+		trace_closest(segment_origin, direction, distance - t, MASK_MEDIUM_BOUNDARY);
+		float segment = hit_value.t == NO_HIT ? distance - t : hit_value.t;
+
+		if (medium >= 0) {
+			result *= medium_transmittance(load_medium(medium), segment_origin, direction, segment, rng);
+		}
+		if (hit_value.t == NO_HIT || max3(result) <= 0.0) {
+			break;
+		}
+
+		t += segment;
+		medium = medium_after_crossing(hit_value, instances[hit_value.instance].interior_medium);
+	}
+	return result;
+}
+
+// Unshadowed point light contribution, weighted by the cosine or phase function by the caller
+vec3 light_radiance(PointLight light, vec3 position, out vec3 to_light, out float distance) {
+	vec3 offset = light.position - position;
+	distance = length(offset);
+	to_light = offset / distance;
+	return color(light.color) * light.intensity / (distance * distance);
+}
+
+// Lights reflected towards wo, given in the shading frame like the BSDF works in
+vec3 direct_light_surface(vec3 position, mat3 frame, Bsdf bsdf, vec3 wo, int medium, inout uint rng) {
+	vec3 result = vec3(0.0);
+	for (uint i = 0; i < pc.light_count; i++) {
+		vec3 to_light;
+		float distance;
+		vec3 radiance = light_radiance(lights[i], position, to_light, distance);
+
+		vec3 f = bsdf_eval(bsdf, wo, to_light * frame);
+		if (max3(f) > 0.0) {
+			result += radiance * f * transmittance(position, to_light, distance, medium, rng);
+		}
+	}
+	return result;
+}
+
+vec3 direct_light_medium(vec3 position, vec3 direction, int medium, inout uint rng) {
+	float g = media[medium].g;
+	vec3 result = vec3(0.0);
+	for (uint i = 0; i < pc.light_count; i++) {
+		vec3 to_light;
+		float distance;
+		vec3 radiance = light_radiance(lights[i], position, to_light, distance);
+
+		float phase = henyey_greenstein(dot(direction, to_light), g);
+		result += radiance * phase * transmittance(position, to_light, distance, medium, rng);
+	}
+	return result;
+}
+
+// Pixel position of a world space point seen through a view projection matrix
+vec2 project_to_pixel(mat4 view_proj, vec3 position) {
+	vec4 clip = view_proj * vec4(position, 1.0);
+	vec2 ndc = clip.xy / clip.w;
+	return (vec2(ndc.x, -ndc.y) * 0.5 + 0.5) * vec2(image_size);
+}
+
+// Screen space offset from where a point is now to where it was in the previous frame
+vec2 motion_vector(vec3 position, vec3 previous_position) {
+	return project_to_pixel(cam.previous_view_proj, previous_position) - project_to_pixel(cam.view_proj, position);
+}
+
+float view_depth(vec3 position) {
+	return dot(position - cam.view_inverse[3].xyz, -normalize(cam.view_inverse[2].xyz));
+}
+
+// Point on the unit aperture, a disk or a regular polygon with the given number of blades
+vec2 sample_aperture(vec2 u, float blades, float rotation) {
+	if (blades < 3.0) {
+		float r = sqrt(u.x);
+		float phi = 2.0 * PI * u.y;
+		return r * vec2(cos(phi), sin(phi));
+	}
+
+	// Pick one of the triangles fanning out from the center, then a point inside it
+	float sector = min(floor(u.x * blades), blades - 1.0);
+	float v = u.x * blades - sector;
+	float a0 = rotation + 2.0 * PI * sector / blades;
+	float a1 = a0 + 2.0 * PI / blades;
+	return sqrt(v) * mix(vec2(cos(a0), sin(a0)), vec2(cos(a1), sin(a1)), u.y);
+}
+
+// One sample of the pixel, accumulated into the images of descriptor set 0
+void render_pixel(uvec2 pixel_id, uvec2 size)
+{
+	image_size = size;
+	ivec2 pixel = ivec2(pixel_id);
+	if (pc.adaptive != 0 && imageLoad(tile_mask, pixel / ADAPTIVE_TILE_SIZE).r == 0) {
+		return;
+	}
+
+	uint rng = rng_seed(pixel_id, pc.seed);
+	if (SPECTRAL) {
+		path_wavelengths = sample_wavelengths(rand(rng));
+	}
+
+	// Jittered within the pixel, accumulation and TAA turn it into anti-aliasing
+	const vec2 pixelCenter = vec2(pixel_id) + pc.jitter;
+	const vec2 inUV = pixelCenter/vec2(size);
+
+	vec2 d = inUV * 2.0 - 1.0;
+
+	vec4 target = cam.proj_inverse * vec4(d.x, -d.y, 1, 1) ;
+	vec3 lens_position = vec3(0.0);
+	vec3 view_direction = normalize(target.xyz);
+
+	// Thin lens, every ray through the pixel converges on the focus plane
+	float aperture_radius = cam.lens.x;
+	if (aperture_radius > 0.0) {
+		float focus_distance = cam.lens.y;
+		vec3 focus_point = view_direction * (focus_distance / -view_direction.z);
+		lens_position = vec3(sample_aperture(rand2(rng), cam.lens.z, cam.lens.w) * aperture_radius, 0.0);
+		view_direction = normalize(focus_point - lens_position);
+	}
+
+	// Every path sees the camera and all instances at one time within the shutter interval
+	float time = mix(cam.shutter.x, cam.shutter.y, rand(rng));
+	ray_time_mask = time_slice_mask(time, rand(rng));
+
+	vec4 origin = mix(cam.previous_view_inverse * vec4(lens_position, 1), cam.view_inverse * vec4(lens_position, 1), time);
+	vec4 direction = mix(cam.previous_view_inverse * vec4(view_direction, 0), cam.view_inverse * vec4(view_direction, 0), time);
+
+	vec3 ray_origin = origin.xyz;
+	vec3 ray_direction = normalize(direction.xyz);
+	vec3 radiance = vec3(0.0);
+	vec3 throughput = vec3(1.0);
+	float alpha = 0.0;
+	int medium = pc.atmosphere;
+	uint bounce = 0;
+	FirstHit first_hit = FirstHit(vec3(0.0), vec3(0.0), 0.0, uvec3(NO_ID), vec2(0.0));
+	bool first_event = true;
+
+	for (int segment = 0; segment < MAX_SEGMENTS; segment++) {
+		trace_closest(ray_origin, ray_direction, T_FAR, MASK_ALL);
+		HitPayload hit = hit_value;
+		float t_surface = hit.t == NO_HIT ? T_FAR : hit.t;
+
+		if (medium >= 0) {
+			Medium current = load_medium(medium);
+			float t_collision;
+			int event = sample_medium(current, ray_origin, ray_direction, t_surface, rng, throughput, t_collision);
+
+			if (first_event && event != MEDIUM_PASS) {
+				vec3 position = ray_origin + ray_direction * t_collision;
+				first_hit.albedo = media[medium].sigma_s / max(media[medium].sigma_a + media[medium].sigma_s, vec3(1e-6));
+				first_hit.depth = view_depth(position);
+				first_hit.motion = motion_vector(position, position);
+				first_event = false;
+			}
+			if (event == MEDIUM_ABSORB) {
+				alpha = 1.0;
+				vec3 emission = medium_emission(current, ray_origin + ray_direction * t_collision, path_wavelengths);
+				radiance += throughput * (SPECTRAL ? emission : to_working_space(emission));
+				break;
+			}
+			if (event == MEDIUM_SCATTER) {
+				alpha = 1.0;
+				vec3 position = ray_origin + ray_direction * t_collision;
+				radiance += throughput * direct_light_medium(position, ray_direction, medium, rng);
+
+				if (bounce >= pc.max_bounces) {
+					break;
+				}
+				ray_direction = sample_henyey_greenstein(ray_direction, current.g, rand2(rng));
+				ray_origin = position;
+				bounce++;
+				continue;
+			}
+		}
+
+		if (hit.t == NO_HIT) {
+			radiance += throughput * color(pc.environment);
+			break;
+		}
+
+		InstanceData instance = instances[hit.instance];
+
+		// Index matched medium boundary, continue on the other side without shading
+		if (instance.interior_medium >= 0) {
+			medium = medium_after_crossing(hit, instance.interior_medium);
+			ray_origin = hit.position + ray_direction * RAY_EPSILON;
+			continue;
+		}
+
+		alpha = 1.0;
+
+		if (first_event) {
+			vec3 previous_position = (instance.previous_transform * vec4(hit.object_position, 1.0)).xyz;
+			vec3 current_position = (instance.transform * vec4(hit.object_position, 1.0)).xyz;
+			first_hit = FirstHit(
+				materials[instance.material].base_color,
+				hit.normal,
+				view_depth(hit.position),
+				uvec3(hit.instance, instance.material, hit.primitive),
+				motion_vector(current_position, previous_position)
+			);
+			first_event = false;
+		}
+
+		// Metallic-roughness surface with next event estimation towards every light
+		Material material = materials[instance.material];
+		Bsdf bsdf = make_bsdf(color(material.base_color), material.roughness, material.metallic);
+		mat3 frame = basis(hit.normal);
+		vec3 wo = -ray_direction * frame;
+		vec3 position = hit.position + hit.normal * RAY_EPSILON;
+		radiance += throughput * direct_light_surface(position, frame, bsdf, wo, medium, rng);
+
+		if (bounce >= pc.max_bounces) {
+			break;
+		}
+
+		vec3 wi;
+		vec3 weight;
+		if (!bsdf_sample(bsdf, wo, rand(rng), rand2(rng), wi, weight)) {
+			break;
+		}
+		ray_direction = frame * wi;
+		ray_origin = position;
+		throughput *= weight;
+		bounce++;
+
+		if (bounce > 2) {
+			float survival = min(max3(throughput), 0.95);
+			if (rand(rng) > survival) {
+				break;
+			}
+			throughput /= survival;
+		}
+	}
+
+	if (any(isnan(radiance)) || any(isinf(radiance))) {
+		radiance = vec3(0.0);
+	}
+
+	// Spectral samples are accumulated as CIE XYZ
+	if (SPECTRAL) {
+		radiance = spectrum_to_xyz(radiance, path_wavelengths);
+	}
+
+	// Running average over all samples of the pixel since the camera last moved, with
+	// Welford's online variance of the luminance for adaptive sampling
+	vec4 stats = pc.frame_index > 0 ? imageLoad(sample_stats, pixel) : vec4(0.0);
+	float count = stats.x + 1.0;
+	float weight = 1.0 / count;
+
+	vec4 accumulated = vec4(radiance, alpha);
+	if (count > 1.0) {
+		vec4 previous = imageLoad(accumulation_image, pixel);
+		accumulated = mix(previous, accumulated, weight);
+	}
+	imageStore(accumulation_image, pixel, accumulated);
+
+	float y = luminance_in(radiance, SPECTRAL ? COLOR_SPACE_XYZ : WORKING_SPACE);
+	float delta = y - stats.y;
+	float mean = stats.y + delta * weight;
+	imageStore(sample_stats, pixel, vec4(count, mean, stats.z + delta * (y - mean), 0.0));
+
+	if (AOVS) {
+		vec4 albedo = vec4(first_hit.albedo, alpha);
+		vec4 normal = vec4(first_hit.normal, 0.0);
+		float depth = first_hit.depth;
+		vec4 motion = vec4(first_hit.motion, 0.0, 0.0);
+		if (count > 1.0) {
+			albedo = mix(imageLoad(aov_albedo, pixel), albedo, weight);
+			normal = mix(imageLoad(aov_normal, pixel), normal, weight);
+			depth = mix(imageLoad(aov_depth, pixel).r, depth, weight);
+			motion = mix(imageLoad(aov_motion, pixel), motion, weight);
+			first_hit.id = imageLoad(aov_id, pixel).xyz;
+		}
+		imageStore(aov_albedo, pixel, albedo);
+		imageStore(aov_normal, pixel, normal);
+		imageStore(aov_depth, pixel, vec4(depth));
+		imageStore(aov_id, pixel, uvec4(first_hit.id, 0));
+		imageStore(aov_motion, pixel, motion);
+	}
+}
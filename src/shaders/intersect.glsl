@@ -0,0 +1,59 @@
+// Hits found outside of the ray tracing pipeline, filled in like the closest hit shaders do
+
+// Where a ray enters the box of a volume's density grid at or after t_min, or where it leaves
+// the box when it starts inside
+bool intersect_volume_box(uint instance, vec3 origin, vec3 direction, float t_min, out float t, out bool enter) {
+	DensityGrid grid = grids[media[instances[instance].interior_medium].grid];
+
+	vec3 inv_dir = 1.0 / direction;
+	vec3 t0 = (grid.bounds_min - origin) * inv_dir;
+	vec3 t1 = (grid.bounds_max - origin) * inv_dir;
+	float t_near = max3(min(t0, t1));
+	vec3 far = max(t0, t1);
+	float t_far = min(far.x, min(far.y, far.z));
+
+	enter = t_near >= t_min;
+	t = enter ? t_near : t_far;
+	return t_near <= t_far && t >= t_min;
+}
+
+// Triangle vertices come from the vertex buffer since there is no position fetch
+HitPayload triangle_hit(uint instance, uint primitive, vec2 barycentrics, mat4x3 world_to_object, vec3 origin, vec3 direction, float t) {
+	uint first = instances[instance].first_vertex + 3 * primitive;
+	vec3 pos0 = vertices[first].xyz;
+	vec3 pos1 = vertices[first + 1].xyz;
+	vec3 pos2 = vertices[first + 2].xyz;
+
+	vec3 normal = normalize(transpose(mat3(world_to_object)) * cross(pos1 - pos0, pos2 - pos0));
+
+	// Counter-clockwise triangles face outwards
+	bool front_face = dot(normal, direction) < 0.0;
+	if (!front_face) {
+		normal = -normal;
+	}
+
+	vec3 weights = vec3(1.0 - barycentrics.x - barycentrics.y, barycentrics.x, barycentrics.y);
+
+	HitPayload hit;
+	hit.position = origin + direction * t;
+	hit.t = t;
+	hit.normal = normal;
+	hit.instance = instance;
+	hit.primitive = primitive;
+	hit.front_face = front_face ? 1 : 0;
+	hit.object_position = pos0 * weights.x + pos1 * weights.y + pos2 * weights.z;
+	return hit;
+}
+
+// Volume boxes are placed in world space, so object and world positions match
+HitPayload volume_hit(uint instance, vec3 origin, vec3 direction, float t, bool enter) {
+	HitPayload hit;
+	hit.position = origin + direction * t;
+	hit.t = t;
+	hit.normal = -direction;
+	hit.instance = instance;
+	hit.primitive = 0;
+	hit.front_face = enter ? 1 : 0;
+	hit.object_position = hit.position;
+	return hit;
+}
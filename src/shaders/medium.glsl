@@ -0,0 +1,179 @@
+// Participating media: density lookup, free flight sampling and transmittance
+// Requires common.glsl and scene.glsl
+
+#define MEDIUM_PASS 0
+#define MEDIUM_SCATTER 1
+#define MEDIUM_ABSORB 2
+
+#define MAX_TRACKING_STEPS 256
+
+// Representative wavelengths of the RGB primaries in nm
+#define RGB_WAVELENGTHS vec3(610.0, 550.0, 465.0)
+
+float grid_density(DensityGrid grid, vec3 p) {
+	vec3 uvw = (p - grid.bounds_min) / (grid.bounds_max - grid.bounds_min);
+	if (any(lessThan(uvw, vec3(0.0))) || any(greaterThan(uvw, vec3(1.0)))) {
+		return 0.0;
+	}
+
+	ivec3 res = ivec3(grid.resolution);
+	vec3 coord = uvw * vec3(res) - 0.5;
+	ivec3 c0 = ivec3(floor(coord));
+	vec3 f = coord - vec3(c0);
+
+	// Trilinear interpolation, never exceeds the grid maximum
+	float density = 0.0;
+	for (int i = 0; i < 8; i++) {
+		ivec3 o = ivec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+		ivec3 c = clamp(c0 + o, ivec3(0), res - 1);
+		vec3 w = mix(1.0 - f, f, vec3(o));
+		density += w.x * w.y * w.z * voxels[grid.offset + uint(c.x + res.x * (c.y + res.y * c.z))];
+	}
+	return density;
+}
+
+float medium_density(Medium medium, vec3 p) {
+	return medium.grid < 0 ? 1.0 : grid_density(grids[medium.grid], p);
+}
+
+float medium_majorant(Medium medium) {
+	float max_density = medium.grid < 0 ? 1.0 : grids[medium.grid].max_density;
+	return max3(medium.sigma_a + medium.sigma_s) * max_density;
+}
+
+// Restricts [t_min, t_max] to the part of the ray where the medium can have non-zero density
+bool medium_clip(Medium medium, vec3 origin, vec3 dir, inout float t_min, inout float t_max) {
+	if (medium.grid < 0) {
+		return t_max > t_min;
+	}
+
+	DensityGrid grid = grids[medium.grid];
+	vec3 inv_dir = 1.0 / dir;
+	vec3 t0 = (grid.bounds_min - origin) * inv_dir;
+	vec3 t1 = (grid.bounds_max - origin) * inv_dir;
+	vec3 near = min(t0, t1);
+	vec3 far = max(t0, t1);
+	t_min = max(t_min, max3(near));
+	t_max = min(t_max, min(far.x, min(far.y, far.z)));
+	return t_max > t_min;
+}
+
+// Spectral delta tracking (Kutz et al. 2017) with history aware collision probabilities.
+// Returns MEDIUM_PASS when no real collision happened before t_max, otherwise the event at t.
+// The throughput is weighted for both real events, absorption collects medium_emission.
+// Paths running out of tracking steps are absorbed with zero throughput.
+int sample_medium(Medium medium, vec3 origin, vec3 dir, float t_max, inout uint rng, inout vec3 throughput, out float t) {
+	t = 0.0;
+	float majorant = medium_majorant(medium);
+	if (majorant <= 0.0 || !medium_clip(medium, origin, dir, t, t_max)) {
+		return MEDIUM_PASS;
+	}
+
+	for (int i = 0; i < MAX_TRACKING_STEPS; i++) {
+		t -= log(1.0 - rand(rng)) / majorant;
+		if (t >= t_max) {
+			return MEDIUM_PASS;
+		}
+
+		float density = medium_density(medium, origin + dir * t);
+		vec3 sigma_a = medium.sigma_a * density;
+		vec3 sigma_s = medium.sigma_s * density;
+		vec3 sigma_n = vec3(majorant) - sigma_a - sigma_s;
+
+		float p_a = average(sigma_a * throughput);
+		float p_s = average(sigma_s * throughput);
+		float p_n = average(abs(sigma_n * throughput));
+		float c = p_a + p_s + p_n;
+		if (c <= 0.0) {
+			return MEDIUM_ABSORB;
+		}
+
+		float u = rand(rng) * c;
+		if (u < p_a) {
+			throughput *= sigma_a * (c / (majorant * p_a));
+			return MEDIUM_ABSORB;
+		}
+		if (u < p_a + p_s) {
+			throughput *= sigma_s * (c / (majorant * p_s));
+			return MEDIUM_SCATTER;
+		}
+		throughput *= sigma_n * (c / (majorant * p_n));
+	}
+
+	// Out of steps, end the path without contribution rather than absorbing at an arbitrary point
+	throughput = vec3(0.0);
+	return MEDIUM_ABSORB;
+}
+
+// Planck's law at three wavelengths given in nm, in W sr^-1 m^-2 nm^-1
+vec3 blackbody(float temperature, vec3 lambda_nm) {
+	if (temperature <= 0.0) {
+		return vec3(0.0);
+	}
+	vec3 lambda = lambda_nm * 1e-9;
+	vec3 lambda5 = lambda * lambda * lambda * lambda * lambda;
+	return 1.191042e-16 / (lambda5 * (exp(1.4387769e-2 / (lambda * temperature)) - 1.0)) * 1e-9;
+}
+
+// Emitted radiance per unit absorption at p, evaluated at the given wavelengths
+vec3 medium_emission(Medium medium, vec3 p, vec3 lambda) {
+	if (medium.temperature_grid < 0) {
+		return vec3(0.0);
+	}
+	float temperature = grid_density(grids[medium.temperature_grid], p) * medium.temperature_scale;
+	return blackbody(temperature, lambda) * medium.emission_scale;
+}
+
+// Ratio tracking for heterogeneous media, closed form otherwise
+vec3 medium_transmittance(Medium medium, vec3 origin, vec3 dir, float t_max, inout uint rng) {
+	vec3 sigma_t = medium.sigma_a + medium.sigma_s;
+	if (medium.grid < 0) {
+		return exp(-sigma_t * t_max);
+	}
+
+	float t = 0.0;
+	float majorant = medium_majorant(medium);
+	if (majorant <= 0.0 || !medium_clip(medium, origin, dir, t, t_max)) {
+		return vec3(1.0);
+	}
+
+	vec3 transmittance = vec3(1.0);
+	for (int i = 0; i < MAX_TRACKING_STEPS; i++) {
+		t -= log(1.0 - rand(rng)) / majorant;
+		if (t >= t_max) {
+			break;
+		}
+
+		transmittance *= 1.0 - sigma_t * (medium_density(medium, origin + dir * t) / majorant);
+
+		// Russian roulette once the estimate becomes small
+		float max_transmittance = max3(transmittance);
+		if (max_transmittance < 0.1) {
+			if (rand(rng) > max_transmittance) {
+				return vec3(0.0);
+			}
+			transmittance /= max_transmittance;
+		}
+	}
+	return transmittance;
+}
+
+// Henyey-Greenstein, cos_theta is measured between the propagation directions
+float henyey_greenstein(float cos_theta, float g) {
+	float denom = 1.0 + g * g - 2.0 * g * cos_theta;
+	return (1.0 - g * g) / (4.0 * PI * denom * sqrt(denom));
+}
+
+vec3 sample_henyey_greenstein(vec3 dir, float g, vec2 u) {
+	float cos_theta;
+	if (abs(g) < 0.001) {
+		cos_theta = 1.0 - 2.0 * u.x;
+	} else {
+		float sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+		cos_theta = (1.0 + g * g - sq * sq) / (2.0 * g);
+	}
+
+	float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+	float phi = 2.0 * PI * u.y;
+	return basis(dir) * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
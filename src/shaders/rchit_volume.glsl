@@ -0,0 +1,18 @@
+#version 460
+#extension GL_EXT_ray_tracing : require
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+
+layout(location = 0) rayPayloadInEXT HitPayload hit_value;
+
+// Volume boxes are reported like mesh boundaries of a medium
+void main() {
+    hit_value.position = gl_WorldRayOriginEXT + gl_WorldRayDirectionEXT * gl_HitTEXT;
+    hit_value.t = gl_HitTEXT;
+    hit_value.normal = -gl_WorldRayDirectionEXT;
+    hit_value.instance = gl_InstanceCustomIndexEXT;
+    hit_value.primitive = gl_PrimitiveID;
+    hit_value.front_face = gl_HitKindEXT == HIT_KIND_VOLUME_ENTER ? 1 : 0;
+    hit_value.object_position = gl_WorldToObjectEXT * vec4(hit_value.position, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 460
+#extension GL_EXT_ray_tracing : require
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "scene.glsl"
+#include "intersect.glsl"
+
+// Reports where the ray enters the box of a volume's density grid, or where it leaves
+// the box when it starts inside
+void main() {
+    float t;
+    bool enter;
+    if (intersect_volume_box(gl_InstanceCustomIndexEXT, gl_ObjectRayOriginEXT, gl_ObjectRayDirectionEXT, gl_RayTminEXT, t, enter)) {
+        reportIntersectionEXT(t, enter ? HIT_KIND_VOLUME_ENTER : HIT_KIND_VOLUME_EXIT);
+    }
+}
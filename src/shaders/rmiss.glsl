@@ -1,13 +1,11 @@
 #version 460
 #extension GL_EXT_ray_tracing : require
+#extension GL_GOOGLE_include_directive : require
 
-struct Payload {
-	vec3 color;
-	uint depth;
-};
+#include "common.glsl"
 
-layout(location = 0) rayPayloadInEXT Payload hit_value;
+layout(location = 0) rayPayloadInEXT HitPayload hit_value;
 
 void main() {
-    hit_value.color = vec3(0.0);
-}
\ No newline at end of file
+    hit_value.t = NO_HIT;
+}
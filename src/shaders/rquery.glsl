@@ -0,0 +1,81 @@
+#version 460
+#extension GL_EXT_ray_query : require
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "scene.glsl"
+#include "intersect.glsl"
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, set = 0) uniform accelerationStructureEXT tlas;
+
+#include "integrator.glsl"
+
+void intersect_closest(vec3 origin, vec3 direction, float t_max, uint mask) {
+	rayQueryEXT query;
+	rayQueryInitializeEXT(query, tlas, gl_RayFlagsOpaqueEXT, mask, origin, RAY_EPSILON, direction, t_max);
+
+	// Triangles are committed by the traversal, volume boxes are tested here like rint_volume.glsl does
+	bool volume_enter = false;
+	while (rayQueryProceedEXT(query)) {
+		if (rayQueryGetIntersectionTypeEXT(query, false) != gl_RayQueryCandidateIntersectionAABBEXT) {
+			continue;
+		}
+
+		float t;
+		bool enter;
+		bool hit = intersect_volume_box(
+			rayQueryGetIntersectionInstanceCustomIndexEXT(query, false),
+			rayQueryGetIntersectionObjectRayOriginEXT(query, false),
+			rayQueryGetIntersectionObjectRayDirectionEXT(query, false),
+			RAY_EPSILON,
+			t,
+			enter
+		);
+		bool committed = rayQueryGetIntersectionTypeEXT(query, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+		float t_closest = committed ? rayQueryGetIntersectionTEXT(query, true) : t_max;
+		if (hit && t <= t_closest) {
+			rayQueryGenerateIntersectionEXT(query, t);
+			volume_enter = enter;
+		}
+	}
+
+	uint type = rayQueryGetIntersectionTypeEXT(query, true);
+	if (type == gl_RayQueryCommittedIntersectionNoneEXT) {
+		hit_value.t = NO_HIT;
+		return;
+	}
+
+	uint instance = rayQueryGetIntersectionInstanceCustomIndexEXT(query, true);
+	float t = rayQueryGetIntersectionTEXT(query, true);
+	if (type == gl_RayQueryCommittedIntersectionGeneratedEXT) {
+		hit_value = volume_hit(instance, origin, direction, t, volume_enter);
+	} else {
+		hit_value = triangle_hit(
+			instance,
+			rayQueryGetIntersectionPrimitiveIndexEXT(query, true),
+			rayQueryGetIntersectionBarycentricsEXT(query, true),
+			rayQueryGetIntersectionWorldToObjectEXT(query, true),
+			origin,
+			direction,
+			t
+		);
+	}
+}
+
+bool occluded(vec3 origin, vec3 direction, float t_max, uint mask) {
+	rayQueryEXT query;
+	rayQueryInitializeEXT(query, tlas, gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT, mask, origin, RAY_EPSILON, direction, t_max);
+	while (rayQueryProceedEXT(query)) {
+	}
+	return rayQueryGetIntersectionTypeEXT(query, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+void main() {
+	uvec2 size = uvec2(imageSize(accumulation_image));
+	if (any(greaterThanEqual(gl_GlobalInvocationID.xy, size))) {
+		return;
+	}
+	render_pixel(gl_GlobalInvocationID.xy, size);
+}
@@ -0,0 +1,55 @@
+// Scene resources of descriptor set 0, uploaded by scene::SceneBuffers. The acceleration
+// structure or BVH is declared by the backend's shader.
+
+struct InstanceData {
+	uint material;
+	int interior_medium; // -1 for solid surfaces
+	uint first_vertex; // of the mesh in vertices, three per triangle
+	uint bvh_root; // node of the mesh in the software backend's BVH
+	mat4 previous_transform; // object to world at the start and end of the frame interval
+	mat4 transform;
+};
+
+struct Material {
+	vec3 base_color;
+	float roughness; // perceptual, squared for GGX
+	float metallic;
+	float pad0;
+	float pad1;
+	float pad2;
+};
+
+struct Medium {
+	vec3 sigma_a;
+	float g;
+	vec3 sigma_s;
+	int grid; // -1 for homogeneous media
+	int temperature_grid; // -1 when not emissive
+	float temperature_scale;
+	float emission_scale;
+	float pad;
+};
+
+struct DensityGrid {
+	vec3 bounds_min;
+	uint offset;
+	vec3 bounds_max;
+	float max_density;
+	uvec3 resolution;
+	uint pad;
+};
+
+struct PointLight {
+	vec3 position;
+	float intensity;
+	vec3 color;
+	float pad;
+};
+
+layout(binding = 4, set = 0, std430) readonly buffer Instances { InstanceData instances[]; };
+layout(binding = 5, set = 0, std430) readonly buffer Materials { Material materials[]; };
+layout(binding = 6, set = 0, std430) readonly buffer Media { Medium media[]; };
+layout(binding = 7, set = 0, std430) readonly buffer Grids { DensityGrid grids[]; };
+layout(binding = 8, set = 0, std430) readonly buffer Voxels { float voxels[]; };
+layout(binding = 9, set = 0, std430) readonly buffer Lights { PointLight lights[]; };
+layout(binding = 17, set = 0, std430) readonly buffer Vertices { vec4 vertices[]; };
@@ -0,0 +1,210 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "scene.glsl"
+#include "intersect.glsl"
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+// Set in the count of leaves, inner nodes have a count of 0 and keep their second child at
+// offset, the first one follows directly. Leaves hold count primitives from offset on.
+#define BVH_LEAF 0x80000000u
+// Nodes pending during traversal, one more than the MAX_DEPTH of bvh.rs so no subtree is skipped
+#define BVH_STACK_SIZE 32
+
+struct BvhNode {
+	vec3 bounds_min;
+	uint offset;
+	vec3 bounds_max;
+	uint count;
+};
+
+// Instance in the top level, see placed_instances in scene/gpu.rs
+struct Placement {
+	mat4 world_to_object;
+	uint instance;
+	uint mask;
+	uint volume; // nonzero for the boxes of density grids
+	uint pad;
+};
+
+// The top level over the placements starts at node 0, followed by one BVH per mesh
+layout(binding = 18, set = 0, std430) readonly buffer BvhNodes { BvhNode nodes[]; };
+layout(binding = 19, set = 0, std430) readonly buffer BvhIndices { uint bvh_indices[]; };
+layout(binding = 20, set = 0, std430) readonly buffer Placements { Placement placements[]; };
+
+#include "integrator.glsl"
+
+// Closest hit found so far while walking the BVH
+struct Candidate {
+	float t;
+	uint placement;
+	uint primitive;
+	vec2 barycentrics;
+	bool enter;
+};
+
+// Slab test against the part of the ray within (RAY_EPSILON, t_max)
+bool intersect_node(BvhNode node, vec3 origin, vec3 inv_dir, float t_max) {
+	vec3 t0 = (node.bounds_min - origin) * inv_dir;
+	vec3 t1 = (node.bounds_max - origin) * inv_dir;
+	float t_near = max(max3(min(t0, t1)), RAY_EPSILON);
+	vec3 far = max(t0, t1);
+	float t_far = min(min(far.x, min(far.y, far.z)), t_max);
+	return t_near <= t_far;
+}
+
+// Möller-Trumbore, like intersect_triangle in cpu/mod.rs
+bool intersect_triangle(vec3 origin, vec3 direction, uint first, float t_max, out float t, out vec2 barycentrics) {
+	vec3 a = vertices[first].xyz;
+	vec3 edge1 = vertices[first + 1].xyz - a;
+	vec3 edge2 = vertices[first + 2].xyz - a;
+	vec3 p = cross(direction, edge2);
+	float det = dot(edge1, p);
+	if (abs(det) < 1e-12) {
+		return false;
+	}
+
+	float inv_det = 1.0 / det;
+	vec3 s = origin - a;
+	float u = dot(s, p) * inv_det;
+	if (u < 0.0 || u > 1.0) {
+		return false;
+	}
+
+	vec3 q = cross(s, edge1);
+	float v = dot(direction, q) * inv_det;
+	if (v < 0.0 || u + v > 1.0) {
+		return false;
+	}
+
+	t = dot(edge2, q) * inv_det;
+	barycentrics = vec2(u, v);
+	return t >= RAY_EPSILON && t <= t_max;
+}
+
+// Closest triangle of an instance's mesh, the ray is in object space so distances stay the same
+bool intersect_mesh(uint placement, vec3 origin, vec3 direction, bool any_hit, inout Candidate closest) {
+	InstanceData instance = instances[placements[placement].instance];
+	vec3 inv_dir = 1.0 / direction;
+	bool found = false;
+
+	uint stack[BVH_STACK_SIZE];
+	uint size = 0;
+	stack[size++] = instance.bvh_root;
+	while (size > 0) {
+		uint index = stack[--size];
+		BvhNode node = nodes[index];
+		if (!intersect_node(node, origin, inv_dir, closest.t)) {
+			continue;
+		}
+
+		if ((node.count & BVH_LEAF) != 0) {
+			for (uint i = 0; i < (node.count & ~BVH_LEAF); i++) {
+				uint primitive = bvh_indices[node.offset + i];
+				float t;
+				vec2 barycentrics;
+				if (intersect_triangle(origin, direction, instance.first_vertex + 3 * primitive, closest.t, t, barycentrics)) {
+					closest = Candidate(t, placement, primitive, barycentrics, false);
+					found = true;
+					if (any_hit) {
+						return true;
+					}
+				}
+			}
+		} else if (size + 2 <= BVH_STACK_SIZE) {
+			stack[size++] = node.offset;
+			stack[size++] = index + 1;
+		}
+	}
+	return found;
+}
+
+// Walks the top level, then the mesh of every placement the ray reaches. With any_hit the
+// first hit found ends the search.
+bool intersect_scene(vec3 origin, vec3 direction, float t_max, uint mask, bool any_hit, out Candidate closest) {
+	closest = Candidate(t_max, 0, 0, vec2(0.0), false);
+	vec3 inv_dir = 1.0 / direction;
+	bool found = false;
+
+	uint stack[BVH_STACK_SIZE];
+	uint size = 0;
+	stack[size++] = 0;
+	while (size > 0) {
+		uint index = stack[--size];
+		BvhNode node = nodes[index];
+		if (!intersect_node(node, origin, inv_dir, closest.t)) {
+			continue;
+		}
+
+		if ((node.count & BVH_LEAF) == 0) {
+			if (size + 2 <= BVH_STACK_SIZE) {
+				stack[size++] = node.offset;
+				stack[size++] = index + 1;
+			}
+			continue;
+		}
+
+		for (uint i = 0; i < (node.count & ~BVH_LEAF); i++) {
+			uint placement = bvh_indices[node.offset + i];
+			Placement placed = placements[placement];
+			if ((placed.mask & mask) == 0) {
+				continue;
+			}
+
+			if (placed.volume != 0) {
+				float t;
+				bool enter;
+				if (intersect_volume_box(placed.instance, origin, direction, RAY_EPSILON, t, enter) && t <= closest.t) {
+					closest = Candidate(t, placement, 0, vec2(0.0), enter);
+					found = true;
+				}
+			} else {
+				vec3 object_origin = (placed.world_to_object * vec4(origin, 1.0)).xyz;
+				vec3 object_direction = mat3(placed.world_to_object) * direction;
+				found = intersect_mesh(placement, object_origin, object_direction, any_hit, closest) || found;
+			}
+			if (found && any_hit) {
+				return true;
+			}
+		}
+	}
+	return found;
+}
+
+void intersect_closest(vec3 origin, vec3 direction, float t_max, uint mask) {
+	Candidate closest;
+	if (!intersect_scene(origin, direction, t_max, mask, false, closest)) {
+		hit_value.t = NO_HIT;
+		return;
+	}
+
+	Placement placed = placements[closest.placement];
+	if (placed.volume != 0) {
+		hit_value = volume_hit(placed.instance, origin, direction, closest.t, closest.enter);
+	} else {
+		hit_value = triangle_hit(
+			placed.instance,
+			closest.primitive,
+			closest.barycentrics,
+			mat4x3(placed.world_to_object),
+			origin,
+			direction,
+			closest.t
+		);
+	}
+}
+
+bool occluded(vec3 origin, vec3 direction, float t_max, uint mask) {
+	Candidate closest;
+	return intersect_scene(origin, direction, t_max, mask, true, closest);
+}
+
+void main() {
+	uvec2 size = uvec2(imageSize(accumulation_image));
+	if (any(greaterThanEqual(gl_GlobalInvocationID.xy, size))) {
+		return;
+	}
+	render_pixel(gl_GlobalInvocationID.xy, size);
+}
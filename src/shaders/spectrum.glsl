@@ -0,0 +1,84 @@
+// Spectral helpers: wavelength sampling, RGB uplifting and CIE XYZ conversion.
+// A path carries three wavelengths, so spectral quantities fit the same vec3 as RGB.
+
+#define LAMBDA_MIN 360.0
+#define LAMBDA_MAX 830.0
+
+// Integral of the y colour matching function, normalizes XYZ so a constant spectrum of 1 has Y = 1
+#define CIE_Y_INTEGRAL 106.856895
+
+// Hero wavelength sampling (Wilkie et al. 2014), the others are rotated evenly through the range
+vec3 sample_wavelengths(float u) {
+	float range = LAMBDA_MAX - LAMBDA_MIN;
+	vec3 offset = u * range + vec3(0.0, 1.0, 2.0) * (range / 3.0);
+	return LAMBDA_MIN + mod(offset, range);
+}
+
+// Basis spectra of "An RGB to Spectrum Conversion for Reflectances" (Smits 1999),
+// ten bins from 380 to 720 nm
+const float SMITS_WHITE[10] = float[](1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000);
+const float SMITS_CYAN[10] = float[](0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000);
+const float SMITS_MAGENTA[10] = float[](1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959);
+const float SMITS_YELLOW[10] = float[](0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840);
+const float SMITS_RED[10] = float[](0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149);
+const float SMITS_GREEN[10] = float[](0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025);
+const float SMITS_BLUE[10] = float[](1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496);
+
+float rgb_to_spectrum(vec3 rgb, float lambda) {
+	int bin = clamp(int((lambda - 380.0) / (340.0 / 10.0)), 0, 9);
+	float r = rgb.r;
+	float g = rgb.g;
+	float b = rgb.b;
+
+	// White for the smallest component, then the secondary and primary spanning the rest
+	if (r <= g && r <= b) {
+		return r * SMITS_WHITE[bin] + (g <= b
+			? (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+			: (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]);
+	}
+	if (g <= r && g <= b) {
+		return g * SMITS_WHITE[bin] + (r <= b
+			? (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+			: (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]);
+	}
+	return b * SMITS_WHITE[bin] + (r <= g
+		? (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+		: (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]);
+}
+
+vec3 rgb_to_spectrum(vec3 rgb, vec3 lambda) {
+	return vec3(rgb_to_spectrum(rgb, lambda.x), rgb_to_spectrum(rgb, lambda.y), rgb_to_spectrum(rgb, lambda.z));
+}
+
+float piecewise_gaussian(float x, float mu, float sigma_low, float sigma_high) {
+	float t = (x - mu) / (x < mu ? sigma_low : sigma_high);
+	return exp(-0.5 * t * t);
+}
+
+// Multi-lobe fit of the CIE 1931 colour matching functions (Wyman et al. 2013)
+vec3 cie_xyz(float lambda) {
+	float x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+		+ 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+		- 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+	float y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+		+ 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+	float z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+		+ 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+	return vec3(x, y, z);
+}
+
+// Monte Carlo estimate of XYZ from radiance at the sampled wavelengths, all with uniform pdf
+vec3 spectrum_to_xyz(vec3 radiance, vec3 lambda) {
+	vec3 xyz = radiance.x * cie_xyz(lambda.x) + radiance.y * cie_xyz(lambda.y) + radiance.z * cie_xyz(lambda.z);
+	return xyz * ((LAMBDA_MAX - LAMBDA_MIN) / (3.0 * CIE_Y_INTEGRAL));
+}
+
+// Linear Rec. 709 primaries, the equal energy white of the uplifted spectra is mapped to D65
+vec3 xyz_to_linear_srgb(vec3 xyz) {
+	const mat3 XYZ_TO_SRGB = mat3(
+		3.2404542, -0.9692660, 0.0556434,
+		-1.5371385, 1.8760108, -0.2040259,
+		-0.4985314, 0.0415560, 1.0572252
+	);
+	return XYZ_TO_SRGB * (xyz * vec3(0.95047, 1.0, 1.08883));
+}
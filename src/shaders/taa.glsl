@@ -0,0 +1,72 @@
+#version 460
+
+// Temporal anti-aliasing: blends the accumulation with the previous result, reprojected
+// along the motion AOV and clipped to the colour distribution around the pixel so
+// disoccluded history does not ghost. While the camera rests the accumulation gains
+// samples and takes over.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0, set = 0, rgba32f) uniform readonly image2D accumulation_image;
+layout(binding = 1, set = 0, rgba16f) uniform readonly image2D aov_motion;
+layout(binding = 2, set = 0, rgba32f) uniform readonly image2D history_image;
+layout(binding = 3, set = 0, rgba32f) uniform writeonly image2D output_image;
+
+layout(push_constant) uniform PushConstants {
+	uint samples; // in the accumulation
+	uint history_valid;
+	float history_samples; // weight of the history, in samples of the accumulation
+	float clip_gamma; // standard deviations the history may differ from the neighbourhood
+} pc;
+
+vec4 load_accumulation(ivec2 pixel) {
+	return imageLoad(accumulation_image, clamp(pixel, ivec2(0), imageSize(accumulation_image) - 1));
+}
+
+vec4 load_history(ivec2 pixel) {
+	return imageLoad(history_image, clamp(pixel, ivec2(0), imageSize(history_image) - 1));
+}
+
+// Bilinear filtering by hand, storage images can not be sampled
+vec4 sample_history(vec2 position) {
+	vec2 texel = position - 0.5;
+	ivec2 base = ivec2(floor(texel));
+	vec2 f = texel - vec2(base);
+	vec4 top = mix(load_history(base), load_history(base + ivec2(1, 0)), f.x);
+	vec4 bottom = mix(load_history(base + ivec2(0, 1)), load_history(base + ivec2(1, 1)), f.x);
+	return mix(top, bottom, f.y);
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(accumulation_image);
+	if (any(greaterThanEqual(pixel, size))) {
+		return;
+	}
+
+	vec4 current = imageLoad(accumulation_image, pixel);
+	vec2 previous = vec2(pixel) + 0.5 + imageLoad(aov_motion, pixel).xy;
+	if (pc.history_valid == 0 || any(lessThan(previous, vec2(0.0))) || any(greaterThan(previous, vec2(size)))) {
+		imageStore(output_image, pixel, current);
+		return;
+	}
+
+	// Variance clipping box of the 3x3 neighbourhood (Salvi 2016)
+	vec4 m1 = vec4(0.0);
+	vec4 m2 = vec4(0.0);
+	for (int y = -1; y <= 1; y++) {
+		for (int x = -1; x <= 1; x++) {
+			vec4 neighbour = load_accumulation(pixel + ivec2(x, y));
+			m1 += neighbour;
+			m2 += neighbour * neighbour;
+		}
+	}
+	vec4 mean = m1 / 9.0;
+	vec4 deviation = sqrt(max(m2 / 9.0 - mean * mean, vec4(0.0)));
+	vec4 box_min = min(mean - pc.clip_gamma * deviation, current);
+	vec4 box_max = max(mean + pc.clip_gamma * deviation, current);
+
+	vec4 history = clamp(sample_history(previous), box_min, box_max);
+	float samples = float(max(pc.samples, 1));
+	imageStore(output_image, pixel, mix(history, current, samples / (samples + pc.history_samples)));
+}
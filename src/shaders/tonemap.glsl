@@ -0,0 +1,207 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+#include "spectrum.glsl"
+#include "color.glsl"
+
+// Post-process pass writing the displayed image, either the exposed and tonemapped
+// accumulation or a visualization of one of the AOVs, encoded for the output
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0, set = 0, rgba32f) uniform readonly image2D accumulation_image;
+layout(binding = 1, set = 0) uniform writeonly image2D output_image;
+layout(binding = 2, set = 0, std430) readonly buffer Exposure { float average_luminance; };
+layout(binding = 3, set = 0, rgba16f) uniform readonly image2D aov_albedo;
+layout(binding = 4, set = 0, rgba16f) uniform readonly image2D aov_normal;
+layout(binding = 5, set = 0, r32f) uniform readonly image2D aov_depth;
+layout(binding = 6, set = 0, rgba32ui) uniform readonly uimage2D aov_id;
+layout(binding = 7, set = 0, rgba16f) uniform readonly image2D aov_motion;
+layout(binding = 8, set = 0, rgba32f) uniform readonly image2D sample_stats;
+
+layout(push_constant) uniform PushConstants {
+	uint tonemapper;
+	float exposure; // linear scale, on top of auto exposure
+	uint auto_exposure;
+	uint input_space; // COLOR_SPACE_ of the accumulation
+	uint debug_view;
+	uint output_encoding;
+	float paper_white; // nits of 1.0 on HDR outputs
+	float max_samples; // samples of the most sampled pixel, the top of the heatmap
+} pc;
+
+// Tonemapper in renderer/tonemap.rs
+#define TONEMAP_NONE 0
+#define TONEMAP_ACES 1
+#define TONEMAP_AGX 2
+#define TONEMAP_REINHARD 3
+#define TONEMAP_PBR_NEUTRAL 4
+
+// DebugView in renderer/aov.rs
+#define DEBUG_VIEW_BEAUTY 0
+#define DEBUG_VIEW_ALBEDO 1
+#define DEBUG_VIEW_NORMAL 2
+#define DEBUG_VIEW_DEPTH 3
+#define DEBUG_VIEW_INSTANCE 4
+#define DEBUG_VIEW_MATERIAL 5
+#define DEBUG_VIEW_PRIMITIVE 6
+#define DEBUG_VIEW_MOTION 7
+#define DEBUG_VIEW_SAMPLES 8
+
+// Auto exposure maps the average luminance to middle grey
+#define MIDDLE_GREY 0.18
+
+// Fitted ACES filmic curve (Narkowicz 2015)
+vec3 aces(vec3 x) {
+	const float a = 2.51;
+	const float b = 0.03;
+	const float c = 2.43;
+	const float d = 0.59;
+	const float e = 0.14;
+	return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+// Polynomial fit of the AgX base contrast curve (Wrensch 2023)
+vec3 agx_contrast(vec3 x) {
+	vec3 x2 = x * x;
+	vec3 x4 = x2 * x2;
+	return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 color) {
+	const mat3 AGX_INSET = mat3(
+		0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+		0.0784335999999992, 0.878468636469772, 0.0784336,
+		0.0792237451477643, 0.0791661274605434, 0.879142973793104
+	);
+	const mat3 AGX_OUTSET = mat3(
+		1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+		-0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+		-0.0990297440797205, -0.0989611768448433, 1.15107367264116
+	);
+	const float MIN_EV = -12.47393;
+	const float MAX_EV = 4.026069;
+
+	vec3 x = clamp(log2(max(AGX_INSET * color, vec3(1e-10))), MIN_EV, MAX_EV);
+	x = agx_contrast((x - MIN_EV) / (MAX_EV - MIN_EV));
+	// The curve ends in display encoding, decode with a 2.2 power to stay linear
+	return pow(clamp(AGX_OUTSET * x, 0.0, 1.0), vec3(2.2));
+}
+
+// Luminance based, keeps the hue of bright colours
+vec3 reinhard(vec3 color) {
+	return color / (1.0 + luminance(color));
+}
+
+// Khronos PBR Neutral, keeps base colours under neutral lighting unchanged
+vec3 pbr_neutral(vec3 color) {
+	const float START_COMPRESSION = 0.8 - 0.04;
+	const float DESATURATION = 0.15;
+
+	float x = min(color.r, min(color.g, color.b));
+	float offset = x < 0.08 ? x - 6.25 * x * x : 0.04;
+	color -= offset;
+
+	float peak = max3(color);
+	if (peak < START_COMPRESSION) {
+		return color;
+	}
+
+	const float d = 1.0 - START_COMPRESSION;
+	float new_peak = 1.0 - d * d / (peak + d - START_COMPRESSION);
+	color *= new_peak / peak;
+
+	float g = 1.0 - 1.0 / (DESATURATION * (peak - new_peak) + 1.0);
+	return mix(color, vec3(new_peak), g);
+}
+
+vec3 tonemap(vec3 color) {
+	switch (pc.tonemapper) {
+	case TONEMAP_ACES:
+		return aces(color);
+	case TONEMAP_AGX:
+		return agx(color);
+	case TONEMAP_REINHARD:
+		return reinhard(color);
+	case TONEMAP_PBR_NEUTRAL:
+		return pbr_neutral(color);
+	}
+	return color;
+}
+
+vec3 id_color(uint id) {
+	if (id == NO_ID) {
+		return vec3(0.0);
+	}
+	uint state = id;
+	return vec3(pcg(state), pcg(state), pcg(state)) * (1.0 / 4294967296.0);
+}
+
+// Polynomial fit of the Turbo colormap (Mikhailov 2019), decoded to linear
+vec3 turbo(float x) {
+	const vec4 RED_4 = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+	const vec4 GREEN_4 = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+	const vec4 BLUE_4 = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+	const vec2 RED_2 = vec2(-152.94239396, 59.28637943);
+	const vec2 GREEN_2 = vec2(4.27729857, 2.82956604);
+	const vec2 BLUE_2 = vec2(-89.90310912, 27.34824973);
+
+	x = clamp(x, 0.0, 1.0);
+	vec4 v4 = vec4(1.0, x, x * x, x * x * x);
+	vec2 v2 = v4.zw * v4.z;
+	vec3 color = vec3(
+		dot(v4, RED_4) + dot(v2, RED_2),
+		dot(v4, GREEN_4) + dot(v2, GREEN_2),
+		dot(v4, BLUE_4) + dot(v2, BLUE_2)
+	);
+	return pow(clamp(color, 0.0, 1.0), vec3(2.2));
+}
+
+vec3 debug_color(ivec2 pixel) {
+	switch (pc.debug_view) {
+	case DEBUG_VIEW_ALBEDO:
+		return imageLoad(aov_albedo, pixel).rgb;
+	case DEBUG_VIEW_NORMAL:
+		return imageLoad(aov_normal, pixel).xyz * 0.5 + 0.5;
+	case DEBUG_VIEW_DEPTH: {
+		float depth = imageLoad(aov_depth, pixel).r;
+		return depth > 0.0 ? vec3(1.0 / (1.0 + 0.1 * depth)) : vec3(0.0);
+	}
+	case DEBUG_VIEW_INSTANCE:
+		return id_color(imageLoad(aov_id, pixel).x);
+	case DEBUG_VIEW_MATERIAL:
+		return id_color(imageLoad(aov_id, pixel).y);
+	case DEBUG_VIEW_PRIMITIVE:
+		return id_color(imageLoad(aov_id, pixel).z);
+	case DEBUG_VIEW_MOTION:
+		return vec3(0.5 + clamp(imageLoad(aov_motion, pixel).xy / 32.0, -0.5, 0.5), 0.5);
+	case DEBUG_VIEW_SAMPLES:
+		return turbo(imageLoad(sample_stats, pixel).x / max(pc.max_samples, 1.0));
+	}
+	return vec3(0.0);
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	if (any(greaterThanEqual(pixel, imageSize(accumulation_image)))) {
+		return;
+	}
+
+	vec4 accumulated = imageLoad(accumulation_image, pixel);
+	if (pc.debug_view != DEBUG_VIEW_BEAUTY) {
+		vec3 color = encode_output(debug_color(pixel), pc.output_encoding, pc.paper_white);
+		imageStore(output_image, pixel, vec4(color, accumulated.a));
+		return;
+	}
+
+	// Tonemappers operate on Rec. 709 primaries, wider gamut colours are clipped by them
+	vec3 color = to_rec709(accumulated.rgb, pc.input_space);
+	float exposure = pc.exposure;
+	if (pc.auto_exposure != 0 && average_luminance > 0.0) {
+		exposure *= MIDDLE_GREY / average_luminance;
+	}
+
+	color = tonemap(max(color, vec3(0.0)) * exposure);
+	imageStore(output_image, pixel, vec4(encode_output(color, pc.output_encoding, pc.paper_white), accumulated.a));
+}
@@ -0,0 +1,85 @@
+#version 460
+
+// Edge-aware spatial upscaling after AMD FSR 1 EASU: a Lanczos 2 kernel over the 4x4 input
+// pixels around each output pixel, narrowed across the local edge and widened along it so
+// edges stay sharp, and clamped to the nearest 2x2 pixels so the negative lobes do not ring.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(binding = 0, set = 0, rgba16f) uniform readonly image2D input_image;
+layout(binding = 1, set = 0, rgba16f) uniform writeonly image2D output_image;
+
+const float PI = 3.14159265358979;
+
+vec4 load_input(ivec2 pixel) {
+	return imageLoad(input_image, clamp(pixel, ivec2(0), imageSize(input_image) - 1));
+}
+
+float luma(vec4 color) {
+	return dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+}
+
+float lanczos2(float x) {
+	if (x < 1e-4) {
+		return 1.0;
+	}
+	if (x >= 2.0) {
+		return 0.0;
+	}
+	return 2.0 * sin(PI * x) * sin(0.5 * PI * x) / (PI * PI * x * x);
+}
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 output_size = imageSize(output_image);
+	if (any(greaterThanEqual(pixel, output_size))) {
+		return;
+	}
+
+	// Output pixel centre in input pixels, the taps are the 4x4 pixels around it
+	vec2 position = (vec2(pixel) + 0.5) * vec2(imageSize(input_image)) / vec2(output_size) - 0.5;
+	ivec2 base = ivec2(floor(position)) - 1;
+	vec2 f = fract(position);
+
+	vec4 taps[4][4];
+	float lumas[4][4];
+	float luma_min = 1e30;
+	float luma_max = -1e30;
+	for (int y = 0; y < 4; y++) {
+		for (int x = 0; x < 4; x++) {
+			taps[y][x] = load_input(base + ivec2(x, y));
+			lumas[y][x] = luma(taps[y][x]);
+			luma_min = min(luma_min, lumas[y][x]);
+			luma_max = max(luma_max, lumas[y][x]);
+		}
+	}
+
+	// Luma gradient over the centre, a clean step edge gives a strength of one and noise less
+	vec2 gradient = vec2(0.0);
+	for (int i = 1; i <= 2; i++) {
+		gradient.x += lumas[i][2] - lumas[i][0] + lumas[i][3] - lumas[i][1];
+		gradient.y += lumas[2][i] - lumas[0][i] + lumas[3][i] - lumas[1][i];
+	}
+	float gradient_length = length(gradient);
+	float edge = clamp(gradient_length / (4.0 * (luma_max - luma_min) + 1e-5), 0.0, 1.0);
+	vec2 across = gradient_length > 1e-6 ? gradient / gradient_length : vec2(1.0, 0.0);
+	vec2 along = vec2(-across.y, across.x);
+	vec2 axis_scale = vec2(1.0 + edge, 1.0 / (1.0 + edge));
+
+	vec4 sum = vec4(0.0);
+	float weight_sum = 0.0;
+	for (int y = 0; y < 4; y++) {
+		for (int x = 0; x < 4; x++) {
+			vec2 offset = vec2(x - 1, y - 1) - f;
+			vec2 scaled = vec2(dot(offset, across), dot(offset, along)) * axis_scale;
+			float weight = lanczos2(length(scaled));
+			sum += weight * taps[y][x];
+			weight_sum += weight;
+		}
+	}
+	vec4 color = sum / max(weight_sum, 1e-4);
+
+	vec4 nearest_min = min(min(taps[1][1], taps[1][2]), min(taps[2][1], taps[2][2]));
+	vec4 nearest_max = max(max(taps[1][1], taps[1][2]), max(taps[2][1], taps[2][2]));
+	imageStore(output_image, pixel, clamp(color, nearest_min, nearest_max));
+}
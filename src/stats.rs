@@ -0,0 +1,165 @@
+// Performance statistics of the interactive view. A frame is complete once its GPU timings were
+// read back, a few frames after it was submitted. Complete frames go to the CSV log one row
+// each, the overlay shows averages over the last second.
+
+use crate::renderer::GpuTimings;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+// What the CPU knew about a frame when it was submitted
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo {
+    // Milliseconds since the previous frame started
+    pub interval: f32,
+    // Milliseconds spent recording and submitting
+    pub cpu: f32,
+    pub width: u32,
+    pub height: u32,
+    // Rays a sample traces at most, a camera or continuation ray and a shadow ray per bounce.
+    // Paths ending early make the ray rates an upper bound
+    pub rays_per_sample: u32,
+}
+
+impl FrameInfo {
+    // Millions of rays traced per second of GPU trace time
+    fn mrays_per_second(&self, gpu: &GpuTimings) -> Option<f64> {
+        let rays = self.width as f64 * self.height as f64 * self.rays_per_sample as f64;
+        (gpu.trace > 0.0).then(|| rays / (gpu.trace as f64 * 1e3))
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    frames: u32,
+    interval: f32,
+    cpu: f32,
+    gpu_frames: u32,
+    gpu: GpuTimings,
+    mrays: f64,
+}
+
+pub struct Stats {
+    log: Option<BufWriter<File>>,
+    start: Instant,
+    frame: u64,
+    totals: Totals,
+    summary_start: Instant,
+    summary: Vec<String>,
+}
+
+impl Stats {
+    pub fn new(log_path: Option<&Path>) -> Result<Self> {
+        let log = log_path
+            .map(|path| -> Result<_> {
+                let mut log = BufWriter::new(
+                    File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+                );
+                writeln!(
+                    log,
+                    "frame,seconds,interval_ms,cpu_ms,trace_ms,denoise_ms,tonemap_ms,blit_ms,gpu_ms,width,height,mrays_per_s"
+                )
+                .context("Failed to write stats log")?;
+                Ok(log)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            log,
+            start: Instant::now(),
+            frame: 0,
+            totals: Totals::default(),
+            summary_start: Instant::now(),
+            summary: Vec::new(),
+        })
+    }
+
+    // Returns whether a new summary is available
+    pub fn record(&mut self, info: FrameInfo, gpu: Option<GpuTimings>) -> Result<bool> {
+        let mrays = gpu.as_ref().and_then(|gpu| info.mrays_per_second(gpu));
+        if let Some(log) = &mut self.log {
+            let gpu_columns = match &gpu {
+                Some(gpu) => format!(
+                    "{:.3},{:.3},{:.3},{:.3},{:.3}",
+                    gpu.trace,
+                    gpu.denoise,
+                    gpu.tonemap,
+                    gpu.blit,
+                    gpu.total()
+                ),
+                None => ",,,,".to_owned(),
+            };
+            writeln!(
+                log,
+                "{},{:.3},{:.3},{:.3},{gpu_columns},{},{},{}",
+                self.frame,
+                self.start.elapsed().as_secs_f32(),
+                info.interval,
+                info.cpu,
+                info.width,
+                info.height,
+                mrays.map_or(String::new(), |mrays| format!("{mrays:.1}")),
+            )
+            .context("Failed to write stats log")?;
+        }
+        self.frame += 1;
+
+        let totals = &mut self.totals;
+        totals.frames += 1;
+        totals.interval += info.interval;
+        totals.cpu += info.cpu;
+        if let Some(gpu) = gpu {
+            totals.gpu_frames += 1;
+            totals.gpu.trace += gpu.trace;
+            totals.gpu.denoise += gpu.denoise;
+            totals.gpu.tonemap += gpu.tonemap;
+            totals.gpu.blit += gpu.blit;
+            totals.mrays += mrays.unwrap_or(0.0);
+        }
+
+        if self.summary_start.elapsed() >= SUMMARY_INTERVAL {
+            self.summary = summarize(&self.totals, self.summary_start.elapsed(), info);
+            self.totals = Totals::default();
+            self.summary_start = Instant::now();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // Averages over the last second, empty until the first one passed
+    pub fn summary(&self) -> &[String] {
+        &self.summary
+    }
+}
+
+fn summarize(totals: &Totals, elapsed: Duration, latest: FrameInfo) -> Vec<String> {
+    let frames = totals.frames.max(1) as f32;
+    let mut lines = vec![
+        format!(
+            "FPS {:.1}  FRAME {:.2} MS  CPU {:.2} MS",
+            totals.frames as f32 / elapsed.as_secs_f32(),
+            totals.interval / frames,
+            totals.cpu / frames
+        ),
+        format!("RESOLUTION {}X{}", latest.width, latest.height),
+    ];
+    if totals.gpu_frames > 0 {
+        let gpu_frames = totals.gpu_frames as f32;
+        lines.push(format!(
+            "GPU {:.2} MS  TRACE {:.2}  DENOISE {:.2}  TONEMAP {:.2}  BLIT {:.2}",
+            totals.gpu.total() / gpu_frames,
+            totals.gpu.trace / gpu_frames,
+            totals.gpu.denoise / gpu_frames,
+            totals.gpu.tonemap / gpu_frames,
+            totals.gpu.blit / gpu_frames
+        ));
+        lines.push(format!("RAYS UP TO {:.0} M/S", totals.mrays / totals.gpu_frames as f64));
+    } else {
+        lines.push("GPU TIMESTAMPS UNAVAILABLE".to_owned());
+    }
+    lines
+}